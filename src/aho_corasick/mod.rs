@@ -0,0 +1,188 @@
+use std::collections::vec_deque::VecDeque;
+
+use ::limits::{Budget, MatchError};
+
+#[cfg(test)] mod spec;
+
+const ROOT: usize = 0;
+
+#[derive(PartialEq,Debug,Clone)]
+struct Node {
+    next: Vec<(u8, usize)>, // trie edges, sorted by byte
+    fail: usize, // node of the longest proper suffix that is also in the trie
+    dict: Option<usize>, // nearest node along fail links that ends a pattern
+    output: Vec<usize>, // patterns ending here, in ascending order
+    depth: usize
+}
+
+impl Node {
+    fn new(depth: usize) -> Node {
+        Node {
+            next: Vec::new(),
+            fail: ROOT,
+            dict: None,
+            output: Vec::new(),
+            depth
+        }
+    }
+
+    fn child(&self, byte: u8) -> Option<usize> {
+        self.next.binary_search_by_key(&byte, |&(b, _)| b)
+            .ok()
+            .map(|i| self.next[i].1)
+    }
+}
+
+// finds many literals in one pass over the text. Patterns are preferred in
+// the order given, so among the matches with the leftmost start the
+// earliest pattern wins, as it would in the NFA matcher
+#[derive(PartialEq,Debug,Clone)]
+pub struct AhoCorasick {
+    nodes: Vec<Node>
+}
+
+impl AhoCorasick {
+    pub fn new(patterns: &[Vec<u8>]) -> AhoCorasick {
+        let mut nodes = vec![Node::new(0)];
+
+        for (pattern, bytes) in patterns.iter().enumerate() {
+            let mut current = ROOT;
+            for &byte in bytes {
+                current = match nodes[current].child(byte) {
+                    Some(next) => next,
+                    None => {
+                        let next = nodes.len();
+                        nodes.push(Node::new(nodes[current].depth + 1));
+                        let edges = &mut nodes[current].next;
+                        let i = edges.binary_search_by_key(&byte, |&(b, _)| b).unwrap_err();
+                        edges.insert(i, (byte, next));
+                        next
+                    }
+                };
+            }
+            nodes[current].output.push(pattern);
+        }
+
+        let mut automaton = AhoCorasick {
+            nodes
+        };
+        automaton.link();
+        automaton
+    }
+
+    // fail and dict links, breadth first so that shallower nodes are done first
+    fn link(&mut self) {
+        let mut queue = self.nodes[ROOT].next.iter().map(|&(_, child)| child).collect::<VecDeque<_>>();
+
+        while let Some(node) = queue.pop_front() {
+            for (byte, child) in self.nodes[node].next.clone() {
+                let fail = self.next_state(self.nodes[node].fail, byte);
+                self.nodes[child].fail = fail;
+                self.nodes[child].dict = if self.nodes[fail].output.is_empty() {
+                    self.nodes[fail].dict
+                } else {
+                    Some(fail)
+                };
+                queue.push_back(child);
+            }
+        }
+    }
+
+    fn next_state(&self, node: usize, byte: u8) -> usize {
+        let mut node = node;
+        loop {
+            if let Some(next) = self.nodes[node].child(byte) {
+                return next;
+            }
+            if node == ROOT {
+                return ROOT;
+            }
+            node = self.nodes[node].fail;
+        }
+    }
+
+    // the patterns it was built from, spelled out along the trie. Nodes are
+    // added after their parents, so a parent's path is known first
+    pub fn patterns(&self) -> Vec<Vec<u8>> {
+        let mut paths = vec![Vec::new(); self.nodes.len()];
+        let mut patterns = Vec::new();
+        for node in 0..self.nodes.len() {
+            for &(byte, child) in &self.nodes[node].next {
+                let mut path = paths[node].clone();
+                path.push(byte);
+                paths[child] = path;
+            }
+            for &pattern in &self.nodes[node].output {
+                patterns.push((pattern, paths[node].clone()));
+            }
+        }
+        patterns.sort();
+        patterns.into_iter().map(|(_, bytes)| bytes).collect()
+    }
+
+    // every pattern that text starts with. A step is a trie edge followed
+    pub fn prefixes_of(&self, text: &[u8], budget: &mut Budget) -> Result<Vec<usize>, MatchError> {
+        let mut result = Vec::new();
+        let mut node = ROOT;
+        for (i, &byte) in text.iter().enumerate() {
+            if self.nodes[node].next.is_empty() {
+                break;
+            }
+            budget.step()?;
+            budget.read(i)?;
+            node = match self.nodes[node].child(byte) {
+                Some(next) => next,
+                None => break
+            };
+            result.extend_from_slice(&self.nodes[node].output);
+        }
+        Ok(result)
+    }
+
+    // end of the preferred pattern occurring at start
+    pub fn match_at(&self, text: &[u8], start: usize) -> Option<usize> {
+        let mut best: Option<(usize, usize)> = None; // pattern, end
+        let mut node = ROOT;
+        for (i, &byte) in text[start..].iter().enumerate() {
+            node = match self.nodes[node].child(byte) {
+                Some(next) => next,
+                None => break
+            };
+            if let Some(&pattern) = self.nodes[node].output.first() {
+                if best.is_none_or(|(preferred, _)| pattern < preferred) {
+                    best = Some((pattern, start + i + 1));
+                }
+            }
+        }
+        best.map(|(_, end)| end)
+    }
+
+    // the leftmost match, as start and end offsets
+    pub fn find(&self, text: &[u8]) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize, usize)> = None; // start, pattern, end
+        let mut node = ROOT;
+
+        for (i, &byte) in text.iter().enumerate() {
+            node = self.next_state(node, byte);
+            let end = i + 1;
+
+            // every match from here on starts after the best one
+            if let Some((start, _, _)) = best {
+                if end - self.nodes[node].depth > start {
+                    break;
+                }
+            }
+
+            let mut found = if self.nodes[node].output.is_empty() { self.nodes[node].dict } else { Some(node) };
+            while let Some(suffix) = found {
+                let candidate = (end - self.nodes[suffix].depth, self.nodes[suffix].output[0], end);
+                if best.is_none_or(|best| (candidate.0, candidate.1) < (best.0, best.1)) {
+                    best = Some(candidate);
+                }
+                found = self.nodes[suffix].dict;
+            }
+        }
+
+        best.map(|(start, _, end)| (start, end))
+    }
+}
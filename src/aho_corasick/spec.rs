@@ -0,0 +1,59 @@
+use ::limits::{Limits, Budget, MatchError};
+use super::AhoCorasick;
+
+fn automaton(patterns: &[&str]) -> AhoCorasick {
+    AhoCorasick::new(&patterns.iter().map(|pattern| pattern.as_bytes().to_vec()).collect::<Vec<_>>())
+}
+
+#[test]
+fn builds_trie_with_fail_links() {
+    let automaton = automaton(&["he", "she", "his", "hers"]);
+
+    assert_eq!(10, automaton.nodes.len());
+    let she = automaton.next_state(automaton.next_state(automaton.next_state(0, b's'), b'h'), b'e');
+    assert_eq!(vec![1], automaton.nodes[she].output);
+    assert_eq!(vec![0], automaton.nodes[automaton.nodes[she].dict.unwrap()].output);
+}
+
+#[test]
+fn finds_leftmost_match() {
+    let automaton = automaton(&["he", "she", "his", "hers"]);
+    assert_eq!(Some((1, 4)), automaton.find(b"ushers"));
+    assert_eq!(Some((0, 3)), automaton.find(b"his hers"));
+    assert_eq!(None, automaton.find(b"hi s"));
+    assert_eq!(None, automaton.find(b""));
+}
+
+#[test]
+fn prefers_earlier_pattern_at_same_start() {
+    assert_eq!(Some((1, 2)), automaton(&["a", "ab"]).find(b"xab"));
+    assert_eq!(Some((1, 3)), automaton(&["ab", "a"]).find(b"xab"));
+    assert_eq!(Some((0, 5)), automaton(&["abcde", "bc"]).find(b"abcde"));
+    assert_eq!(Some((1, 3)), automaton(&["abcdx", "bc"]).find(b"abcde"));
+}
+
+#[test]
+fn matches_at_position() {
+    let automaton = automaton(&["foo", "foobar", "f"]);
+    assert_eq!(Some(4), automaton.match_at(b"xfoobar", 1));
+    assert_eq!(Some(1), automaton.match_at(b"fxx", 0));
+    assert_eq!(None, automaton.match_at(b"xfoobar", 0));
+}
+
+#[test]
+fn lists_patterns_text_starts_with() {
+    let automaton = automaton(&["foobar", "foo", "bar", "foo"]);
+    assert_eq!(Ok(vec![1, 3, 0]), automaton.prefixes_of(b"foobarbaz", &mut Budget::unlimited()));
+    assert_eq!(Ok(Vec::new()), automaton.prefixes_of(b"xfoo", &mut Budget::unlimited()));
+
+    // the walk stops at the end of the longest pattern
+    assert_eq!(Ok(vec![1, 3, 0]), automaton.prefixes_of(b"foobarbaz", &mut Budget::new(Limits::new().bytes(6))));
+    assert_eq!(Err(MatchError::BudgetExceeded), automaton.prefixes_of(b"foobarbaz", &mut Budget::new(Limits::new().bytes(5))));
+}
+
+#[test]
+fn spells_out_its_patterns() {
+    let patterns = ["she", "he", "", "hers", "he"];
+    let expected = patterns.iter().map(|pattern| pattern.as_bytes().to_vec()).collect::<Vec<_>>();
+    assert_eq!(expected, automaton(&patterns).patterns());
+}
@@ -0,0 +1,112 @@
+use ::program::{Program, Inst};
+
+#[cfg(test)] mod spec;
+
+// the most (instruction, position) pairs worth a visited bit each, 32 KiB of bits
+const MAX_VISITED: usize = 256 * 1024;
+
+#[derive(Debug)]
+enum Frame {
+    Explore(u32, usize),
+    Restore(usize, Option<usize>) // a capture slot and its value before a save
+}
+
+// scratch space for `Backtracker::captures`, reused between searches
+#[derive(Debug)]
+pub struct Cache {
+    visited: Vec<u64>,
+    stack: Vec<Frame>
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            visited: Vec::new(),
+            stack: Vec::new()
+        }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+// whether the visited bits for searching text fit in the budget
+pub fn fits(program: &Program, text: &[u8]) -> bool {
+    program.len().saturating_mul(text.len() + 1) <= MAX_VISITED
+}
+
+// tries the outputs of each split depth first in order of priority. No
+// (instruction, position) pair is explored twice, so the time is linear in
+// the size of the program times the length of the text
+pub struct Backtracker<'a> {
+    program: &'a Program,
+    text: &'a [u8]
+}
+
+impl<'a> Backtracker<'a> {
+    pub fn new<T: AsRef<[u8]> + ?Sized>(program: &'a Program, text: &'a T) -> Backtracker<'a> {
+        Backtracker {
+            program,
+            text: text.as_ref()
+        }
+    }
+
+    // capture slots of the match at the start of text
+    pub fn captures(&self, cache: &mut Cache) -> Option<Vec<Option<usize>>> {
+        let positions = self.text.len() + 1;
+        let bits = self.program.len() * positions;
+        cache.visited.clear();
+        cache.visited.resize(bits.div_ceil(64), 0);
+        cache.stack.clear();
+
+        let mut slots = vec![None; self.program.num_slots()];
+        slots[0] = Some(0);
+        cache.stack.push(Frame::Explore(self.program.start(), 0));
+
+        while let Some(frame) = cache.stack.pop() {
+            let (pc, position) = match frame {
+                Frame::Explore(pc, position) => (pc, position),
+                Frame::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+
+            let bit = pc as usize * positions + position;
+            if cache.visited[bit / 64] & (1 << (bit % 64)) != 0 {
+                continue;
+            }
+            cache.visited[bit / 64] |= 1 << (bit % 64);
+
+            let inst = self.program.inst(pc);
+            match inst {
+                Inst::Match(_) => {
+                    slots[1] = Some(position);
+                    return Some(slots);
+                },
+                Inst::Split(first, second) => {
+                    cache.stack.push(Frame::Explore(second, position));
+                    cache.stack.push(Frame::Explore(first, position));
+                },
+                Inst::Jump(target) => cache.stack.push(Frame::Explore(target, position)),
+                Inst::Save(slot, target) => {
+                    let slot = slot as usize;
+                    cache.stack.push(Frame::Restore(slot, slots[slot]));
+                    slots[slot] = Some(position);
+                    cache.stack.push(Frame::Explore(target, position));
+                },
+                Inst::Byte(..) | Inst::Range(..) | Inst::Class(..) => {
+                    let next = self.text.get(position).and_then(|&byte| self.program.next(inst, byte));
+                    if let Some(target) = next {
+                        cache.stack.push(Frame::Explore(target, position + 1));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
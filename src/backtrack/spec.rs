@@ -0,0 +1,65 @@
+use ::expr::Expr;
+use ::nfa::{NFA, Builder, Construction};
+use ::pike::{self, PikeVM};
+use ::program::Program;
+use super::{Backtracker, Cache, fits};
+
+fn program(pattern: &str) -> Program {
+    Program::from_nfa(&NFA::from_expr(&pattern.parse::<Expr>().unwrap()))
+}
+
+#[test]
+fn captures_groups() {
+    let program = program("([0123456789]+)-([0123456789]+)");
+    assert_eq!(Some(vec![Some(0), Some(5), Some(0), Some(2), Some(3), Some(5)]),
+               Backtracker::new(&program, "12-34x").captures(&mut Cache::new()));
+    assert_eq!(None, Backtracker::new(&program, "12-x").captures(&mut Cache::new()));
+}
+
+#[test]
+fn restores_slots_when_backtracking() {
+    let program = program("(a)b|(a)c");
+    assert_eq!(Some(vec![Some(0), Some(2), None, None, Some(0), Some(1)]),
+               Backtracker::new(&program, "ac").captures(&mut Cache::new()));
+}
+
+#[test]
+fn agrees_with_pike_vm() {
+    let patterns = ["(a+)(b|c)", "a(x)?b", "(ab|c)+", "(a|ab)(c|bcd)(d*)", "((a)|b)+", "(é|e)(.)", "(a*)*b?"];
+    let texts = ["", "aacd", "ab", "abcab", "abcd", "abab", "aab", "éa", "eé"];
+
+    let mut cache = Cache::new();
+    for pattern in patterns.iter() {
+        let program = program(pattern);
+        for text in texts.iter() {
+            assert_eq!(PikeVM::new(&program).captures(&mut pike::Cache::new(), text.as_bytes()),
+                       Backtracker::new(&program, text).captures(&mut cache),
+                       "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn agrees_with_pike_vm_on_glushkov_nfa() {
+    let glushkov = Builder::new().construction(Construction::Glushkov);
+    let texts = ["", "aacd", "ab", "abcab", "abcd", "abab", "aab", "éa", "eé"];
+
+    let mut cache = Cache::new();
+    for pattern in ["a+(b|c)", "(ab|c)+", "(a|ab)(c|bcd)d*", "(é|e).", "(a*)?b"].iter() {
+        let program = Program::from_nfa(&glushkov.try_build(&pattern.parse::<Expr>().unwrap()).unwrap());
+        for text in texts.iter() {
+            assert_eq!(PikeVM::new(&program).captures(&mut pike::Cache::new(), text.as_bytes()),
+                       Backtracker::new(&program, text).captures(&mut cache),
+                       "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn fits_small_searches_only() {
+    let program = program("a+b");
+    assert!(fits(&program, b"aab"));
+
+    let text = vec![b'a'; 1 << 20];
+    assert!(!fits(&program, &text));
+}
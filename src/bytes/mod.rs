@@ -0,0 +1,429 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ::expr::Expr;
+use ::nfa::{NFA, Builder, Construction};
+use ::matcher::{self, Matcher, Observer, Trace};
+use ::backtrack::{self, Backtracker};
+use ::pike::{self, PikeVM};
+use ::limits::{Limits, Budget, MatchError, CompileLimits, CompileError};
+use ::program::Program;
+use ::onepass::OnePass;
+use ::glushkov::Glushkov;
+use ::lazy::{self, LazyDFA, Interrupted};
+use ::stream::StreamMatcher;
+use ::literal::{self, Prefilter, Searcher};
+use ::format::{self, Reader};
+
+#[cfg(test)] mod spec;
+
+// identifies the regex a cache was last used with, 0 is none
+static NEXT_REGEX_ID: AtomicUsize = AtomicUsize::new(1);
+
+const REGEX_MAGIC: &[u8; 4] = b"RXRB";
+
+// a regex over arbitrary bytes. In `(?-u)` mode `.` and char classes match
+// single bytes and `\xFF` is the raw byte, so it can match invalid utf-8
+#[derive(Debug)]
+pub struct Regex {
+    id: usize,
+    nfa: NFA,
+    program: Program, // the NFA compiled flat, for the Pike VM and the backtracker
+    unanchored: NFA, // finds where a match anywhere in a stream ends, without empty edges if small enough
+    reverse: NFA, // read backwards from the end of a match, finds where it starts
+    onepass: Option<OnePass>, // when at most one thread survives each byte
+    glushkov: Option<Glushkov>, // short patterns without alternation, run bit-parallel
+    prefilter: Option<Prefilter>,
+    literals: Option<Searcher>, // searched for directly when the pattern is only literals
+    pool: Mutex<Vec<Cache>> // caches not in use by any search
+}
+
+// scratch space for searching. A search with a cache last used by another
+// regex resets it first
+#[derive(Debug)]
+pub struct Cache {
+    owner: usize,
+    lazy: lazy::Cache,
+    leftmost: lazy::Cache,
+    reverse: lazy::Cache,
+    matcher: matcher::Cache,
+    backtrack: backtrack::Cache,
+    pike: pike::Cache
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            owner: 0,
+            lazy: lazy::Cache::new(),
+            leftmost: lazy::Cache::new(),
+            reverse: lazy::Cache::new(),
+            matcher: matcher::Cache::new(),
+            backtrack: backtrack::Cache::new(),
+            pike: pike::Cache::new()
+        }
+    }
+}
+
+// the offsets matched by each capture group, group 0 being the whole match
+#[derive(PartialEq,Debug,Clone)]
+pub struct Captures {
+    slots: Vec<Option<usize>> // start and end of each group
+}
+
+impl Captures {
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    // none if the group did not take part in the match
+    pub fn get(&self, group: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(2 * group), self.slots.get(2 * group + 1)) {
+            (Some(&Some(start)), Some(&Some(end))) => Some((start, end)),
+            _ => None
+        }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+impl PartialEq for Regex {
+    fn eq(&self, other: &Regex) -> bool {
+        self.nfa == other.nfa
+    }
+}
+
+impl Regex {
+    pub fn from(pattern: &str) -> Result<Regex, String> {
+        Self::with_limits(pattern, CompileLimits::new()).map_err(|error| error.to_string())
+    }
+
+    pub fn with_limits(pattern: &str, limits: CompileLimits) -> Result<Regex, CompileError> {
+        let expr = Expr::parse(pattern, limits)?;
+        Self::from_expr(&expr, limits)
+    }
+
+    pub(crate) fn from_expr(expr: &Expr, limits: CompileLimits) -> Result<Regex, CompileError> {
+        let nfa = Builder::new().limits(limits).try_build(expr)?;
+
+        // a literal pattern is searched for directly, so it needs no
+        // prefilter or position automaton
+        let literals = literal::alternatives(expr).map(Searcher::new);
+        let prefilter = if literals.is_some() { None } else { Prefilter::from_expr(expr) };
+        let glushkov = if literals.is_some() { None } else { Glushkov::from_expr(expr) };
+
+        // the Glushkov NFA has no empty edges for a stream to follow, unless
+        // it has too many transitions
+        let unanchored = match Builder::new().construction(Construction::Glushkov).limits(limits).try_build(expr) {
+            Ok(glushkov) => glushkov.unanchored(),
+            Err(_) => nfa.unanchored()
+        };
+        Ok(Self::from_parts(nfa, unanchored, glushkov, prefilter, literals))
+    }
+
+    // the parts that need the expression, the rest is compiled from the NFA.
+    // The reversed NFA of a literal pattern is left empty
+    fn from_parts(nfa: NFA, unanchored: NFA, glushkov: Option<Glushkov>,
+                  prefilter: Option<Prefilter>, literals: Option<Searcher>) -> Regex {
+        Regex {
+            id: NEXT_REGEX_ID.fetch_add(1, Ordering::Relaxed),
+            unanchored,
+            reverse: if literals.is_some() { NFA::new() } else { nfa.reverse() },
+            onepass: OnePass::new(&nfa),
+            glushkov,
+            program: Program::from_nfa(&nfa),
+            nfa,
+            prefilter,
+            literals,
+            pool: Mutex::new(Vec::new())
+        }
+    }
+
+    // the versioned binary format of ::format, e.g. to compile patterns
+    // ahead of time: the NFA, the unanchored NFA, then the Glushkov tables,
+    // prefilter and literals, each after a flag saying whether there is one.
+    // The other engines are compiled from the NFA on load
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        format::write_header(&mut out, REGEX_MAGIC);
+        format::write_block(&mut out, &self.nfa.to_bytes());
+        format::write_block(&mut out, &self.unanchored.to_bytes());
+        write_option(&mut out, &self.glushkov, Glushkov::write);
+        write_option(&mut out, &self.prefilter, Prefilter::write);
+        write_option(&mut out, &self.literals, Searcher::write);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Regex, String> {
+        let mut reader = Reader::new(bytes, REGEX_MAGIC)?;
+        let nfa = NFA::from_bytes(reader.block()?)?;
+        let unanchored = NFA::from_bytes(reader.block()?)?;
+        let glushkov = read_option(&mut reader, Glushkov::read)?;
+        let prefilter = read_option(&mut reader, Prefilter::read)?;
+        let literals = read_option(&mut reader, Searcher::read)?;
+        reader.finish()?;
+        Ok(Self::from_parts(nfa, unanchored, glushkov, prefilter, literals))
+    }
+
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        self.with_pooled_cache(|cache| self.is_match_with(cache, text))
+    }
+
+    pub fn match_offset(&self, text: &[u8]) -> Option<usize> {
+        self.with_pooled_cache(|cache| self.match_offset_with(cache, text))
+    }
+
+    pub fn captures(&self, text: &[u8]) -> Option<Captures> {
+        self.with_pooled_cache(|cache| self.captures_with(cache, text))
+    }
+
+    // the leftmost match anywhere in text, as start and end offsets
+    pub fn find(&self, text: &[u8]) -> Option<(usize, usize)> {
+        self.with_pooled_cache(|cache| self.find_with(cache, text))
+    }
+
+    // searches that give up with an error once they exceed limits. The lazy
+    // DFA runs first and the matcher or the Pike VM takes over from it, on
+    // one budget. The literal searchers, the prefilter and the other
+    // engines cannot stop part way, so these searches go without them
+    pub fn is_match_limited(&self, text: &[u8], limits: Limits) -> Result<bool, MatchError> {
+        self.with_pooled_cache(|cache| self.is_match_within(cache, text, &mut Budget::new(limits)))
+    }
+
+    // the lazy DFA rules out text without a match before the matcher looks
+    // for the end it prefers
+    pub fn match_offset_limited(&self, text: &[u8], limits: Limits) -> Result<Option<usize>, MatchError> {
+        self.with_pooled_cache(|cache| {
+            let mut budget = Budget::new(limits);
+            if !self.is_match_within(cache, text, &mut budget)? {
+                return Ok(None);
+            }
+            Matcher::new(&self.nfa, text).run_limited(&mut cache.matcher, &mut budget)
+        })
+    }
+
+    pub fn captures_limited(&self, text: &[u8], limits: Limits) -> Result<Option<Captures>, MatchError> {
+        self.with_pooled_cache(|cache| {
+            let mut budget = Budget::new(limits);
+            if !self.is_match_within(cache, text, &mut budget)? {
+                return Ok(None);
+            }
+            let slots = PikeVM::new(&self.program).captures_limited(&mut cache.pike, text, &mut budget)?;
+            Ok(slots.map(|slots| Captures {
+                slots
+            }))
+        })
+    }
+
+    pub fn find_limited(&self, text: &[u8], limits: Limits) -> Result<Option<(usize, usize)>, MatchError> {
+        self.with_pooled_cache(|cache| self.find_within(cache, text, None, &mut Budget::new(limits)))
+    }
+
+    fn is_match_within(&self, cache: &mut Cache, text: &[u8], budget: &mut Budget) -> Result<bool, MatchError> {
+        self.claim(cache);
+        let dfa = LazyDFA::new(&self.nfa, lazy::DEFAULT_CACHE_SIZE);
+
+        match dfa.is_match(&mut cache.lazy, text, budget) {
+            Ok(is_match) => Ok(is_match),
+            Err(Interrupted::CacheThrashed) => {
+                Matcher::new(&self.nfa, text).run_limited(&mut cache.matcher, budget).map(|end| end.is_some())
+            },
+            Err(Interrupted::Exceeded(error)) => Err(error)
+        }
+    }
+
+    // searches with caller-owned scratch space, which never contends with
+    // other threads
+    pub fn is_match_with(&self, cache: &mut Cache, text: &[u8]) -> bool {
+        if let Some(ref literals) = self.literals {
+            return literals.match_at(text, 0).is_some();
+        }
+        if let Some(ref glushkov) = self.glushkov {
+            return glushkov.is_match(text);
+        }
+
+        self.claim(cache);
+        let dfa = LazyDFA::new(&self.nfa, lazy::DEFAULT_CACHE_SIZE);
+
+        match dfa.is_match(&mut cache.lazy, text, &mut Budget::unlimited()) {
+            Ok(is_match) => is_match,
+            Err(_) => self.match_end(cache, text).is_some() // fall back to the NFA matcher
+        }
+    }
+
+    pub fn match_offset_with(&self, cache: &mut Cache, text: &[u8]) -> Option<usize> {
+        if let Some(ref literals) = self.literals {
+            return literals.match_at(text, 0);
+        }
+
+        self.claim(cache);
+        self.match_end(cache, text)
+    }
+
+    // end of the match at the start of text, from the NFA
+    fn match_end(&self, cache: &mut Cache, text: &[u8]) -> Option<usize> {
+        if let Some(ref onepass) = self.onepass {
+            onepass.captures(text).and_then(|slots| slots[1])
+        } else if backtrack::fits(&self.program, text) {
+            Backtracker::new(&self.program, text).captures(&mut cache.backtrack).and_then(|slots| slots[1])
+        } else {
+            Matcher::new(&self.nfa, text).run(&mut cache.matcher)
+        }
+    }
+
+    // a one-pass NFA runs as a DFA. Otherwise the bounded backtracker is
+    // fastest, but its visited bits grow with both the NFA and the text.
+    // Larger searches go through the Pike VM
+    pub fn captures_with(&self, cache: &mut Cache, text: &[u8]) -> Option<Captures> {
+        let slots = if let Some(ref onepass) = self.onepass {
+            onepass.captures(text)
+        } else if backtrack::fits(&self.program, text) {
+            Backtracker::new(&self.program, text).captures(&mut cache.backtrack)
+        } else {
+            PikeVM::new(&self.program).captures(&mut cache.pike, text)
+        };
+
+        slots.map(|slots| Captures {
+            slots
+        })
+    }
+
+    pub fn find_with(&self, cache: &mut Cache, text: &[u8]) -> Option<(usize, usize)> {
+        if let Some(ref literals) = self.literals {
+            return literals.find(text);
+        }
+
+        self.find_within(cache, text, self.prefilter.as_ref(), &mut Budget::unlimited()).unwrap() // cannot run out
+    }
+
+    fn find_within(&self, cache: &mut Cache, text: &[u8], prefilter: Option<&Prefilter>,
+                   budget: &mut Budget) -> Result<Option<(usize, usize)>, MatchError> {
+        self.claim(cache);
+
+        // no match starts before the first occurrence of a literal prefix
+        let first = match prefilter {
+            Some(prefilter) => match prefilter.find(text, 0) {
+                Some(first) => first,
+                None => return Ok(None)
+            },
+            None => 0
+        };
+
+        // a literal pattern has no reversed NFA, so the matcher finds it
+        if self.literals.is_none() {
+            match self.find_leftmost(cache, text, first, budget) {
+                Ok(found) => return Ok(found),
+                Err(Interrupted::CacheThrashed) => {},
+                Err(Interrupted::Exceeded(error)) => return Err(error)
+            }
+        }
+        Matcher::new(&self.nfa, text).find_limited(&mut cache.matcher, first, prefilter, budget)
+    }
+
+    // a forward scan finds where the match ends, then the reversed NFA runs
+    // back from there to the leftmost position a match ending there starts
+    fn find_leftmost(&self, cache: &mut Cache, text: &[u8], first: usize,
+                     budget: &mut Budget) -> Result<Option<(usize, usize)>, Interrupted> {
+        let forward = LazyDFA::leftmost_first(&self.nfa, lazy::DEFAULT_CACHE_SIZE);
+        let end = match forward.leftmost_match_end(&mut cache.leftmost, &text[first..], budget)? {
+            Some(end) => first + end,
+            None => return Ok(None)
+        };
+
+        let reverse = LazyDFA::new(&self.reverse, lazy::DEFAULT_CACHE_SIZE);
+        let mut current = reverse.start_state(&mut cache.reverse)?;
+        let mut start = end;
+        let mut position = end;
+        loop {
+            let closure = cache.reverse.closure(current);
+            if closure.is_match() {
+                start = position;
+            }
+            if closure.states.is_empty() || position == first {
+                return Ok(Some((start, end)));
+            }
+
+            position -= 1;
+            budget.step()?;
+            budget.read(position)?;
+            current = reverse.next_state(&mut cache.reverse, current, text[position])?;
+        }
+    }
+
+    // matches text pushed a chunk at a time, e.g. from a file or socket
+    pub fn stream(&self) -> StreamMatcher<'_> {
+        StreamMatcher::new(&self.nfa)
+    }
+
+    // finds where the first match anywhere in a stream ends
+    pub fn find_stream(&self) -> StreamMatcher<'_> {
+        StreamMatcher::unanchored(&self.unanchored)
+    }
+
+    // the NFA the regex is matched with, as a graphviz digraph
+    pub fn to_dot(&self) -> String {
+        self.nfa.to_dot()
+    }
+
+    // how the reference matcher finds the match at the start of text
+    pub fn trace(&self, text: &[u8]) -> Trace {
+        let mut trace = Trace::new();
+        self.trace_with(text, &mut trace);
+        trace
+    }
+
+    // the match offset, found by the reference matcher while observer watches
+    pub fn trace_with<O: Observer>(&self, text: &[u8], observer: &mut O) -> Option<usize> {
+        Matcher::new(&self.nfa, text).run_observed(&mut matcher::Cache::new(), observer)
+    }
+
+    // the lazy DFA states in a cache belong to one regex
+    fn claim(&self, cache: &mut Cache) {
+        if cache.owner != self.id {
+            cache.owner = self.id;
+            cache.lazy = lazy::Cache::new();
+            cache.leftmost = lazy::Cache::new();
+            cache.reverse = lazy::Cache::new();
+        }
+    }
+
+    // the pool is only locked to take and return a cache, so concurrent
+    // searches run in parallel. It grows to the number of concurrent searches
+    fn with_pooled_cache<T, F: FnOnce(&mut Cache) -> T>(&self, search: F) -> T {
+        let cache = self.pool.lock().ok().and_then(|mut pool| pool.pop());
+        let mut cache = cache.unwrap_or_default();
+
+        let result = search(&mut cache);
+
+        if let Ok(mut pool) = self.pool.lock() {
+            pool.push(cache);
+        }
+        result
+    }
+}
+
+fn write_option<T>(out: &mut Vec<u8>, part: &Option<T>, write: fn(&T, &mut Vec<u8>)) {
+    match *part {
+        Some(ref part) => {
+            out.push(1);
+            write(part, out);
+        },
+        None => out.push(0)
+    }
+}
+
+fn read_option<T>(reader: &mut Reader, read: fn(&mut Reader) -> Result<T, String>) -> Result<Option<T>, String> {
+    match reader.u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(read(reader)?)),
+        flag => Err(format!("invalid flag {}", flag))
+    }
+}
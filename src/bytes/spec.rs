@@ -0,0 +1,204 @@
+use ::matcher::{self, Matcher};
+use ::literal::Searcher;
+use ::limits::Budget;
+use super::{Regex, Cache};
+
+#[test]
+fn matches_bytes() {
+    let regex = Regex::from("ab+").unwrap();
+    assert_eq!(Some(3), regex.match_offset(b"abbc"));
+    assert!(!regex.is_match(b"a\xFFb"));
+}
+
+#[test]
+fn matches_invalid_utf8_text() {
+    let regex = Regex::from("a.c").unwrap();
+    assert!(regex.is_match(b"abc\xFF"));
+    assert!(!regex.is_match(b"a\xFFc"));
+}
+
+#[test]
+fn dot_matches_whole_char_in_unicode_mode() {
+    let regex = Regex::from(".").unwrap();
+    assert_eq!(Some(2), regex.match_offset("é".as_bytes()));
+    assert_eq!(Some(4), regex.match_offset("😀".as_bytes()));
+    assert_eq!(None, regex.match_offset(b"\xFF"));
+}
+
+#[test]
+fn dot_matches_any_byte_in_bytes_mode() {
+    let regex = Regex::from("(?-u).").unwrap();
+    assert_eq!(Some(1), regex.match_offset("é".as_bytes()));
+    assert_eq!(Some(1), regex.match_offset(b"\xFF"));
+    assert_eq!(None, regex.match_offset(b"\n"));
+}
+
+#[test]
+fn hex_escape_is_raw_byte_in_bytes_mode() {
+    let regex = Regex::from("(?-u)\\xFF\\x00").unwrap();
+    assert!(regex.is_match(b"\xFF\x00"));
+    assert!(!regex.is_match("\u{FF}\u{0}".as_bytes()));
+
+    let regex = Regex::from("\\xFF").unwrap();
+    assert!(regex.is_match("\u{FF}".as_bytes()));
+    assert!(!regex.is_match(b"\xFF"));
+}
+
+#[test]
+fn class_matches_bytes_in_bytes_mode() {
+    let regex = Regex::from("(?-u)[\\x80\\xC3\\xFF]+").unwrap();
+    assert_eq!(Some(3), regex.match_offset(b"\x80\xC3\xFFa"));
+    assert_eq!(None, regex.match_offset(b"a"));
+
+    let regex = Regex::from("[é]").unwrap();
+    assert!(regex.is_match("é".as_bytes()));
+    assert!(!regex.is_match(b"\xC3"));
+}
+
+#[test]
+fn matches_stream() {
+    let regex = Regex::from("(?-u)\\xFF[ab]*\\x00").unwrap();
+    let mut stream = regex.stream();
+
+    stream.push(b"\xFFaba");
+    stream.push(b"b\x00\x00");
+    assert_eq!(Some(6), stream.shortest_match());
+}
+
+#[test]
+fn matches_reader() {
+    let regex = Regex::from("a+").unwrap();
+    let mut stream = regex.stream();
+
+    stream.push_reader(&b"aaaab"[..]).unwrap();
+    assert_eq!(Some(4), stream.longest_match());
+}
+
+#[test]
+fn finds_match_in_stream() {
+    let regex = Regex::from("(?-u)\\xFF[ab]+\\x00").unwrap();
+    let mut stream = regex.find_stream();
+
+    assert!(stream.push(b"xx\xFF\x00\xFF"));
+    assert!(stream.push(b"ab"));
+    assert!(!stream.is_match());
+    assert!(!stream.push(b"b\x00yy"));
+    assert_eq!(Some(9), stream.shortest_match());
+    assert_eq!(9, stream.offset());
+}
+
+#[test]
+fn finds_match_in_binary_data() {
+    let regex = Regex::from("(?-u)\\xFF\\x00+").unwrap();
+    assert_eq!(Some((3, 6)), regex.find(b"\xFF\x01\x02\xFF\x00\x00\x01"));
+    assert_eq!(None, regex.find(b"\x00\xFF"));
+}
+
+// the leftmost match found by running the NFA matcher from every start
+fn nfa_find(regex: &Regex, text: &[u8]) -> Option<(usize, usize)> {
+    (0..text.len() + 1).filter_map(|start| {
+        Matcher::new(&regex.nfa, &text[start..]).run(&mut matcher::Cache::new()).map(|end| (start, start + end))
+    }).next()
+}
+
+#[test]
+fn literal_fast_path_agrees_with_nfa() {
+    let patterns = ["abc", "foo|fob", "cd|(ab|b)", "a|ab", "ab|a", "(y|x)(a|b)c", "é|e", "(?-u)\\xFFa|a"];
+    let texts: [&[u8]; 9] = [b"", b"abc", b"xabcd", b"fob foo", b"bcd", b"xacyb", b"ab", "eé".as_bytes(), b"\xFFa"];
+
+    for pattern in patterns.iter() {
+        let regex = Regex::from(pattern).unwrap();
+        assert!(regex.literals.is_some(), "{}", pattern);
+
+        for text in texts.iter() {
+            let expected = Matcher::new(&regex.nfa, text).run(&mut matcher::Cache::new());
+            assert_eq!(expected, regex.match_offset(text), "{} on {:?}", pattern, text);
+            assert_eq!(expected.is_some(), regex.is_match(text), "{} on {:?}", pattern, text);
+            assert_eq!(nfa_find(&regex, text), regex.find(text), "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn keyword_alternation_agrees_with_nfa() {
+    let keywords = (0..200).map(|i| format!("k{}w{}", i % 7, i)).collect::<Vec<_>>();
+    let regex = Regex::from(&keywords.join("|")).unwrap();
+    assert!(matches!(regex.literals, Some(Searcher::AhoCorasick(_))));
+
+    let texts: [&[u8]; 6] = [b"", b"k3w3", b"k3w31 k3w3", b"xx k1w1 k1w15", b"k1w15", b"k1w"];
+    for text in texts.iter() {
+        let expected = Matcher::new(&regex.nfa, text).run(&mut matcher::Cache::new());
+        assert_eq!(expected, regex.match_offset(text), "{:?}", text);
+        assert_eq!(nfa_find(&regex, text), regex.find(text), "{:?}", text);
+    }
+}
+
+#[test]
+fn find_agrees_with_nfa() {
+    let patterns = ["b+", "a*b", "(ab|a)(bc|c)*", "x*", "é.", "[0123456789]+-[0123456789]+", "(?-u)\\xFF+a?", "ca?|cab"];
+    let texts: [&[u8]; 9] = [b"", b"aab", b"xabcbcd", b"12-34 5-6", "aéé".as_bytes(), b"\xFF\xFFa", b"ccc", b"a-1-2", b"xcab"];
+
+    for pattern in patterns.iter() {
+        let regex = Regex::from(pattern).unwrap();
+        for text in texts.iter() {
+            assert_eq!(nfa_find(&regex, text), regex.find(text), "{} on {:?}", pattern, text);
+
+            let mut cache = Cache::new();
+            regex.claim(&mut cache);
+            assert_eq!(Ok(nfa_find(&regex, text)), regex.find_leftmost(&mut cache, text, 0, &mut Budget::unlimited()), "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn finds_in_stream_with_empty_edges_when_glushkov_nfa_is_too_large() {
+    // each `a?` is followed by every position after it
+    let regex = Regex::from(&format!("{}b", "a?".repeat(400))).unwrap();
+    assert_eq!(regex.nfa.unanchored(), regex.unanchored);
+    let mut stream = regex.find_stream();
+    stream.push(b"xaab");
+    assert_eq!(Some(4), stream.shortest_match());
+
+    let regex = Regex::from("a?a?b").unwrap();
+    assert!(regex.unanchored != regex.nfa.unanchored());
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    let keywords = (0..20).map(|i| format!("kw{}", i)).collect::<Vec<_>>().join("|");
+    let patterns = ["(a|b)*c", "ab+c", "foo|bar", &keywords, "(?-u)\\xFF[ab]*", "x(y)?z"];
+    let texts: [&[u8]; 5] = [b"", b"aabc xfoo", b"abbbc kw13", b"\xFFab", b"xz xyz"];
+
+    for pattern in patterns.iter() {
+        let regex = Regex::from(pattern).unwrap();
+        let loaded = Regex::from_bytes(&regex.to_bytes()).unwrap();
+        assert_eq!(regex, loaded);
+        assert_eq!(regex.unanchored, loaded.unanchored);
+        assert_eq!(regex.reverse, loaded.reverse);
+        assert_eq!(regex.glushkov, loaded.glushkov);
+        assert_eq!(regex.prefilter, loaded.prefilter);
+        assert_eq!(regex.literals, loaded.literals);
+        for text in texts.iter() {
+            assert_eq!(regex.find(text), loaded.find(text), "{} on {:?}", pattern, text);
+            assert_eq!(regex.captures(text), loaded.captures(text), "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn rejects_invalid_bytes() {
+    let bytes = Regex::from("ab|cd").unwrap().to_bytes();
+    assert_eq!(Some("unexpected end of input".to_owned()), Regex::from_bytes(&bytes[..bytes.len() - 1]).err());
+    assert_eq!(Some("not a serialized RXRB".to_owned()), Regex::from_bytes(b"RXNF").err());
+
+    // ends with the literals "ab" and "cd", after flags saying there are no
+    // Glushkov tables and no prefilter
+    let literals = bytes.len() - 17;
+    assert_eq!(&b"\0\0\x01\x02\0\0\0\x02\0\0\0ab"[..], &bytes[literals - 2..literals + 11]);
+    let mut invalid = bytes.clone();
+    invalid[literals] = 2;
+    assert_eq!(Some("invalid flag 2".to_owned()), Regex::from_bytes(&invalid).err());
+    let mut invalid = bytes.clone();
+    invalid[literals + 5] = 0;
+    assert_eq!(Some("empty literal".to_owned()), Regex::from_bytes(&invalid).err());
+}
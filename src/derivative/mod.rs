@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use ::expr::Expr;
+
+#[cfg(test)] mod spec;
+
+// what is left of an expression after deriving it. Patterns cannot write
+// the empty string or nothing, so those live here rather than in Expr
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
+pub enum Derivative {
+    Empty, // matches only the empty string
+    Nothing, // matches no string at all
+    Expr(Expr),
+    Sequence(Box<Derivative>, Box<Derivative>),
+    Or(Box<Derivative>, Box<Derivative>)
+}
+
+impl Derivative {
+    // parsed sequences and alternatives are taken apart, so that they are
+    // spelled as the ones derivatives build
+    pub fn from_expr(expr: &Expr) -> Derivative {
+        match *expr {
+            Expr::Sequence(ref a, ref b) => sequence(Derivative::from_expr(a), Derivative::from_expr(b)),
+            Expr::Or(ref a, ref b) => or(Derivative::from_expr(a), Derivative::from_expr(b)),
+            _ => Derivative::Expr(expr.clone())
+        }
+    }
+
+    pub fn is_nullable(&self) -> bool {
+        match *self {
+            Derivative::Empty => true,
+            Derivative::Nothing => false,
+            Derivative::Expr(ref expr) => expr.is_nullable(),
+            Derivative::Sequence(ref a, ref b) => a.is_nullable() && b.is_nullable(),
+            Derivative::Or(ref a, ref b) => a.is_nullable() || b.is_nullable()
+        }
+    }
+
+    pub fn derivative(&self, byte: u8) -> Derivative {
+        match *self {
+            Derivative::Empty | Derivative::Nothing => Derivative::Nothing,
+            Derivative::Expr(ref expr) => expr.derivative(byte),
+            Derivative::Sequence(ref a, ref b) => {
+                let first = sequence(a.derivative(byte), (**b).clone());
+                if a.is_nullable() {
+                    or(first, b.derivative(byte))
+                } else {
+                    first
+                }
+            },
+            Derivative::Or(ref a, ref b) => or(a.derivative(byte), b.derivative(byte))
+        }
+    }
+}
+
+// derivatives by bytes, so utf-8 chars and `.` are taken apart into the
+// bytes the NFA reads. Derivatives lose capture groups, and with them the
+// order of alternatives: they tell which texts match, not how
+impl Expr {
+    pub fn is_nullable(&self) -> bool {
+        match *self {
+            Expr::Optional(_) | Expr::ZeroOrMore(_) => true,
+            Expr::Single(_) | Expr::Class(_) | Expr::Any |
+            Expr::Byte(_) | Expr::ByteClass(_) | Expr::AnyByte => false,
+            Expr::Group(ref expr) | Expr::OneOrMore(ref expr) => expr.is_nullable(),
+            Expr::Sequence(ref a, ref b) => a.is_nullable() && b.is_nullable(),
+            Expr::Or(ref a, ref b) => a.is_nullable() || b.is_nullable()
+        }
+    }
+
+    // matches the rest of each match of self that starts with byte
+    pub fn derivative(&self, byte: u8) -> Derivative {
+        match *self {
+            Expr::Single(c) => rest_of_char(c, byte),
+            Expr::Class(ref chars) => {
+                chars.iter().fold(Derivative::Nothing, |result, &c| or(result, rest_of_char(c, byte)))
+            },
+            Expr::Any => {
+                // as in the NFA, any char but newline, checked loosely by its lead byte
+                let continuations = match byte {
+                    b'\n' => return Derivative::Nothing,
+                    0x00..=0x7F => 0,
+                    0xC2..=0xDF => 1,
+                    0xE0..=0xEF => 2,
+                    0xF0..=0xF4 => 3,
+                    _ => return Derivative::Nothing
+                };
+                (0..continuations).fold(Derivative::Empty, |rest, _| {
+                    sequence(Derivative::Expr(Expr::ByteClass((0x80..0xC0).collect())), rest)
+                })
+            },
+            Expr::Byte(b) => if b == byte { Derivative::Empty } else { Derivative::Nothing },
+            Expr::ByteClass(ref bytes) => {
+                if bytes.contains(&byte) { Derivative::Empty } else { Derivative::Nothing }
+            },
+            Expr::AnyByte => if byte != b'\n' { Derivative::Empty } else { Derivative::Nothing },
+            Expr::Group(ref expr) | Expr::Optional(ref expr) => expr.derivative(byte),
+            Expr::Sequence(ref a, ref b) => {
+                let first = sequence(a.derivative(byte), Derivative::from_expr(b));
+                if a.is_nullable() {
+                    or(first, b.derivative(byte))
+                } else {
+                    first
+                }
+            },
+            Expr::Or(ref a, ref b) => or(a.derivative(byte), b.derivative(byte)),
+            Expr::ZeroOrMore(ref expr) | Expr::OneOrMore(ref expr) => {
+                sequence(expr.derivative(byte), zero_or_more(expr))
+            }
+        }
+    }
+
+    // whether the whole of text matches, deriving by each byte in turn
+    pub fn matches(&self, text: &[u8]) -> bool {
+        let mut derivative = Derivative::from_expr(self);
+        for &byte in text {
+            derivative = derivative.derivative(byte);
+            if derivative == Derivative::Nothing {
+                return false;
+            }
+        }
+        derivative.is_nullable()
+    }
+}
+
+// the bytes after the first of c's utf-8 encoding, if it starts with byte
+fn rest_of_char(c: char, byte: u8) -> Derivative {
+    let mut buf = [0; 4];
+    let bytes = c.encode_utf8(&mut buf).as_bytes();
+    if bytes[0] != byte {
+        return Derivative::Nothing;
+    }
+    bytes[1..].iter().rev().fold(Derivative::Empty, |rest, &b| {
+        sequence(Derivative::Expr(Expr::Byte(b)), rest)
+    })
+}
+
+// the smart constructors below keep derivatives in a normal form, in which
+// an expression has finitely many distinct derivatives
+
+fn sequence(left: Derivative, right: Derivative) -> Derivative {
+    match (left, right) {
+        (Derivative::Nothing, _) | (_, Derivative::Nothing) => Derivative::Nothing,
+        (Derivative::Empty, derivative) | (derivative, Derivative::Empty) => derivative,
+        (Derivative::Sequence(a, b), right) => sequence(*a, sequence(*b, right)),
+        (left, right) => Derivative::Sequence(Box::new(left), Box::new(right))
+    }
+}
+
+// alternatives are flattened, sorted and deduplicated
+fn or(left: Derivative, right: Derivative) -> Derivative {
+    let mut alternatives = Vec::new();
+    flatten(left, &mut alternatives);
+    flatten(right, &mut alternatives);
+    alternatives.retain(|derivative| derivative != &Derivative::Nothing);
+    alternatives.sort();
+    alternatives.dedup();
+
+    match alternatives.pop() {
+        None => Derivative::Nothing,
+        Some(last) => alternatives.into_iter().rev().fold(last, |rest, derivative| {
+            Derivative::Or(Box::new(derivative), Box::new(rest))
+        })
+    }
+}
+
+fn flatten(derivative: Derivative, alternatives: &mut Vec<Derivative>) {
+    match derivative {
+        Derivative::Or(a, b) => {
+            flatten(*a, alternatives);
+            flatten(*b, alternatives);
+        },
+        derivative => alternatives.push(derivative)
+    }
+}
+
+fn zero_or_more(expr: &Expr) -> Derivative {
+    match *expr {
+        Expr::ZeroOrMore(_) => Derivative::Expr(expr.clone()),
+        _ => Derivative::Expr(Expr::zero_or_more(expr.clone()))
+    }
+}
+
+// a DFA whose states are the derivatives of an expression, built as text
+// is read. Equal derivatives are memoised as one state
+#[derive(Debug)]
+pub struct DerivativeDFA {
+    states: Vec<Derivative>,
+    accepting: Vec<bool>,
+    ids: HashMap<Derivative, usize>,
+    transitions: Vec<Vec<Option<usize>>> // by state and byte, derived on first use
+}
+
+impl DerivativeDFA {
+    pub fn new(expr: &Expr) -> DerivativeDFA {
+        let mut dfa = DerivativeDFA {
+            states: Vec::new(),
+            accepting: Vec::new(),
+            ids: HashMap::new(),
+            transitions: Vec::new()
+        };
+        dfa.add_state(Derivative::from_expr(expr));
+        dfa
+    }
+
+    fn add_state(&mut self, derivative: Derivative) -> usize {
+        if let Some(&id) = self.ids.get(&derivative) {
+            return id;
+        }
+
+        let id = self.states.len();
+        self.accepting.push(derivative.is_nullable());
+        self.transitions.push(vec![None; 256]);
+        self.ids.insert(derivative.clone(), id);
+        self.states.push(derivative);
+        id
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn next_state(&mut self, state: usize, byte: u8) -> usize {
+        if let Some(next) = self.transitions[state][byte as usize] {
+            return next;
+        }
+
+        let derivative = self.states[state].derivative(byte);
+        let next = self.add_state(derivative);
+        self.transitions[state][byte as usize] = Some(next);
+        next
+    }
+
+    fn is_dead(&self, state: usize) -> bool {
+        self.states[state] == Derivative::Nothing
+    }
+
+    // whether the whole of text matches
+    pub fn matches(&mut self, text: &[u8]) -> bool {
+        let mut state = 0;
+        for &byte in text {
+            state = self.next_state(state, byte);
+            if self.is_dead(state) {
+                return false;
+            }
+        }
+        self.accepting[state]
+    }
+
+    // whether a match starts at the start of text, as in the other engines
+    pub fn is_match(&mut self, text: &[u8]) -> bool {
+        let mut state = 0;
+        for &byte in text {
+            if self.accepting[state] {
+                return true;
+            }
+            state = self.next_state(state, byte);
+            if self.is_dead(state) {
+                return false;
+            }
+        }
+        self.accepting[state]
+    }
+}
@@ -0,0 +1,73 @@
+use ::expr::Expr;
+use ::bytes::Regex;
+use super::{Derivative, DerivativeDFA};
+
+fn expr(pattern: &str) -> Expr {
+    pattern.parse::<Expr>().unwrap()
+}
+
+fn derived(pattern: &str) -> Derivative {
+    Derivative::from_expr(&expr(pattern))
+}
+
+#[test]
+fn finds_nullable_exprs() {
+    assert!(expr("a*").is_nullable());
+    assert!(expr("(a?b*)+").is_nullable());
+    assert!(expr("ab|c*").is_nullable());
+    assert!(!expr("a*b").is_nullable());
+    assert!(!expr("(ab)+").is_nullable());
+}
+
+#[test]
+fn derives_in_normal_form() {
+    assert_eq!(derived("b"), expr("ab").derivative(b'a'));
+    assert_eq!(Derivative::Nothing, expr("a|b").derivative(b'c'));
+    assert_eq!(Derivative::Empty, expr("a|a").derivative(b'a'));
+    assert_eq!(Derivative::Sequence(Box::new(derived("b")), Box::new(derived("(ab)*"))),
+               expr("(ab)*").derivative(b'a'));
+    assert_eq!(Derivative::Or(Box::new(derived("b")), Box::new(derived("c"))),
+               expr("ab|ac|ab").derivative(b'a'));
+    assert_eq!(expr("abc").derivative(b'a'), expr("(ab)c").derivative(b'a'));
+}
+
+#[test]
+fn derives_by_utf8_bytes() {
+    assert_eq!(Derivative::Expr(Expr::Byte(0xA9)), expr("é").derivative(0xC3));
+    assert!(expr("é.").matches("éñ".as_bytes()));
+    assert!(!expr("é.").matches("é\n".as_bytes()));
+    assert!(expr("(?-u)\\xFF.").matches(b"\xFF\xFE"));
+}
+
+#[test]
+fn matches_whole_text() {
+    let expr = expr("(a|b)*abb");
+    assert!(expr.matches(b"ababb"));
+    assert!(!expr.matches(b"ababba"));
+    assert!(!expr.matches(b""));
+}
+
+#[test]
+fn memoises_finitely_many_states() {
+    let mut dfa = DerivativeDFA::new(&expr("(a|b)*abb"));
+    for text in ["ababb", "bbbbabab", "aabbabba", "abbbbbba"].iter() {
+        dfa.matches(text.as_bytes());
+    }
+    assert!(dfa.matches(b"abababbabb"));
+    assert!(!dfa.matches(b"abba"));
+    assert_eq!(4, dfa.num_states()); // as many as the minimal DFA
+}
+
+#[test]
+fn agrees_with_regex() {
+    let patterns = ["a?a?aa", "((abc|acc)b)+", "[ab]+c", ".*c", "(ab|bc)*d", "(a|ab)(c|bcd)", "é|[àáâãä]x", "a(b*c)+", "(a*)+b"];
+    let texts = ["", "aa", "a", "abcbaccb", "abbac", "xyzc", "ababbcd", "abcd", "aab", "é", "âx", "\nc"];
+
+    for pattern in patterns.iter() {
+        let regex = Regex::from(pattern).unwrap();
+        let mut dfa = DerivativeDFA::new(&expr(pattern));
+        for text in texts.iter() {
+            assert_eq!(regex.is_match(text.as_bytes()), dfa.is_match(text.as_bytes()), "{} on {:?}", pattern, text);
+        }
+    }
+}
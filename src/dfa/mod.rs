@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::vec_deque::VecDeque;
+
+use ::expr::Expr;
+use ::nfa::{self, NFA, ByteClasses};
+use ::format::{self, Reader};
+
+#[cfg(test)] mod spec;
+
+const DFA_MAGIC: &[u8; 4] = b"RXDF";
+
+#[derive(PartialEq,Debug,Clone)]
+pub struct DFA {
+    start: usize,
+    classes: ByteClasses,
+    transitions: Vec<usize>, // one entry per byte class per state
+    accepting: Vec<bool>
+}
+
+impl DFA {
+    // the minimal DFA matching expr at the start of text
+    pub fn new(expr: &Expr) -> DFA {
+        DFA::from_nfa(&NFA::from_expr(expr)).minimize()
+    }
+
+    // subset construction over every state reachable from the start
+    pub fn from_nfa(nfa: &NFA) -> DFA {
+        let classes = nfa.byte_classes().clone();
+        let representatives = classes.representatives();
+        let start = nfa.start_closure();
+
+        let mut ids = HashMap::new();
+        ids.insert(start.clone(), 0);
+        let mut closures = vec![start];
+        let mut transitions = Vec::new();
+
+        let mut current = 0;
+        while current < closures.len() {
+            for &byte in &representatives {
+                let next = nfa.step(&closures[current].states, byte);
+
+                let id = match ids.get(&next) {
+                    Some(&id) => id,
+                    None => {
+                        let id = closures.len();
+                        ids.insert(next.clone(), id);
+                        closures.push(next);
+                        id
+                    }
+                };
+                transitions.push(id);
+            }
+            current += 1;
+        }
+
+        DFA {
+            start: 0,
+            classes,
+            transitions,
+            accepting: closures.iter().map(|closure| closure.is_match()).collect()
+        }
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.accepting.len()
+    }
+
+    pub fn byte_classes(&self) -> &ByteClasses {
+        &self.classes
+    }
+
+    pub fn next_state(&self, state: usize, byte: u8) -> usize {
+        self.transitions[state * self.classes.num_classes() + self.classes.get(byte)]
+    }
+
+    // return optional end offset of the shortest match at the start of text
+    pub fn shortest_match(&self, text: &[u8]) -> Option<usize> {
+        let mut state = self.start;
+
+        for (i, &byte) in text.iter().enumerate() {
+            if self.accepting[state] {
+                return Some(i);
+            }
+            state = self.next_state(state, byte);
+        }
+
+        if self.accepting[state] {
+            Some(text.len())
+        } else {
+            None
+        }
+    }
+
+    // the versioned binary format of ::format: the start, the number of
+    // states and of byte classes, the class of each byte, whether each state
+    // accepts, then the transition table
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        format::write_header(&mut out, DFA_MAGIC);
+        format::write_u32(&mut out, self.start);
+        format::write_u32(&mut out, self.num_states());
+        format::write_u32(&mut out, self.classes.num_classes());
+        out.extend((0..256).map(|byte| self.classes.get(byte as u8) as u8));
+        out.extend(self.accepting.iter().map(|&accepting| accepting as u8));
+        for &next in &self.transitions {
+            format::write_u32(&mut out, next);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<DFA, String> {
+        let view = DFAView::new(bytes)?;
+        Ok(DFA {
+            start: view.start,
+            classes: ByteClasses::from_table(view.classes),
+            transitions: (0..view.num_states * view.stride).map(|i| view.transition(i)).collect(),
+            accepting: view.accepting.iter().map(|&accepting| accepting == 1).collect()
+        })
+    }
+
+    // a graphviz digraph with an edge for the bytes going from one state to
+    // another. States that never reach a match are left out
+    pub fn to_dot(&self) -> String {
+        let live = self.live_states();
+
+        let mut dot = "digraph dfa {\n    rankdir=LR;\n    node [shape=circle];\n    start [shape=point];\n".to_owned();
+        for state in (0..self.num_states()).filter(|&state| self.accepting[state]) {
+            dot.push_str(&format!("    {} [shape=doublecircle];\n", state));
+        }
+        dot.push_str(&format!("    start -> {};\n", self.start));
+
+        for state in (0..self.num_states()).filter(|&state| live[state]) {
+            let mut targets = BTreeMap::new();
+            for byte in 0..256 {
+                let next = self.next_state(state, byte as u8);
+                if live[next] {
+                    targets.entry(next).or_insert_with(Vec::new).push(byte as u8);
+                }
+            }
+            for (next, bytes) in targets {
+                dot.push_str(&format!("    {} -> {} [label={}];\n", state, next, nfa::dot_string(&nfa::bytes_label(&bytes))));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // the states a match can be reached from, found by walking back from
+    // the accepting states
+    fn live_states(&self) -> Vec<bool> {
+        let mut predecessors = vec![Vec::new(); self.num_states()];
+        for state in 0..self.num_states() {
+            for byte in self.classes.representatives() {
+                predecessors[self.next_state(state, byte)].push(state);
+            }
+        }
+
+        let mut live = self.accepting.clone();
+        let mut stack = (0..self.num_states()).filter(|&state| live[state]).collect::<Vec<_>>();
+        while let Some(state) = stack.pop() {
+            for &predecessor in &predecessors[state] {
+                if !live[predecessor] {
+                    live[predecessor] = true;
+                    stack.push(predecessor);
+                }
+            }
+        }
+        live
+    }
+
+    // Hopcroft's partition refinement. States of the result are numbered in
+    // breadth first order from the start, so two minimal DFAs for the same
+    // language compare equal
+    pub fn minimize(&self) -> DFA {
+        let num_states = self.num_states();
+        let stride = self.classes.num_classes();
+
+        let mut predecessors = vec![Vec::new(); num_states * stride];
+        for state in 0..num_states {
+            for class in 0..stride {
+                let next = self.transitions[state * stride + class];
+                predecessors[next * stride + class].push(state);
+            }
+        }
+
+        let (accepting, rejecting): (Vec<usize>, Vec<usize>) =
+            (0..num_states).partition(|&state| self.accepting[state]);
+
+        let mut blocks = Vec::new();
+        let mut block_of = vec![0; num_states];
+        for block in [accepting, rejecting] {
+            if block.is_empty() {
+                continue;
+            }
+            for &state in &block {
+                block_of[state] = blocks.len();
+            }
+            blocks.push(block);
+        }
+
+        // only the smaller of two complementary blocks needs to be a splitter
+        let mut worklist = vec![(0..blocks.len()).min_by_key(|&block| blocks[block].len()).unwrap()];
+        let mut in_worklist = (0..blocks.len()).map(|block| worklist.contains(&block)).collect::<Vec<_>>();
+
+        while let Some(splitter) = worklist.pop() {
+            in_worklist[splitter] = false;
+            let splitter = blocks[splitter].clone();
+
+            for class in 0..stride {
+                // states with an edge on `class` into the splitter, by block
+                let mut marked = HashMap::new();
+                for &target in &splitter {
+                    for &state in &predecessors[target * stride + class] {
+                        marked.entry(block_of[state]).or_insert_with(Vec::new).push(state);
+                    }
+                }
+
+                let mut marked = marked.into_iter().collect::<Vec<_>>();
+                marked.sort();
+
+                for (block, moved) in marked {
+                    if moved.len() == blocks[block].len() {
+                        continue;
+                    }
+
+                    let new_block = blocks.len();
+                    let moved_set = moved.iter().cloned().collect::<HashSet<_>>();
+                    blocks[block].retain(|state| !moved_set.contains(state));
+                    for &state in &moved {
+                        block_of[state] = new_block;
+                    }
+                    blocks.push(moved);
+
+                    // the smaller half is enough, unless the block is
+                    // already queued and both halves are needed
+                    if in_worklist[block] || blocks[new_block].len() < blocks[block].len() {
+                        worklist.push(new_block);
+                        in_worklist.push(true);
+                    } else {
+                        worklist.push(block);
+                        in_worklist[block] = true;
+                        in_worklist.push(false);
+                    }
+                }
+            }
+        }
+
+        self.quotient(&blocks, &block_of)
+    }
+
+    // the DFA over blocks of equivalent states, numbered breadth first
+    fn quotient(&self, blocks: &[Vec<usize>], block_of: &[usize]) -> DFA {
+        let stride = self.classes.num_classes();
+        let mut ids = vec![None; blocks.len()];
+        let mut order = Vec::new();
+        let mut queue = VecDeque::new();
+
+        ids[block_of[self.start]] = Some(0);
+        order.push(block_of[self.start]);
+        queue.push_back(block_of[self.start]);
+
+        while let Some(block) = queue.pop_front() {
+            let state = blocks[block][0];
+            for class in 0..stride {
+                let next = block_of[self.transitions[state * stride + class]];
+                if ids[next].is_none() {
+                    ids[next] = Some(order.len());
+                    order.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        let mut transitions = Vec::with_capacity(order.len() * stride);
+        for &block in &order {
+            let state = blocks[block][0];
+            for class in 0..stride {
+                let next = block_of[self.transitions[state * stride + class]];
+                transitions.push(ids[next].unwrap());
+            }
+        }
+
+        DFA {
+            start: 0,
+            classes: self.classes.clone(),
+            transitions,
+            accepting: order.iter().map(|&block| self.accepting[blocks[block][0]]).collect()
+        }
+    }
+
+    // both DFAs accept the same language. Their minimal DFAs must be
+    // isomorphic, even when their byte classes differ
+    pub fn is_equivalent(&self, other: &DFA) -> bool {
+        let (a, b) = (self.minimize(), other.minimize());
+        if a.num_states() != b.num_states() {
+            return false;
+        }
+
+        let mut mapping = vec![None; a.num_states()];
+        mapping[a.start] = Some(b.start);
+        let mut queue = VecDeque::new();
+        queue.push_back(a.start);
+
+        while let Some(state) = queue.pop_front() {
+            let other_state = mapping[state].unwrap();
+            if a.accepting[state] != b.accepting[other_state] {
+                return false;
+            }
+
+            for byte in 0..256 {
+                let next = a.next_state(state, byte as u8);
+                let other_next = b.next_state(other_state, byte as u8);
+
+                match mapping[next] {
+                    Some(mapped) if mapped != other_next => return false,
+                    Some(_) => (),
+                    None => {
+                        mapping[next] = Some(other_next);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
+
+// a serialized DFA searched in place, without copying its tables. The
+// bytes are checked once, when the view is made
+#[derive(Debug,Clone,Copy)]
+pub struct DFAView<'a> {
+    start: usize,
+    num_states: usize,
+    stride: usize,
+    classes: &'a [u8],
+    accepting: &'a [u8],
+    transitions: &'a [u8] // a little endian u32 per byte class per state
+}
+
+impl<'a> DFAView<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<DFAView<'a>, String> {
+        let mut reader = Reader::new(bytes, DFA_MAGIC)?;
+        let start = reader.u32()?;
+        let num_states = reader.u32()?;
+        let stride = reader.u32()?;
+        if start >= num_states {
+            return Err(format!("start state {} out of range", start));
+        }
+
+        // classes are numbered in order of their smallest byte
+        let classes = reader.bytes(256)?;
+        let mut num_classes = 0;
+        for &class in classes {
+            if class as usize > num_classes {
+                return Err(format!("byte class {} out of order", class));
+            }
+            if class as usize == num_classes {
+                num_classes += 1;
+            }
+        }
+        if num_classes != stride {
+            return Err(format!("{} byte classes, expected {}", num_classes, stride));
+        }
+
+        let accepting = reader.bytes(num_states)?;
+        if accepting.iter().any(|&accepting| accepting > 1) {
+            return Err("invalid accepting flag".to_owned());
+        }
+
+        let len = num_states.checked_mul(stride * 4).ok_or("transition table too large")?;
+        let transitions = reader.bytes(len)?;
+        reader.finish()?;
+
+        let view = DFAView {
+            start,
+            num_states,
+            stride,
+            classes,
+            accepting,
+            transitions
+        };
+        if let Some(next) = (0..num_states * stride).map(|i| view.transition(i)).find(|&next| next >= num_states) {
+            return Err(format!("state {} out of range", next));
+        }
+        Ok(view)
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.num_states
+    }
+
+    fn transition(&self, i: usize) -> usize {
+        format::read_u32_at(self.transitions, 4 * i) as usize
+    }
+
+    pub fn next_state(&self, state: usize, byte: u8) -> usize {
+        self.transition(state * self.stride + self.classes[byte as usize] as usize)
+    }
+
+    // as DFA::shortest_match
+    pub fn shortest_match(&self, text: &[u8]) -> Option<usize> {
+        let mut state = self.start;
+
+        for (i, &byte) in text.iter().enumerate() {
+            if self.accepting[state] == 1 {
+                return Some(i);
+            }
+            state = self.next_state(state, byte);
+        }
+
+        if self.accepting[state] == 1 {
+            Some(text.len())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use ::expr::Expr;
+use ::nfa::{NFA, ByteClasses, Condition};
+use super::{DFA, DFAView};
+
+fn dfa(pattern: &str) -> DFA {
+    DFA::from_nfa(&NFA::from_expr(&pattern.parse::<Expr>().unwrap()))
+}
+
+#[test]
+fn builds_complete_dfa() {
+    let dfa = dfa("ab");
+    // start, after 'a', accepting and dead
+    assert_eq!(4, dfa.num_states());
+    assert_eq!(0, dfa.start);
+}
+
+#[test]
+fn finds_shortest_match() {
+    let dfa = dfa("a(b|c)+");
+    assert_eq!(Some(2), dfa.shortest_match(b"abcbc"));
+    assert_eq!(Some(2), dfa.shortest_match(b"ac"));
+    assert_eq!(None, dfa.shortest_match(b"a"));
+    assert_eq!(None, dfa.shortest_match(b"ba"));
+}
+
+#[test]
+fn minimizes_redundant_states() {
+    let dfa = dfa("(ab|cb)d");
+    let minimal = dfa.minimize();
+
+    assert_eq!(6, dfa.num_states());
+    assert_eq!(5, minimal.num_states());
+    assert_eq!(Some(3), minimal.shortest_match(b"cbd"));
+    assert_eq!(None, minimal.shortest_match(b"cbb"));
+}
+
+#[test]
+fn minimizes_equivalent_patterns_to_same_dfa() {
+    let a = dfa("(a|b)*").minimize();
+    let b = dfa("(a*b*)*").minimize();
+
+    assert_eq!(2, a.num_states());
+    assert_eq!(a, b);
+}
+
+#[test]
+fn detects_equivalent_patterns() {
+    assert!(dfa("(a|b)*").is_equivalent(&dfa("(a*b*)*")));
+    assert!(dfa("a+").is_equivalent(&dfa("aa*")));
+    assert!(dfa("[ab]c").is_equivalent(&dfa("(a|b)c")));
+    assert!(!dfa("a+").is_equivalent(&dfa("a*")));
+    assert!(!dfa("ab|ac").is_equivalent(&dfa("a[bcd]")));
+}
+
+#[test]
+fn minimizing_minimal_dfa_is_identity() {
+    let minimal = dfa("a.?c").minimize();
+    assert_eq!(minimal, minimal.minimize());
+}
+
+#[test]
+fn new_builds_minimal_dfa_for_expression() {
+    let minimal = DFA::new(&"(a*b*)*".parse::<Expr>().unwrap());
+    assert_eq!(dfa("(a|b)*").minimize(), minimal);
+}
+
+#[test]
+fn uses_byte_classes_for_transitions() {
+    let dfa = dfa("(ab|cb)d");
+
+    assert_eq!(5, dfa.byte_classes().num_classes());
+    assert_eq!(dfa.num_states() * 5, dfa.transitions.len());
+    assert_eq!(dfa.next_state(0, b'x'), dfa.next_state(0, b'\n'));
+}
+
+#[test]
+fn exports_dot_without_dead_states() {
+    assert_eq!("digraph dfa {\n    \
+                    rankdir=LR;\n    \
+                    node [shape=circle];\n    \
+                    start [shape=point];\n    \
+                    2 [shape=doublecircle];\n    \
+                    start -> 0;\n    \
+                    0 -> 2 [label=\"a\"];\n    \
+                    2 -> 2 [label=\"[0-9x]\"];\n\
+                }\n", dfa("a[0123456789x]*").minimize().to_dot());
+}
+
+#[test]
+fn exports_dot_without_states_that_cannot_reach_a_match() {
+    // 1 and 3 go back and forth without ever reaching 2
+    let dfa = DFA {
+        start: 0,
+        classes: ByteClasses::from_conditions(&[&Condition::One(b'a')]),
+        transitions: vec![1, 2, 3, 3, 3, 3, 1, 1],
+        accepting: vec![false, false, true, false]
+    };
+    assert_eq!("digraph dfa {\n    \
+                    rankdir=LR;\n    \
+                    node [shape=circle];\n    \
+                    start [shape=point];\n    \
+                    2 [shape=doublecircle];\n    \
+                    start -> 0;\n    \
+                    0 -> 2 [label=\"a\"];\n\
+                }\n", dfa.to_dot());
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    let dfa = DFA::new(&"(ab|cb)d+".parse::<Expr>().unwrap());
+    let bytes = dfa.to_bytes();
+    assert_eq!(Ok(dfa.clone()), DFA::from_bytes(&bytes));
+
+    let view = DFAView::new(&bytes).unwrap();
+    assert_eq!(dfa.num_states(), view.num_states());
+    for text in &[&b"abdd"[..], b"cbd", b"ab", b"", b"xbd"] {
+        assert_eq!(dfa.shortest_match(text), view.shortest_match(text));
+    }
+}
+
+#[test]
+fn rejects_invalid_bytes() {
+    let bytes = DFA::new(&"ab".parse::<Expr>().unwrap()).to_bytes();
+
+    let mut invalid = bytes.clone();
+    invalid.push(0);
+    assert_eq!(Some("trailing bytes after automaton".to_owned()), DFAView::new(&invalid).err());
+
+    // the classes of 'a' and 'b' swapped, so that they are out of order
+    let mut invalid = bytes.clone();
+    invalid.swap(20 + b'a' as usize, 20 + b'b' as usize);
+    assert_eq!(Some("byte class 2 out of order".to_owned()), DFAView::new(&invalid).err());
+
+    // the first transition of the start state
+    let mut invalid = bytes.clone();
+    let transitions = invalid.len() - 4 * dfa("ab").minimize().transitions.len();
+    invalid[transitions] = 200;
+    assert_eq!(Some("state 200 out of range".to_owned()), DFAView::new(&invalid).err());
+}
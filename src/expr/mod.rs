@@ -1,17 +1,32 @@
-use std::str::FromStr;
+use std::cmp;
+use std::mem;
+use std::str::{FromStr, Chars};
 use std::collections::vec_deque::VecDeque;
 
+use ::limits::{CompileLimits, CompileError};
+use ::nfa;
+
 #[cfg(test)] mod spec;
 
 static UNARY_POSTFIX_OPERATORS: &'static [char] = &['?', '*', '+'];
 static BINARY_OPERATORS: &'static [char] = &['|'];
 static SPECIAL_CHARS: &'static [char] = &['.'];
-
-#[derive(PartialEq, Debug)]
+static METACHARS: &[char] = &['\\', '.', '?', '*', '+', '|', '(', ')', '[', ']'];
+static BYTES_FLAG: &str = "(?-u)";
+
+// sequences nest to the right and alternations to the left, as deep as
+// the pattern is long, so passes over an expression walk them in loops.
+// They only recurse into groups and repetitions, which
+// CompileLimits::nesting_depth bounds
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
 pub enum Expr {
     Single(char),
     Class(Vec<char>),
     Any,
+    Byte(u8), // a byte that need not be valid utf-8 on its own, from `(?-u)` mode
+    ByteClass(Vec<u8>),
+    AnyByte,
+    Group(Box<Expr>), // a capture group, numbered by the position of its opening paren
     Sequence(Box<Expr>, Box<Expr>),
     Or(Box<Expr>, Box<Expr>),
     Optional(Box<Expr>),
@@ -28,6 +43,10 @@ impl Expr {
         Expr::Or(Box::new(left), Box::new(right))
     }
 
+    pub fn group(item: Expr) -> Expr {
+        Expr::Group(Box::new(item))
+    }
+
     pub fn optional(item: Expr) -> Expr {
         Expr::Optional(Box::new(item))
     }
@@ -39,42 +58,106 @@ impl Expr {
     pub fn zero_or_more(item: Expr) -> Expr {
         Expr::ZeroOrMore(Box::new(item))
     }
+
+    pub fn can_match_invalid_utf8(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            match *expr {
+                Expr::Byte(b) if b >= 0x80 => return true,
+                Expr::ByteClass(ref bytes) if bytes.iter().any(|&b| b >= 0x80) => return true,
+                Expr::AnyByte => return true,
+                Expr::Byte(_) | Expr::ByteClass(_) => (),
+                Expr::Single(_) | Expr::Class(_) | Expr::Any => (),
+                Expr::Sequence(ref a, ref b) | Expr::Or(ref a, ref b) => {
+                    stack.push(b);
+                    stack.push(a);
+                },
+                Expr::Group(ref expr) | Expr::Optional(ref expr) |
+                Expr::ZeroOrMore(ref expr) | Expr::OneOrMore(ref expr) => stack.push(expr)
+            }
+        }
+        false
+    }
+
+    // made only of chars, groups and alternation, see CompileLimits::max_states
+    pub fn is_literal(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            match *expr {
+                Expr::Single(_) | Expr::Byte(_) => (),
+                Expr::Sequence(ref a, ref b) | Expr::Or(ref a, ref b) => {
+                    stack.push(b);
+                    stack.push(a);
+                },
+                Expr::Group(ref expr) => stack.push(expr),
+                _ => return false
+            }
+        }
+        true
+    }
+
+    // leaves a leaf in place of each of its parts
+    fn take_parts(&mut self, parts: &mut Vec<Expr>) {
+        match *self {
+            Expr::Sequence(ref mut a, ref mut b) | Expr::Or(ref mut a, ref mut b) => {
+                parts.push(mem::replace(&mut **a, Expr::AnyByte));
+                parts.push(mem::replace(&mut **b, Expr::AnyByte));
+            },
+            Expr::Group(ref mut expr) | Expr::Optional(ref mut expr) |
+            Expr::ZeroOrMore(ref mut expr) | Expr::OneOrMore(ref mut expr) => {
+                parts.push(mem::replace(&mut **expr, Expr::AnyByte));
+            },
+            _ => ()
+        }
+    }
+}
+
+// taken apart in a loop, as the parts would otherwise drop recursively
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut parts = Vec::new();
+        self.take_parts(&mut parts);
+        while let Some(mut part) = parts.pop() {
+            part.take_parts(&mut parts);
+        }
+    }
 }
 
 impl FromStr for Expr {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Expr,String> {
+        Expr::parse(s, CompileLimits::new()).map_err(|error| error.to_string())
+    }
+}
+
+impl Expr {
+    // parses a pattern, stopping as soon as it exceeds limits. Each char,
+    // class or `.` is charged the NFA states it expands to
+    pub fn parse(s: &str, limits: CompileLimits) -> Result<Expr, CompileError> {
 
         let mut output_queue = VecDeque::<Expr>::new();
         let mut operator_stack = Vec::<char>::new();
         let mut last_was_char = false;
-        let mut in_char_class = false;
+        let mut group_starts = Vec::new(); // length of the output queue at each open paren
+        let mut num_states = 0;
+        let mut literal = true; // only chars, groups and alternation so far
 
-        let mut current_class = Vec::new();
+        // `(?-u)` switches from matching unicode chars to matching arbitrary bytes
+        let (unicode, s) = match s.strip_prefix(BYTES_FLAG) {
+            Some(rest) => (false, rest),
+            None => (true, s)
+        };
 
         let mut chars = s.chars();
-        while chars.clone().count() > 0 {
-            let mut c = chars.next().unwrap();
-
-            if in_char_class {
-                if c == ']' {
-                    output_queue.push_back(Expr::Class(current_class));
-                    current_class = Vec::new();
-                    in_char_class = false;
-                } else {
-                    current_class.push(c);
-                }
-
-                continue;
-            }
-
+        while let Some(c) = chars.next() {
             if c == '(' {
 
                 if !output_queue.is_empty() && last_was_char {
                     operator_stack.push('@'); // "sequence" operator
                 }
                 operator_stack.push(c);
+                group_starts.push(output_queue.len());
                 last_was_char = false;
 
             } else if c == ')' {
@@ -85,11 +168,23 @@ impl FromStr for Expr {
                     top = operator_stack.pop().unwrap();
                 }
 
+                // an empty group captures nothing and is left out
+                if output_queue.len() > group_starts.pop().unwrap() {
+                    let item = output_queue.pop_back().unwrap();
+                    output_queue.push_back(Expr::group(item));
+                    check_nesting(output_queue.back().unwrap(), limits)?;
+                }
+                last_was_char = true; // a group is sequenced like a char
+
             } else if c == '[' {
-                last_was_char = false;
-                in_char_class = true;
+
+                let class = parse_class(&mut chars, unicode, limits)?;
+                num_states += nfa::leaf_states(&class);
+                literal = false;
+                push_operand(class, &mut output_queue, &mut operator_stack, &mut last_was_char);
+
             } else if c == ']' {
-                panic!();
+                return Err(CompileError::Pattern("unopened char class".to_owned()));
             } else if BINARY_OPERATORS.contains(&c) {
 
                 while !operator_stack.is_empty() {
@@ -102,25 +197,29 @@ impl FromStr for Expr {
             } else if UNARY_POSTFIX_OPERATORS.contains(&c) {
 
                 apply_postfix_operator(c, &mut output_queue);
-                last_was_char = false;
+                check_nesting(output_queue.back().unwrap(), limits)?;
+                literal = false;
+                last_was_char = true; // so that the next item is sequenced after the operand
 
             } else if SPECIAL_CHARS.contains(&c) {
 
-                if !output_queue.is_empty() && last_was_char {
-                    operator_stack.push('@'); // "sequence" operator
-                }
-                output_queue.push_back(Expr::Any);
-                last_was_char = true;
+                let any = if unicode { Expr::Any } else { Expr::AnyByte };
+                num_states += nfa::leaf_states(&any);
+                literal = false;
+                push_operand(any, &mut output_queue, &mut operator_stack, &mut last_was_char);
 
             } else { // literal char
 
-                if !output_queue.is_empty() && last_was_char {
-                    operator_stack.push('@'); // "sequence" operator
-                }
-                output_queue.push_back(Expr::Single(c));
-                last_was_char = true;
+                let literal = if c == '\\' { parse_escape(&mut chars)? } else { Literal::Char(c) };
+                let expr = literal.to_expr(unicode);
+                num_states += nfa::leaf_states(&expr);
+                push_operand(expr, &mut output_queue, &mut operator_stack, &mut last_was_char);
 
             }
+
+            if num_states > limits.max_states(literal) {
+                return Err(CompileError::TooManyStates(limits.max_states(literal)));
+            }
         }
 
         while !operator_stack.is_empty() {
@@ -136,8 +235,116 @@ impl FromStr for Expr {
                                                   right));
         }
 
-        output_queue.pop_front().ok_or("output queue empty".to_owned())
+        Ok(output_queue.pop_front().ok_or("output queue empty".to_owned())?)
+    }
+}
+
+// groups and repetitions around the most deeply nested part of expr
+fn nesting_depth(expr: &Expr) -> usize {
+    let mut max = 0;
+    let mut stack = vec![(expr, 0)];
+    while let Some((expr, depth)) = stack.pop() {
+        max = cmp::max(max, depth);
+        match *expr {
+            Expr::Sequence(ref a, ref b) | Expr::Or(ref a, ref b) => {
+                stack.push((a, depth));
+                stack.push((b, depth));
+            },
+            Expr::Group(ref expr) | Expr::Optional(ref expr) |
+            Expr::ZeroOrMore(ref expr) | Expr::OneOrMore(ref expr) => stack.push((expr, depth + 1)),
+            _ => ()
+        }
+    }
+    max
+}
+
+// checked as each group or repetition is added, so that no expression
+// nests much deeper than the limit
+fn check_nesting(expr: &Expr, limits: CompileLimits) -> Result<(), CompileError> {
+    if nesting_depth(expr) > limits.max_nesting_depth() {
+        Err(CompileError::NestingTooDeep(limits.max_nesting_depth()))
+    } else {
+        Ok(())
+    }
+}
+
+// a char written in a pattern, or a value written as `\xHH`
+#[derive(PartialEq,Debug,Clone,Copy)]
+enum Literal {
+    Char(char),
+    Hex(u8)
+}
+
+impl Literal {
+    fn to_expr(self, unicode: bool) -> Expr {
+        match self {
+            Literal::Char(c) => Expr::Single(c),
+            Literal::Hex(value) if unicode || value < 0x80 => Expr::Single(value as char),
+            Literal::Hex(value) => Expr::Byte(value)
+        }
+    }
+
+    // the code point in unicode mode, otherwise the byte
+    fn value(self, unicode: bool) -> Result<u32, String> {
+        match self {
+            Literal::Char(c) if unicode || (c as u32) < 0x80 => Ok(c as u32),
+            Literal::Char(c) => Err(format!("non-ascii char {:?} in byte class", c)),
+            Literal::Hex(value) => Ok(value as u32)
+        }
+    }
+}
+
+fn parse_escape(chars: &mut Chars) -> Result<Literal, String> {
+    match chars.next() {
+        Some('x') => {
+            let digits = chars.by_ref().take(2).collect::<String>();
+            if digits.len() != 2 {
+                return Err("incomplete hex escape".to_owned());
+            }
+            u8::from_str_radix(&digits, 16)
+                .map(Literal::Hex)
+                .map_err(|_| format!("invalid hex escape \\x{}", digits))
+        },
+        Some('n') => Ok(Literal::Char('\n')),
+        Some('t') => Ok(Literal::Char('\t')),
+        Some('r') => Ok(Literal::Char('\r')),
+        Some(c) if METACHARS.contains(&c) => Ok(Literal::Char(c)),
+        Some(c) => Err(format!("unsupported escape \\{}", c)),
+        None => Err("incomplete escape".to_owned())
+    }
+}
+
+// parses the rest of a char class after its opening `[`
+fn parse_class(chars: &mut Chars, unicode: bool, limits: CompileLimits) -> Result<Expr, CompileError> {
+    let mut members = Vec::new();
+
+    loop {
+        let c = chars.next().ok_or("unclosed char class".to_owned())?;
+        if c == ']' {
+            break;
+        }
+
+        let member = if c == '\\' { parse_escape(chars)? } else { Literal::Char(c) };
+        if members.len() + 1 > limits.max_class_size() {
+            return Err(CompileError::ClassTooLarge(limits.max_class_size()));
+        }
+        members.push(member.value(unicode)?);
+    }
+
+    if !unicode && members.iter().any(|&value| value >= 0x80) {
+        Ok(Expr::ByteClass(members.iter().map(|&value| value as u8).collect()))
+    } else {
+        Ok(Expr::Class(members.into_iter().filter_map(char::from_u32).collect()))
+    }
+}
+
+fn push_operand(operand: Expr, output_queue: &mut VecDeque<Expr>,
+                operator_stack: &mut Vec<char>, last_was_char: &mut bool) {
+    if !output_queue.is_empty() && *last_was_char {
+        operator_stack.push('@'); // "sequence" operator
     }
+    output_queue.push_back(operand);
+    *last_was_char = true;
 }
 
 fn pop_infix_operator(operator: char, output_queue: &mut VecDeque<Expr>) {
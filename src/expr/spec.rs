@@ -1,3 +1,4 @@
+use ::limits::{CompileLimits, CompileError};
 use super::Expr;
 
 #[test]
@@ -17,16 +18,16 @@ fn parse_sequence() {
                               Expr::Single('b')),
                "ab".parse::<Expr>().unwrap());
 
-    assert_eq!(Expr::sequence(Expr::Single('a'),
+    assert_eq!(Expr::sequence(Expr::group(Expr::Single('a')),
                               Expr::Single('b')),
                "(a)b".parse::<Expr>().unwrap());
 
-    assert_eq!(Expr::sequence(Expr::or(Expr::Single('a'),Expr::Single('b')),
+    assert_eq!(Expr::sequence(Expr::group(Expr::or(Expr::Single('a'),Expr::Single('b'))),
                               Expr::Single('b')),
                 "(a|b)b".parse::<Expr>().unwrap());
 
-    assert_eq!(Expr::sequence(Expr::Single('a'),
-                              Expr::Single('b')),
+    assert_eq!(Expr::group(Expr::sequence(Expr::group(Expr::Single('a')),
+                                          Expr::Single('b'))),
                "((a)b)".parse::<Expr>().unwrap());
 }
 
@@ -83,30 +84,30 @@ fn parse_combination() {
 
 #[test]
 fn parse_parens() {
-    assert_eq!(Expr::OneOrMore(Box::new(Expr::sequence(Expr::Single('a'),
-                                             Expr::Single('b')))),
+    assert_eq!(Expr::OneOrMore(Box::new(Expr::group(Expr::sequence(Expr::Single('a'),
+                                                                 Expr::Single('b'))))),
                "(ab)+".parse::<Expr>().unwrap());
 
     assert_eq!(
         Expr::sequence(
-            Expr::OneOrMore(Box::new(Expr::sequence(Expr::Single('a'),
-                                          Expr::Single('b')))),
+            Expr::OneOrMore(Box::new(Expr::group(Expr::sequence(Expr::Single('a'),
+                                                              Expr::Single('b'))))),
             Expr::Single('c')),
                "(ab)+c".parse::<Expr>().unwrap());
     
-    assert_eq!(Expr::Single('a'),
+    assert_eq!(Expr::group(Expr::Single('a')),
         "(a)".parse::<Expr>().unwrap());
 
     assert_eq!(Expr::Single('a'),
         "()a".parse::<Expr>().unwrap());
 
-    assert_eq!(Expr::Single('a'),
+    assert_eq!(Expr::group(Expr::group(Expr::Single('a'))),
         "((a))".parse::<Expr>().unwrap());
 }
 
 #[test]
 fn parse_parens_with_or() {
-    assert_eq!(Expr::Or(Box::new(Expr::Single('a')), Box::new(Expr::Single('b'))),
+    assert_eq!(Expr::group(Expr::Or(Box::new(Expr::Single('a')), Box::new(Expr::Single('b')))),
                "(a|b)".parse::<Expr>().unwrap());
 }
 
@@ -124,18 +125,18 @@ fn parse_parens_with_kleene_star() {
 #[test]
 fn parse_complex() {
     assert_eq!(Expr::one_or_more(
-            Expr::sequence(
-            Expr::Single('a'),
-            Expr::Single('b'))),
+            Expr::group(Expr::sequence(
+            Expr::group(Expr::Single('a')),
+            Expr::Single('b')))),
             "((a)b)+".parse::<Expr>().unwrap());
 
     assert_eq!(
         Expr::one_or_more(
-            Expr::sequence(
-                Expr::or(
+            Expr::group(Expr::sequence(
+                Expr::group(Expr::or(
                     Expr::Single('a'),
-                    Expr::Single('b')),
-                Expr::Single('b'))),
+                    Expr::Single('b'))),
+                Expr::Single('b')))),
             "((a|b)b)+".parse::<Expr>().unwrap());
 }
 
@@ -154,3 +155,138 @@ fn parse_char_class() {
         "[ab]+".parse::<Expr>().unwrap());
 }
 
+
+#[test]
+fn parse_postfix_operator_on_group() {
+    assert_eq!(Expr::zero_or_more(
+            Expr::group(Expr::sequence(
+                Expr::zero_or_more(Expr::Single('a')),
+                Expr::zero_or_more(Expr::Single('b'))))),
+        "(a*b*)*".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_or_after_postfix_operator() {
+    assert_eq!(Expr::or(
+            Expr::sequence(
+                Expr::zero_or_more(Expr::Single('a')),
+                Expr::Single('b')),
+            Expr::Single('c')),
+        "a*b|c".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_sequence_after_postfix_operator() {
+    assert_eq!(Expr::sequence(Expr::one_or_more(Expr::Single('a')), Expr::Single('b')),
+               "a+b".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_sequence_after_group() {
+    assert_eq!(Expr::sequence(Expr::group(Expr::sequence(Expr::Single('a'), Expr::Single('b'))), Expr::Single('c')),
+               "(ab)c".parse::<Expr>().unwrap());
+    assert_eq!(Expr::sequence(Expr::group(Expr::Single('a')), Expr::group(Expr::Single('b'))),
+               "(a)(b)".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_escaped_metachars() {
+    assert_eq!(Expr::sequence(Expr::Single('a'), Expr::Single('+')),
+               "a\\+".parse::<Expr>().unwrap());
+    assert_eq!(Expr::sequence(Expr::Single('.'), Expr::Single('\\')),
+               "\\.\\\\".parse::<Expr>().unwrap());
+    assert!("a\\".parse::<Expr>().is_err());
+}
+
+#[test]
+fn parse_whitespace_escapes() {
+    assert_eq!(Expr::Class(vec!['\n', '\t', '\r']),
+               "[\\n\\t\\r]".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn rejects_unsupported_escapes() {
+    assert_eq!(Err("unsupported escape \\d".to_owned()), "a\\d".parse::<Expr>());
+    assert!("[\\w]".parse::<Expr>().is_err());
+}
+
+#[test]
+fn parse_hex_escape() {
+    assert_eq!(Expr::Single('A'), "\\x41".parse::<Expr>().unwrap());
+    assert_eq!(Expr::Single('\u{FF}'), "\\xFF".parse::<Expr>().unwrap());
+    assert!("\\xG1".parse::<Expr>().is_err());
+    assert!("\\x4".parse::<Expr>().is_err());
+}
+
+#[test]
+fn parse_char_class_escapes() {
+    assert_eq!(Expr::Class(vec!['a','-','c']),
+        "[a-c]".parse::<Expr>().unwrap());
+    assert_eq!(Expr::Class(vec![']','A','.']),
+        "[\\]\\x41\\.]".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_unclosed_char_class() {
+    assert!("a[b".parse::<Expr>().is_err());
+    assert!("a]".parse::<Expr>().is_err());
+}
+
+#[test]
+fn parse_char_class_in_sequence() {
+    assert_eq!(Expr::or(
+            Expr::sequence(Expr::Single('a'), Expr::Class(vec!['b'])),
+            Expr::Single('c')),
+        "a[b]|c".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_bytes_mode() {
+    assert_eq!(Expr::sequence(Expr::AnyByte, Expr::Byte(0xFF)),
+        "(?-u).\\xFF".parse::<Expr>().unwrap());
+    assert_eq!(Expr::Single('A'),
+        "(?-u)\\x41".parse::<Expr>().unwrap());
+    assert_eq!(Expr::ByteClass(vec![b'a', 0x80, 0x81]),
+        "(?-u)[a\\x80\\x81]".parse::<Expr>().unwrap());
+    assert_eq!(Expr::Class(vec!['a', 'b']),
+        "(?-u)[ab]".parse::<Expr>().unwrap());
+    assert!("(?-u)[é]".parse::<Expr>().is_err());
+}
+
+#[test]
+fn detects_exprs_matching_invalid_utf8() {
+    assert!(!"a.[é]\\xFF".parse::<Expr>().unwrap().can_match_invalid_utf8());
+    assert!(!"(?-u)a[bc]\\x7F".parse::<Expr>().unwrap().can_match_invalid_utf8());
+    assert!("(?-u)a.".parse::<Expr>().unwrap().can_match_invalid_utf8());
+    assert!("(?-u)(a|\\xFF)*".parse::<Expr>().unwrap().can_match_invalid_utf8());
+}
+
+#[test]
+fn parse_groups_in_order_of_open_paren() {
+    assert_eq!(Expr::sequence(
+            Expr::group(Expr::sequence(Expr::Single('a'), Expr::group(Expr::Single('b')))),
+            Expr::group(Expr::Single('c'))),
+        "(a(b))(c)".parse::<Expr>().unwrap());
+}
+
+#[test]
+fn parse_within_limits() {
+    let limits = CompileLimits::new().nesting_depth(3).states(4).class_size(3);
+    assert!(Expr::parse("((a*))[abc]", limits).is_ok());
+    assert_eq!(Err(CompileError::NestingTooDeep(3)), Expr::parse("((a*)?)", limits));
+    assert_eq!(Err(CompileError::NestingTooDeep(3)), Expr::parse("a+*?+", limits));
+    assert_eq!(Err(CompileError::TooManyStates(4)), Expr::parse("ab*cde", limits));
+    assert_eq!(Err(CompileError::TooManyStates(4)), Expr::parse("a.", limits));
+    assert_eq!(Err(CompileError::TooManyStates(4)), Expr::parse("abcde|fghij", limits));
+    assert_eq!(Err(CompileError::ClassTooLarge(3)), Expr::parse("[abcd]", limits));
+
+    assert_eq!(Err(CompileError::Pattern("incomplete escape".to_owned())), Expr::parse("a\\", limits));
+    assert_eq!(Err("unopened char class".to_owned()), "a]".parse::<Expr>());
+}
+
+#[test]
+fn drops_long_sequences() {
+    let expr = "ab".repeat(200000).parse::<Expr>().unwrap();
+    assert!(expr.is_literal());
+    drop(expr);
+}
@@ -0,0 +1,101 @@
+// the binary format of compiled automata: a four byte magic naming the
+// kind of automaton, the format version, then the automaton. Numbers are
+// little endian u32s
+
+#[cfg(test)] mod spec;
+
+pub const VERSION: usize = 1;
+
+pub fn write_header(out: &mut Vec<u8>, magic: &[u8; 4]) {
+    out.extend_from_slice(magic);
+    write_u32(out, VERSION);
+}
+
+pub fn write_u32(out: &mut Vec<u8>, value: usize) {
+    assert!(value <= u32::MAX as usize, "{} does not fit the format", value);
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+// a length, then the bytes, e.g. a literal or a nested automaton
+pub fn write_block(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+pub fn read_u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+// reads the parts of a serialized automaton in turn, failing on bytes that
+// run out or make no sense
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], magic: &[u8; 4]) -> Result<Reader<'a>, String> {
+        let mut reader = Reader {
+            bytes,
+            position: 0
+        };
+        if reader.bytes(4)? != &magic[..] {
+            return Err(format!("not a serialized {}", String::from_utf8_lossy(magic)));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported format version {}", version));
+        }
+        Ok(reader)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < len {
+            return Err("unexpected end of input".to_owned());
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<usize, String> {
+        Ok(read_u32_at(self.bytes(4)?, 0) as usize)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn block(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()?;
+        self.bytes(len)
+    }
+
+    // a u32 that must be less than bound
+    pub fn index(&mut self, bound: usize, what: &str) -> Result<usize, String> {
+        let index = self.u32()?;
+        if index >= bound {
+            return Err(format!("{} {} out of range", what, index));
+        }
+        Ok(index)
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        if self.position != self.bytes.len() {
+            return Err("trailing bytes after automaton".to_owned());
+        }
+        Ok(())
+    }
+}
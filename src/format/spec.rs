@@ -0,0 +1,42 @@
+use super::{Reader, write_header, write_u32, write_u64, write_block, VERSION};
+
+#[test]
+fn reads_what_is_written() {
+    let mut out = Vec::new();
+    write_header(&mut out, b"TEST");
+    write_u32(&mut out, 7);
+    out.push(3);
+    write_u64(&mut out, 1 << 40);
+    write_block(&mut out, b"abc");
+
+    let mut reader = Reader::new(&out, b"TEST").unwrap();
+    assert_eq!(Ok(7), reader.u32());
+    assert_eq!(Ok(3), reader.u8());
+    assert_eq!(Ok(1 << 40), reader.u64());
+    assert_eq!(Ok(&b"abc"[..]), reader.block());
+    assert_eq!(Ok(()), reader.finish());
+}
+
+#[test]
+fn rejects_malformed_input() {
+    let mut out = Vec::new();
+    write_header(&mut out, b"TEST");
+    assert_eq!(Some("not a serialized ABCD".to_owned()), Reader::new(&out, b"ABCD").err());
+    assert_eq!(Some("unexpected end of input".to_owned()), Reader::new(&out[..6], b"TEST").err());
+
+    let mut reader = Reader::new(&out, b"TEST").unwrap();
+    assert_eq!(Err("unexpected end of input".to_owned()), reader.u32());
+
+    write_u32(&mut out, 5);
+    let mut reader = Reader::new(&out, b"TEST").unwrap();
+    assert_eq!(Err("state 5 out of range".to_owned()), reader.index(5, "state"));
+
+    let mut out = b"TEST".to_vec();
+    write_u32(&mut out, VERSION + 1);
+    assert_eq!(Some(format!("unsupported format version {}", VERSION + 1)), Reader::new(&out, b"TEST").err());
+
+    let mut out = Vec::new();
+    write_header(&mut out, b"TEST");
+    out.push(0);
+    assert_eq!(Err("trailing bytes after automaton".to_owned()), Reader::new(&out, b"TEST").unwrap().finish());
+}
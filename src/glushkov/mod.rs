@@ -0,0 +1,423 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+
+use ::expr::Expr;
+use ::nfa::{self, Condition, Transition};
+use ::format::{self, Reader};
+
+#[cfg(test)] mod spec;
+
+// the bits of a state set
+const MAX_POSITIONS: usize = 64;
+
+// the position automaton of a pattern, with one state per byte the pattern
+// reads. Every transition into a position reads that position's byte, so
+// a set of positions fits in a u64 and moves on a byte in a few table
+// lookups
+#[derive(PartialEq,Debug,Clone)]
+pub struct Glushkov {
+    nullable: bool,
+    first: u64,
+    last: u64,
+    masks: Vec<u64>, // positions matching each byte
+    follow: Vec<Vec<u64>> // positions following each value of each byte of a state
+}
+
+impl Glushkov {
+    // none for patterns with alternation or with more than 64 positions
+    pub fn from_expr(expr: &Expr) -> Option<Glushkov> {
+        if Self::count_positions(expr)? > MAX_POSITIONS {
+            return None;
+        }
+        let positions = Positions::from_expr(expr, usize::MAX)?;
+
+        let bits = |transitions: &[Transition]| transitions.iter().fold(0, |bits, transition| match *transition {
+            Transition::Id(position) => bits | 1 << position,
+            _ => bits
+        });
+        let follow = positions.follow.iter().map(|follow| bits(follow)).collect::<Vec<u64>>();
+        let last = positions.follow.iter()
+            .enumerate()
+            .filter(|&(_, follow)| follow.contains(&Transition::End))
+            .fold(0, |last, (position, _)| last | 1 << position);
+
+        let mut masks = vec![0; 256];
+        for (position, condition) in positions.conditions.iter().enumerate() {
+            for (byte, mask) in masks.iter_mut().enumerate() {
+                if condition.matches(byte as u8) {
+                    *mask |= 1 << position;
+                }
+            }
+        }
+
+        // the positions following a state are looked up a byte at a time
+        let tables = (0..follow.len().div_ceil(8))
+            .map(|chunk| {
+                (0..256)
+                    .map(|bits| {
+                        (0..8)
+                            .filter(|&i| bits & (1 << i) != 0 && chunk * 8 + i < follow.len())
+                            .fold(0, |next, i| next | follow[chunk * 8 + i])
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Some(Glushkov {
+            nullable: positions.first.contains(&Transition::End),
+            first: bits(&positions.first),
+            last,
+            masks,
+            follow: tables
+        })
+    }
+
+    // how many positions expr has, counted until there are more than fit
+    // in a state set. None for patterns with alternation, which includes a
+    // utf-8 `.` or non-ascii class as they try each byte sequence
+    fn count_positions(expr: &Expr) -> Option<usize> {
+        match *expr {
+            Expr::Any | Expr::Or(_, _) => None,
+            Expr::Class(ref chars) if !chars.iter().all(|c| c.is_ascii()) => None,
+            Expr::Sequence(..) => {
+                let mut count = 0;
+                let mut rest = expr;
+                while let Expr::Sequence(ref part, ref next) = *rest {
+                    count += Self::count_positions(part)?;
+                    if count > MAX_POSITIONS {
+                        return Some(count);
+                    }
+                    rest = next;
+                }
+                Some(count + Self::count_positions(rest)?)
+            },
+            Expr::Group(ref expr) | Expr::Optional(ref expr) |
+            Expr::ZeroOrMore(ref expr) | Expr::OneOrMore(ref expr) => Self::count_positions(expr),
+            _ => Some(nfa::byte_sequences(expr)[0].len())
+        }
+    }
+
+    // the bit sets as little endian u64s, the tables in full
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.push(self.nullable as u8);
+        format::write_u64(out, self.first);
+        format::write_u64(out, self.last);
+        format::write_u32(out, self.follow.len());
+        for &mask in self.masks.iter().chain(self.follow.iter().flatten()) {
+            format::write_u64(out, mask);
+        }
+    }
+
+    pub fn read(reader: &mut Reader) -> Result<Glushkov, String> {
+        let nullable = match reader.u8()? {
+            0 => false,
+            1 => true,
+            flag => return Err(format!("invalid nullable flag {}", flag))
+        };
+        let first = reader.u64()?;
+        let last = reader.u64()?;
+        let chunks = reader.u32()?;
+        if chunks > MAX_POSITIONS / 8 {
+            return Err(format!("{} position tables, at most {} fit", chunks, MAX_POSITIONS / 8));
+        }
+
+        let mut table = || (0..256).map(|_| reader.u64()).collect::<Result<Vec<_>, _>>();
+        let masks = table()?;
+        let follow = (0..chunks).map(|_| table()).collect::<Result<_, _>>()?;
+        Ok(Glushkov {
+            nullable,
+            first,
+            last,
+            masks,
+            follow
+        })
+    }
+
+    fn next(&self, state: u64) -> u64 {
+        self.follow.iter()
+            .enumerate()
+            .fold(0, |next, (chunk, table)| next | table[(state >> (8 * chunk)) as usize & 0xFF])
+    }
+
+    // whether a match starts at the start of text
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        if self.nullable {
+            return true;
+        }
+
+        let mut reachable = self.first;
+        for &byte in text {
+            let state = reachable & self.masks[byte as usize];
+            if state & self.last != 0 {
+                return true;
+            }
+            if state == 0 {
+                return false;
+            }
+            reachable = self.next(state);
+        }
+
+        false
+    }
+}
+
+// the states of the Thompson NFA of an expression that a search passes
+// without reading a byte, named by the subexpression they belong to
+#[derive(PartialEq,Eq,Hash,Debug,Clone,Copy)]
+enum Target {
+    Entry(usize), // the start of a subexpression
+    Close(usize), // the end of a capture group
+    Repeat(usize), // the split after the inner expression of a `+`
+    End
+}
+
+// a subexpression, with what is entered after it
+struct Node<'e> {
+    expr: &'e Expr,
+    children: Vec<usize>, // the parts of a sequence, alternatives of an or, or the inner expression
+    nullable: bool,
+    key: usize, // the least priority of the positions it can start with
+    after: Target,
+    after_key: usize,
+    starts: Vec<usize>, // of a leaf, the positions it starts with in the order they are tried
+    ends: Vec<usize> // of a leaf, the positions it ends with
+}
+
+// the positions of an expression, one for each byte it reads, numbered in
+// pattern order. The positions a match starts with and those following
+// each position are listed in the order a search of the Thompson NFA tries
+// them, with Transition::End for the end of a match
+#[derive(PartialEq,Debug,Clone)]
+pub struct Positions {
+    pub conditions: Vec<Condition>,
+    pub first: Vec<Transition>,
+    pub follow: Vec<Vec<Transition>>
+}
+
+impl Positions {
+    // none when there are more than limit transitions from the positions,
+    // as they can grow with the square of the number of positions
+    pub fn from_expr(expr: &Expr, limit: usize) -> Option<Positions> {
+        let mut conditions = Vec::new();
+        let mut nodes = Vec::new();
+        add_node(expr, &mut nodes, &mut conditions);
+        summarize(&mut nodes);
+
+        // each byte of a leaf but its last is followed by the next, and
+        // the ends of alternatives share what follows them
+        let mut follow = (1..conditions.len() + 1).map(|next| vec![Transition::Id(next)]).collect::<Vec<_>>();
+        let mut transitions = follow.len();
+        let mut closures = HashMap::new();
+        let mut visited = vec![false; 3 * nodes.len()];
+        for node in &nodes {
+            for &end in &node.ends {
+                let closure = closures.entry(node.after).or_insert_with(|| closure(&nodes, node.after, &mut visited));
+                transitions = transitions - 1 + closure.len(); // in place of the one to the next byte
+                if transitions > limit {
+                    return None;
+                }
+                follow[end] = closure.clone();
+            }
+        }
+
+        Some(Positions {
+            first: closure(&nodes, entry(&nodes, 0), &mut visited),
+            conditions,
+            follow
+        })
+    }
+}
+
+// adds the nodes of expr in pattern order, after the node of expr itself,
+// and the conditions of its positions
+fn add_node<'e>(expr: &'e Expr, nodes: &mut Vec<Node<'e>>, conditions: &mut Vec<Condition>) -> usize {
+    let id = nodes.len();
+    nodes.push(Node {
+        expr,
+        children: Vec::new(),
+        nullable: false,
+        key: usize::MAX,
+        after: Target::End,
+        after_key: usize::MAX,
+        starts: Vec::new(),
+        ends: Vec::new()
+    });
+
+    let children = match *expr {
+        Expr::Sequence(..) => {
+            // sequences nest to the right, alternatives to the left
+            let mut parts = Vec::new();
+            let mut rest = expr;
+            while let Expr::Sequence(ref part, ref next) = *rest {
+                parts.push(&**part);
+                rest = next;
+            }
+            parts.push(rest);
+            parts.into_iter().map(|part| add_node(part, nodes, conditions)).collect()
+        },
+        Expr::Or(..) => {
+            let mut alternatives = Vec::new();
+            let mut first = expr;
+            while let Expr::Or(ref left, ref right) = *first {
+                alternatives.push(&**right);
+                first = left;
+            }
+            alternatives.push(first);
+            alternatives.into_iter().rev().map(|alternative| add_node(alternative, nodes, conditions)).collect()
+        },
+        Expr::Group(ref inner) | Expr::Optional(ref inner) |
+        Expr::ZeroOrMore(ref inner) | Expr::OneOrMore(ref inner) => vec![add_node(inner, nodes, conditions)],
+        _ => {
+            // a split to each sequence of bytes in turn, the least keyed first
+            let mut starts = Vec::new();
+            for bytes in nfa::byte_sequences(expr) {
+                starts.push((conditions.len(), bytes[0].priority()));
+                conditions.extend(bytes);
+                nodes[id].ends.push(conditions.len() - 1);
+            }
+
+            let (last, mut key) = starts.pop().unwrap();
+            let mut order = VecDeque::new();
+            order.push_back(last);
+            for (start, priority) in starts.into_iter().rev() {
+                if key < priority {
+                    order.push_back(start);
+                } else {
+                    order.push_front(start);
+                }
+                key = cmp::min(key, priority);
+            }
+            nodes[id].starts = order.into_iter().collect();
+            nodes[id].key = key;
+            Vec::new()
+        }
+    };
+    nodes[id].children = children;
+    id
+}
+
+// works out which nodes match empty text and their keys from their
+// children, then what follows each node from its parent
+fn summarize(nodes: &mut [Node]) {
+    for id in (0..nodes.len()).rev() {
+        let (nullable, key) = {
+            let node = &nodes[id];
+            let children = node.children.iter().map(|&child| &nodes[child]);
+            match *node.expr {
+                Expr::Sequence(..) => children.rev().fold((true, usize::MAX), |(nullable, key), part| {
+                    (nullable && part.nullable, if part.nullable { cmp::min(part.key, key) } else { part.key })
+                }),
+                Expr::Or(..) => children.fold((false, usize::MAX), |(nullable, key), alternative| {
+                    (nullable || alternative.nullable, cmp::min(key, alternative.key))
+                }),
+                Expr::Group(_) | Expr::OneOrMore(_) => (nodes[node.children[0]].nullable, nodes[node.children[0]].key),
+                Expr::Optional(_) | Expr::ZeroOrMore(_) => (true, nodes[node.children[0]].key),
+                _ => (false, node.key)
+            }
+        };
+        nodes[id].nullable = nullable;
+        nodes[id].key = key;
+    }
+
+    for id in 0..nodes.len() {
+        // the parts of a sequence are followed by the one after them
+        let children = nodes[id].children.clone();
+        for (i, &child) in children.iter().enumerate().rev() {
+            // the next part is keyed before the parts inside it that it
+            // starts at, as the key is the same
+            let (after, after_key) = match *nodes[id].expr {
+                Expr::Sequence(..) if i + 1 < children.len() => {
+                    (entry(nodes, children[i + 1]), key(nodes, Target::Entry(children[i + 1])))
+                },
+                Expr::Group(_) => (Target::Close(id), key(nodes, Target::Close(id))),
+                Expr::ZeroOrMore(_) => (Target::Entry(id), key(nodes, Target::Entry(id))),
+                Expr::OneOrMore(_) => (Target::Repeat(id), key(nodes, Target::Repeat(id))),
+                _ => (nodes[id].after, nodes[id].after_key)
+            };
+            nodes[child].after = after;
+            nodes[child].after_key = after_key;
+        }
+    }
+}
+
+// the state a node starts at
+fn entry(nodes: &[Node], id: usize) -> Target {
+    match *nodes[id].expr {
+        Expr::Sequence(..) | Expr::OneOrMore(_) => entry(nodes, nodes[id].children[0]),
+        _ => Target::Entry(id)
+    }
+}
+
+// the least priority of the positions reached from target without reading
+// a byte, as the Thompson NFA keys its states
+fn key(nodes: &[Node], target: Target) -> usize {
+    match target {
+        Target::Entry(id) if nodes[id].nullable => cmp::min(nodes[id].key, nodes[id].after_key),
+        Target::Entry(id) => nodes[id].key,
+        Target::Close(id) => nodes[id].after_key,
+        Target::Repeat(id) => cmp::min(nodes[id].key, nodes[id].after_key),
+        Target::End => usize::MAX
+    }
+}
+
+// the positions and ends reached from target without reading a byte, in
+// the order a search of the Thompson NFA visits them, each state once.
+// Clears the visited marks it sets
+fn closure(nodes: &[Node], target: Target, visited: &mut [bool]) -> Vec<Transition> {
+    let mut result = Vec::new();
+    let mut touched = Vec::new();
+
+    let mut stack = vec![target];
+    while let Some(target) = stack.pop() {
+        let (id, state) = match target {
+            Target::Entry(id) => (id, 3 * id),
+            Target::Close(id) => (id, 3 * id + 1),
+            Target::Repeat(id) => (id, 3 * id + 2),
+            Target::End => {
+                if !result.contains(&Transition::End) {
+                    result.push(Transition::End);
+                }
+                continue;
+            }
+        };
+
+        if visited[state] {
+            continue;
+        }
+        visited[state] = true;
+        touched.push(state);
+
+        let node = &nodes[id];
+        let split = |out1: Target, out2: Target| {
+            if key(nodes, out2) < key(nodes, out1) { vec![out1, out2] } else { vec![out2, out1] }
+        };
+        match (target, node.expr) {
+            (Target::Close(_), _) => stack.push(node.after),
+            (Target::Repeat(_), _) | (_, &Expr::Optional(_)) | (_, &Expr::ZeroOrMore(_)) => {
+                stack.extend(split(entry(nodes, node.children[0]), node.after))
+            },
+            (_, &Expr::Group(_)) => stack.push(entry(nodes, node.children[0])),
+            (_, &Expr::Or(..)) => {
+                // a split between the alternatives so far and the next one
+                let mut order = VecDeque::new();
+                let mut key = usize::MAX;
+                for &alternative in &node.children {
+                    let alternative_key = self::key(nodes, entry(nodes, alternative));
+                    if order.is_empty() || key <= alternative_key {
+                        order.push_back(alternative);
+                    } else {
+                        order.push_front(alternative);
+                    }
+                    key = cmp::min(key, alternative_key);
+                }
+                stack.extend(order.into_iter().rev().map(|alternative| entry(nodes, alternative)));
+            },
+            _ => result.extend(node.starts.iter().map(|&start| Transition::Id(start)))
+        }
+    }
+
+    for state in touched {
+        visited[state] = false;
+    }
+    result
+}
@@ -0,0 +1,78 @@
+use ::expr::Expr;
+use ::nfa::{NFA, Condition, Transition};
+use ::matcher::{self, Matcher};
+use super::{Glushkov, Positions};
+
+fn glushkov(pattern: &str) -> Option<Glushkov> {
+    Glushkov::from_expr(&pattern.parse::<Expr>().unwrap())
+}
+
+#[test]
+fn matches_sequences() {
+    let glushkov = glushkov("ab?c+d*").unwrap();
+    assert!(glushkov.is_match(b"ac"));
+    assert!(glushkov.is_match(b"abccd"));
+    assert!(glushkov.is_match(b"acx"));
+    assert!(!glushkov.is_match(b"ab"));
+    assert!(!glushkov.is_match(b"abb"));
+    assert!(!glushkov.is_match(b""));
+}
+
+#[test]
+fn matches_repeated_groups() {
+    let glushkov = glushkov("x(ab)+y").unwrap();
+    assert!(glushkov.is_match(b"xababy"));
+    assert!(!glushkov.is_match(b"xy"));
+    assert!(!glushkov.is_match(b"xabay"));
+}
+
+#[test]
+fn matches_nullable_patterns() {
+    assert!(glushkov("a*").unwrap().is_match(b""));
+    assert!(glushkov("(ab)?").unwrap().is_match(b"x"));
+}
+
+#[test]
+fn matches_multibyte_chars() {
+    let glushkov = glushkov("é+[0123456789]").unwrap();
+    assert!(glushkov.is_match("éé1".as_bytes()));
+    assert!(!glushkov.is_match("é".as_bytes()));
+}
+
+#[test]
+fn rejects_alternation_and_long_patterns() {
+    assert!(glushkov("a|b").is_none());
+    assert!(glushkov("a.b").is_none());
+    assert!(glushkov("[aé]").is_none());
+    assert!(glushkov(&"a".repeat(65)).is_none());
+    assert!(glushkov(&"a".repeat(64)).is_some());
+}
+
+#[test]
+fn agrees_with_matcher() {
+    let patterns = ["a?a?aa", "(abc)+c*", "[ab]+c", "x[0123456789]*-?[0123456789]+", "(?-u)\\xFF.a", "((a)b*)*c", "é?a+"];
+    let texts = ["", "aa", "a", "abcabcc", "abbac", "x12-3", "x-", "\u{FF}za", "ababbc", "éaa", "c"];
+
+    let mut cache = matcher::Cache::new();
+    for pattern in patterns.iter() {
+        let expr = pattern.parse::<Expr>().unwrap();
+        let nfa = NFA::from_expr(&expr);
+        let glushkov = Glushkov::from_expr(&expr).unwrap();
+        for text in texts.iter() {
+            assert_eq!(Matcher::new(&nfa, *text).run(&mut cache).is_some(), glushkov.is_match(text.as_bytes()),
+                       "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn lists_positions_in_the_order_they_are_tried() {
+    // after `a`, repeating is tried before `b` as its key is less
+    let positions = Positions::from_expr(&"(ab?)*".parse::<Expr>().unwrap(), usize::MAX).unwrap();
+    assert_eq!(vec![Condition::one('a'), Condition::one('b')], positions.conditions);
+    assert_eq!(vec![Transition::Id(0), Transition::End], positions.first);
+    assert_eq!(vec![vec![Transition::Id(0), Transition::End, Transition::Id(1)], vec![Transition::Id(0), Transition::End]],
+               positions.follow);
+
+    assert_eq!(None, Positions::from_expr(&"a?".repeat(10).parse::<Expr>().unwrap(), 20));
+}
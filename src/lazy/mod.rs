@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::mem;
+
+use ::nfa::{NFA, Closure};
+use ::limits::{Budget, MatchError};
+
+#[cfg(test)] mod spec;
+
+pub const DEFAULT_CACHE_SIZE: usize = 1 << 20; // bytes
+
+// a transition that has not been computed yet
+const UNKNOWN: usize = usize::MAX;
+
+// once the cache has been flushed during a search, building a state for
+// fewer than this many scanned bytes means the cache is thrashing
+const MIN_BYTES_PER_STATE: usize = 10;
+
+#[derive(PartialEq,Debug)]
+pub struct CacheThrashed;
+
+// why a search stopped before it could answer
+#[derive(PartialEq,Debug)]
+pub enum Interrupted {
+    CacheThrashed, // an NFA engine can finish the search instead
+    Exceeded(MatchError)
+}
+
+impl From<CacheThrashed> for Interrupted {
+    fn from(_: CacheThrashed) -> Interrupted {
+        Interrupted::CacheThrashed
+    }
+}
+
+impl From<MatchError> for Interrupted {
+    fn from(error: MatchError) -> Interrupted {
+        Interrupted::Exceeded(error)
+    }
+}
+
+// DFA states built so far for one NFA, each one a set of NFA states
+#[derive(Debug)]
+pub struct Cache {
+    states: Vec<Closure>,
+    transitions: Vec<usize>, // one entry per byte class per state
+    ids: HashMap<Closure, usize>,
+    start: Option<usize>,
+    memory: usize,
+    flushes: usize,
+    search_flushes: usize, // flushes during the current search
+    scanned: usize // bytes scanned since the last flush of the current search
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            states: Vec::new(),
+            transitions: Vec::new(),
+            ids: HashMap::new(),
+            start: None,
+            memory: 0,
+            flushes: 0,
+            search_flushes: 0,
+            scanned: 0
+        }
+    }
+
+    pub fn closure(&self, state: usize) -> &Closure {
+        &self.states[state]
+    }
+
+    fn clear(&mut self) {
+        self.states.clear();
+        self.transitions.clear();
+        self.ids.clear();
+        self.start = None;
+        self.memory = 0;
+    }
+
+    fn intern(&mut self, closure: Closure, stride: usize) -> usize {
+        if let Some(&id) = self.ids.get(&closure) {
+            return id;
+        }
+
+        let id = self.states.len();
+        self.memory += Self::state_memory(&closure, stride);
+        self.ids.insert(closure.clone(), id);
+        self.states.push(closure);
+        self.transitions.extend((0..stride).map(|_| UNKNOWN));
+
+        id
+    }
+
+    fn state_memory(closure: &Closure, stride: usize) -> usize {
+        // transition row, plus the closure stored in both `states` and `ids`
+        stride * mem::size_of::<usize>() +
+            2 * (mem::size_of::<Closure>() + closure.states.len() * mem::size_of::<usize>())
+    }
+}
+
+// a DFA whose states are built from the NFA on demand and cached
+pub struct LazyDFA<'a> {
+    nfa: &'a NFA,
+    leftmost_first: bool, // states are built with NFA::leftmost_step
+    cache_size: usize
+}
+
+impl<'a> LazyDFA<'a> {
+    pub fn new(nfa: &'a NFA, cache_size: usize) -> LazyDFA<'a> {
+        LazyDFA {
+            nfa,
+            leftmost_first: false,
+            cache_size
+        }
+    }
+
+    // a DFA for unanchored searches that find the match the matcher
+    // prefers, see leftmost_match_end
+    pub fn leftmost_first(nfa: &'a NFA, cache_size: usize) -> LazyDFA<'a> {
+        LazyDFA {
+            nfa,
+            leftmost_first: true,
+            cache_size
+        }
+    }
+
+    pub fn is_match(&self, cache: &mut Cache, text: &[u8], budget: &mut Budget) -> Result<bool, Interrupted> {
+        self.shortest_match(cache, text, budget).map(|end| end.is_some())
+    }
+
+    // return optional end offset of the shortest match at the start of
+    // text. A step is a state entered
+    pub fn shortest_match(&self, cache: &mut Cache, text: &[u8], budget: &mut Budget) -> Result<Option<usize>, Interrupted> {
+        let mut current = self.start_state(cache)?;
+
+        for (i, &byte) in text.iter().enumerate() {
+            let closure = cache.closure(current);
+            if closure.is_match() {
+                return Ok(Some(i));
+            }
+            if closure.states.is_empty() { // dead state, no match is possible
+                return Ok(None);
+            }
+
+            budget.step()?;
+            budget.read(i)?;
+            current = self.next_state(cache, current, byte)?;
+        }
+
+        if cache.closure(current).is_match() {
+            Ok(Some(text.len()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // the end of the leftmost match the matcher prefers, for DFAs made with
+    // leftmost_first. Each match that ends is preferred to the ones before
+    // it, so the search goes on until no thread is left
+    pub fn leftmost_match_end(&self, cache: &mut Cache, text: &[u8], budget: &mut Budget) -> Result<Option<usize>, Interrupted> {
+        let mut current = self.start_state(cache)?;
+        let mut end = None;
+
+        for (i, &byte) in text.iter().enumerate() {
+            let closure = cache.closure(current);
+            if closure.is_match() {
+                end = Some(i);
+            }
+            if closure.states.is_empty() {
+                return Ok(end);
+            }
+
+            budget.step()?;
+            budget.read(i)?;
+            current = self.next_state(cache, current, byte)?;
+        }
+
+        if cache.closure(current).is_match() {
+            end = Some(text.len());
+        }
+        Ok(end)
+    }
+
+    // begins a new search, returning the id of the start state
+    pub fn start_state(&self, cache: &mut Cache) -> Result<usize, CacheThrashed> {
+        cache.scanned = 0;
+        cache.search_flushes = 0;
+
+        if let Some(start) = cache.start {
+            return Ok(start);
+        }
+
+        let start = if self.leftmost_first { self.nfa.leftmost_start() } else { self.nfa.start_closure() };
+        if !cache.ids.contains_key(&start) {
+            self.make_room(cache, &start)?;
+        }
+        let start = cache.intern(start, self.nfa.byte_classes().num_classes());
+        cache.start = Some(start);
+        Ok(start)
+    }
+
+    // the state entered by consuming `byte` from `current`. State ids are
+    // only valid until the next call, which may flush the cache
+    pub fn next_state(&self, cache: &mut Cache, current: usize, byte: u8) -> Result<usize, CacheThrashed> {
+        let classes = self.nfa.byte_classes();
+        let stride = classes.num_classes();
+        let class = classes.get(byte);
+        let mut current = current;
+        cache.scanned += 1;
+
+        let mut next = cache.transitions[current * stride + class];
+        if next == UNKNOWN {
+            let states = &cache.states[current].states;
+            let closure = if self.leftmost_first { self.nfa.leftmost_step(states, byte) } else { self.nfa.step(states, byte) };
+            next = match cache.ids.get(&closure) {
+                Some(&id) => id,
+                None => {
+                    let left = cache.states[current].clone();
+                    if self.make_room(cache, &closure)? {
+                        // the state being left was flushed along with the rest
+                        current = cache.intern(left, stride);
+                    }
+                    cache.intern(closure, stride)
+                }
+            };
+            cache.transitions[current * stride + class] = next;
+        }
+
+        Ok(next)
+    }
+
+    // flushes the cache if `closure` does not fit in it, returning whether it was flushed
+    fn make_room(&self, cache: &mut Cache, closure: &Closure) -> Result<bool, CacheThrashed> {
+        let memory = Cache::state_memory(closure, self.nfa.byte_classes().num_classes());
+        if cache.states.is_empty() || cache.memory + memory <= self.cache_size {
+            return Ok(false);
+        }
+
+        if cache.search_flushes > 0 && cache.scanned < MIN_BYTES_PER_STATE * cache.states.len() {
+            return Err(CacheThrashed);
+        }
+
+        cache.clear();
+        cache.flushes += 1;
+        cache.search_flushes += 1;
+        cache.scanned = 0;
+        Ok(true)
+    }
+}
@@ -0,0 +1,156 @@
+use ::expr::Expr;
+use ::nfa::NFA;
+use ::matcher::{self, Matcher};
+use ::limits::{Limits, Budget, MatchError};
+use super::{LazyDFA, Cache, Interrupted, DEFAULT_CACHE_SIZE};
+
+fn nfa(pattern: &str) -> NFA {
+    NFA::from_expr(&pattern.parse::<Expr>().unwrap())
+}
+
+// '(a|b)*a(a|b)(a|b)...', whose DFA has 2^(n+1) states
+fn nth_from_last(n: usize) -> NFA {
+    let mut pattern = "(a|b)*a".to_owned();
+    pattern.push_str(&"(a|b)".repeat(n));
+    nfa(&pattern)
+}
+
+// deterministic pseudo-random string of 'a' and 'b'
+fn random_text(len: usize) -> String {
+    let mut seed: u32 = 12345;
+    (0..len).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        if (seed >> 16) & 1 == 0 { 'a' } else { 'b' }
+    }).collect()
+}
+
+// memory used by the cached start state
+fn state_size(nfa: &NFA) -> usize {
+    Cache::state_memory(&nfa.start_closure(), nfa.byte_classes().num_classes())
+}
+
+#[test]
+fn finds_shortest_match() {
+    let nfa = nfa("ab+");
+    let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+    let mut cache = Cache::new();
+
+    assert_eq!(Ok(Some(2)), dfa.shortest_match(&mut cache, b"abbb", &mut Budget::unlimited()));
+    assert_eq!(Ok(None), dfa.shortest_match(&mut cache, b"a", &mut Budget::unlimited()));
+    assert_eq!(Ok(None), dfa.shortest_match(&mut cache, b"bab", &mut Budget::unlimited()));
+}
+
+#[test]
+fn matches_empty_pattern_immediately() {
+    let nfa = nfa("a*");
+    let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+
+    assert_eq!(Ok(Some(0)), dfa.shortest_match(&mut Cache::new(), b"", &mut Budget::unlimited()));
+    assert_eq!(Ok(Some(0)), dfa.shortest_match(&mut Cache::new(), b"aaa", &mut Budget::unlimited()));
+}
+
+#[test]
+fn does_not_match_newline_with_dot() {
+    let nfa = nfa("a.");
+    let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+
+    assert_eq!(Ok(false), dfa.is_match(&mut Cache::new(), b"a\n", &mut Budget::unlimited()));
+    assert_eq!(Ok(true), dfa.is_match(&mut Cache::new(), b"ab", &mut Budget::unlimited()));
+}
+
+#[test]
+fn agrees_with_matcher() {
+    let patterns = ["a?a?aa", "((abc|acc)b)+", "[ab]+c", ".*c", "(ab|bc)*d"];
+    let texts = ["", "aa", "a", "abcbaccb", "abbac", "xyzc", "ababbcd", "abab"];
+
+    for pattern in patterns.iter() {
+        let nfa = nfa(pattern);
+        let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+        let mut cache = Cache::new();
+
+        for text in texts.iter() {
+            let expected = Matcher::new(&nfa, text).run(&mut matcher::Cache::new()).is_some();
+            assert_eq!(Ok(expected), dfa.is_match(&mut cache, text.as_bytes(), &mut Budget::unlimited()),
+                       "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn reuses_cached_states() {
+    let nfa = nfa("(a|b)*c");
+    let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+    let mut cache = Cache::new();
+
+    assert_eq!(Ok(Some(7)), dfa.shortest_match(&mut cache, b"abababc", &mut Budget::unlimited()));
+    let num_states = cache.states.len();
+
+    assert_eq!(Ok(Some(9)), dfa.shortest_match(&mut cache, b"bbbaaabac", &mut Budget::unlimited()));
+    assert_eq!(num_states, cache.states.len());
+}
+
+#[test]
+fn flushes_full_cache() {
+    let nfa = nfa("abcdefghijk*l");
+    let dfa = LazyDFA::new(&nfa, 8 * state_size(&nfa));
+    let mut cache = Cache::new();
+
+    let text = "abcdefghij".to_owned() + &"k".repeat(1000) + "l";
+    assert_eq!(Ok(Some(text.len())), dfa.shortest_match(&mut cache, text.as_bytes(), &mut Budget::unlimited()));
+    assert_eq!(1, cache.flushes);
+}
+
+#[test]
+fn gives_up_when_thrashing() {
+    let nfa = nth_from_last(20);
+    let text = random_text(2000) + "c";
+    let dfa = LazyDFA::new(&nfa, 8 * state_size(&nfa));
+
+    assert_eq!(Err(Interrupted::CacheThrashed), dfa.is_match(&mut Cache::new(), text.as_bytes(), &mut Budget::unlimited()));
+}
+
+#[test]
+fn handles_exponential_pattern_with_large_cache() {
+    let nfa = nth_from_last(20);
+    let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+
+    let text = random_text(100) + "a" + &"b".repeat(20);
+    assert_eq!(Ok(true), dfa.is_match(&mut Cache::new(), text.as_bytes(), &mut Budget::unlimited()));
+    assert_eq!(Ok(false), dfa.is_match(&mut Cache::new(), b"bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb", &mut Budget::unlimited()));
+}
+
+#[test]
+fn finds_end_of_leftmost_first_match() {
+    let cases = [("b+", "abbbc", Some(4)), ("ca?|cab", "xcab", Some(3)), ("a|ab", "ab", Some(1)),
+                 ("ab|a", "ab", Some(2)), ("x*", "abc", Some(0)), ("ab", "aab ab", Some(3)), ("ab", "ba", None)];
+
+    for &(pattern, text, end) in cases.iter() {
+        let nfa = nfa(pattern);
+        let dfa = LazyDFA::leftmost_first(&nfa, DEFAULT_CACHE_SIZE);
+        assert_eq!(Ok(end), dfa.leftmost_match_end(&mut Cache::new(), text.as_bytes(), &mut Budget::unlimited()), "{} on {:?}", pattern, text);
+    }
+}
+
+#[test]
+fn stops_starting_matches_once_one_ends() {
+    let nfa = nfa("ab");
+    let start = nfa.leftmost_start();
+    assert_eq!(vec![0, nfa.num_states()], start.states);
+
+    let matched = nfa.leftmost_step(&nfa.leftmost_step(&start.states, b'a').states, b'b');
+    assert!(matched.is_match());
+    assert!(matched.states.is_empty());
+}
+
+#[test]
+fn stops_when_limits_are_exceeded() {
+    let nfa = nfa("(a|b)*c");
+    let dfa = LazyDFA::new(&nfa, DEFAULT_CACHE_SIZE);
+    assert_eq!(Ok(Some(5)), dfa.shortest_match(&mut Cache::new(), b"ababc", &mut Budget::new(Limits::new().steps(5).bytes(5))));
+    assert_eq!(Err(Interrupted::Exceeded(MatchError::BudgetExceeded)),
+               dfa.shortest_match(&mut Cache::new(), b"ababc", &mut Budget::new(Limits::new().steps(4))));
+
+    let dfa = LazyDFA::leftmost_first(&nfa, DEFAULT_CACHE_SIZE);
+    assert_eq!(Err(Interrupted::Exceeded(MatchError::BudgetExceeded)),
+               dfa.leftmost_match_end(&mut Cache::new(), b"ababc", &mut Budget::new(Limits::new().bytes(4))));
+}
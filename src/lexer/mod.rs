@@ -0,0 +1,191 @@
+use std::fmt;
+use std::ops::Range;
+
+use ::expr::Expr;
+use ::nfa::{NFA, Closure};
+use ::lazy::{self, LazyDFA, Interrupted};
+use ::limits::{Limits, Budget, MatchError, CompileLimits, CompileError};
+
+#[cfg(test)] mod spec;
+
+// the kind of a token and the span of text it covers
+type Token<K> = (K, Range<usize>);
+
+#[derive(PartialEq,Debug,Clone)]
+pub struct LexError {
+    pub position: usize // offset of the first char no rule matches
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no rule matches at offset {}", self.position)
+    }
+}
+
+// maximal munch tokenizer over an ordered list of (token kind, pattern) rules.
+// The longest match wins, and the earlier rule wins between matches of equal length
+#[derive(Debug)]
+pub struct Lexer<K> {
+    kinds: Vec<K>,
+    nfa: NFA
+}
+
+impl<K: Clone> Lexer<K> {
+    pub fn new(rules: &[(K, &str)]) -> Result<Lexer<K>, String> {
+        Self::with_limits(rules, CompileLimits::new()).map_err(|error| error.to_string())
+    }
+
+    // each rule is held to limits as it is parsed, and the NFA of the rules
+    // together to the states limit
+    pub fn with_limits(rules: &[(K, &str)], limits: CompileLimits) -> Result<Lexer<K>, CompileError> {
+        let exprs = rules.iter()
+            .map(|&(_, pattern)| Expr::parse(pattern, limits))
+            .collect::<Result<Vec<_>, _>>()?;
+        if exprs.iter().any(|expr| expr.can_match_invalid_utf8()) {
+            return Err(CompileError::Pattern("pattern can match invalid utf-8".to_owned()));
+        }
+
+        let literal = exprs.iter().all(Expr::is_literal);
+        Ok(Lexer {
+            kinds: rules.iter().map(|(kind, _)| kind.clone()).collect(),
+            nfa: NFA::try_from_exprs(&exprs, limits.max_states(literal))?
+        })
+    }
+
+    pub fn tokens<'a>(&'a self, text: &'a str) -> Tokens<'a, K> {
+        Tokens {
+            lexer: self,
+            dfa: LazyDFA::new(&self.nfa, lazy::DEFAULT_CACHE_SIZE),
+            cache: lazy::Cache::new(),
+            text: text.as_bytes(),
+            position: 0,
+            failed: false,
+            budget: Budget::unlimited()
+        }
+    }
+
+    pub fn tokenize(&self, text: &str) -> Result<Vec<Token<K>>, LexError> {
+        self.tokens(text).collect()
+    }
+
+    // gives up with an error once the whole text has taken more than limits
+    pub fn tokenize_limited(&self, text: &str, limits: Limits) -> Result<Result<Vec<Token<K>>, LexError>, MatchError> {
+        let mut tokens = self.tokens(text);
+        tokens.budget = Budget::new(limits);
+
+        let mut found = Vec::new();
+        while let Some(token) = tokens.next_token()? {
+            match token {
+                Ok(token) => found.push(token),
+                Err(error) => return Ok(Err(error))
+            }
+        }
+        Ok(Ok(found))
+    }
+}
+
+pub struct Tokens<'a, K: 'a> {
+    lexer: &'a Lexer<K>,
+    dfa: LazyDFA<'a>,
+    cache: lazy::Cache,
+    text: &'a [u8],
+    position: usize,
+    failed: bool,
+    budget: Budget
+}
+
+impl<'a, K: Clone> Tokens<'a, K> {
+    fn next_token(&mut self) -> Result<Option<Result<Token<K>, LexError>>, MatchError> {
+        if self.failed || self.position == self.text.len() {
+            return Ok(None);
+        }
+
+        match self.longest_match()? {
+            Some((rule, end)) if end > self.position => {
+                let span = self.position..end;
+                self.position = end;
+                Ok(Some(Ok((self.lexer.kinds[rule].clone(), span))))
+            },
+            _ => { // no rule applies, or only matches the empty string
+                self.failed = true;
+                Ok(Some(Err(LexError { position: self.position })))
+            }
+        }
+    }
+
+    // (rule, end offset) of the longest match starting at the current position
+    fn longest_match(&mut self) -> Result<Option<(usize, usize)>, MatchError> {
+        let text = &self.text[self.position..];
+        let mut longest = None;
+
+        let scanned = {
+            let (dfa, cache, budget) = (&self.dfa, &mut self.cache, &mut self.budget);
+            Self::scan_lazy(dfa, cache, text, self.position, &mut longest, budget)
+        };
+        match scanned {
+            Ok(()) => {},
+            Err(Interrupted::CacheThrashed) => {
+                longest = None;
+                Self::scan_nfa(&self.lexer.nfa, text, self.position, &mut longest, &mut self.budget)?;
+            },
+            Err(Interrupted::Exceeded(error)) => return Err(error)
+        }
+
+        Ok(longest.map(|(rule, len)| (rule, self.position + len)))
+    }
+
+    // a step is a state entered. `text` starts at offset `start`
+    fn scan_lazy(dfa: &LazyDFA, cache: &mut lazy::Cache, text: &[u8], start: usize,
+                 longest: &mut Option<(usize, usize)>, budget: &mut Budget) -> Result<(), Interrupted> {
+        let mut current = dfa.start_state(cache)?;
+
+        for (i, &byte) in text.iter().enumerate() {
+            if !Self::record(cache.closure(current), i, longest) {
+                return Ok(());
+            }
+            budget.step()?;
+            budget.read(start + i)?;
+            current = dfa.next_state(cache, current, byte)?;
+        }
+
+        Self::record(cache.closure(current), text.len(), longest);
+        Ok(())
+    }
+
+    // steps through the NFA directly when the lazy DFA cache is thrashing.
+    // A step is an NFA state moved over a byte
+    fn scan_nfa(nfa: &NFA, text: &[u8], start: usize, longest: &mut Option<(usize, usize)>,
+                budget: &mut Budget) -> Result<(), MatchError> {
+        let mut closure = nfa.start_closure();
+
+        for (i, &byte) in text.iter().enumerate() {
+            if !Self::record(&closure, i, longest) {
+                return Ok(());
+            }
+            budget.take(closure.states.len())?;
+            budget.read(start + i)?;
+            closure = nfa.step(&closure.states, byte);
+        }
+
+        Self::record(&closure, text.len(), longest);
+        Ok(())
+    }
+
+    // keeps the earliest rule matching `len` bytes, returning whether a
+    // longer match is still possible
+    fn record(closure: &Closure, len: usize, longest: &mut Option<(usize, usize)>) -> bool {
+        if let Some(&rule) = closure.matches.first() {
+            *longest = Some((rule, len));
+        }
+
+        !closure.states.is_empty()
+    }
+}
+
+impl<'a, K: Clone> Iterator for Tokens<'a, K> {
+    type Item = Result<Token<K>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().unwrap() // an unlimited budget cannot run out
+    }
+}
@@ -0,0 +1,98 @@
+use ::limits::{Limits, MatchError, CompileLimits, CompileError};
+use super::{Lexer, LexError};
+
+#[derive(PartialEq,Debug,Clone)]
+enum Kind {
+    Keyword,
+    Ident,
+    Number,
+    Space,
+    Op
+}
+
+fn lexer() -> Lexer<Kind> {
+    Lexer::new(&[
+        (Kind::Keyword, "let|if"),
+        (Kind::Ident, "[abcdefghijklmnopqrstuvwxyz_]+"),
+        (Kind::Number, "[0123456789]+"),
+        (Kind::Space, "[ \n]+"),
+        (Kind::Op, "=|==")
+    ]).unwrap()
+}
+
+#[test]
+fn returns_err_on_invalid_rule() {
+    assert!(Lexer::new(&[(Kind::Ident, "[")]).is_err());
+}
+
+#[test]
+fn tokenizes_text() {
+    assert_eq!(Ok(vec![
+        (Kind::Keyword, 0..3),
+        (Kind::Space, 3..4),
+        (Kind::Ident, 4..5),
+        (Kind::Space, 5..6),
+        (Kind::Op, 6..7),
+        (Kind::Space, 7..8),
+        (Kind::Number, 8..10)
+    ]), lexer().tokenize("let x = 42"));
+}
+
+#[test]
+fn prefers_longest_match() {
+    assert_eq!(Ok(vec![(Kind::Ident, 0..6)]), lexer().tokenize("letter"));
+    assert_eq!(Ok(vec![(Kind::Op, 0..2), (Kind::Op, 2..3)]), lexer().tokenize("==="));
+}
+
+#[test]
+fn breaks_ties_by_rule_order() {
+    assert_eq!(Ok(vec![(Kind::Keyword, 0..2)]), lexer().tokenize("if"));
+
+    let lexer = Lexer::new(&[(Kind::Ident, "[ab]+"), (Kind::Keyword, "ab")]).unwrap();
+    assert_eq!(Ok(vec![(Kind::Ident, 0..2)]), lexer.tokenize("ab"));
+}
+
+#[test]
+fn reports_position_of_error() {
+    assert_eq!(Err(LexError { position: 4 }), lexer().tokenize("let ?"));
+    assert_eq!("no rule matches at offset 4", format!("{}", LexError { position: 4 }));
+}
+
+#[test]
+fn stops_after_error() {
+    let tokens = lexer().tokens("a?b").collect::<Vec<_>>();
+    assert_eq!(vec![Ok((Kind::Ident, 0..1)), Err(LexError { position: 1 })], tokens);
+}
+
+#[test]
+fn does_not_emit_empty_tokens() {
+    let lexer = Lexer::new(&[(Kind::Space, " *")]).unwrap();
+    assert_eq!(Ok(vec![(Kind::Space, 0..2)]), lexer.tokenize("  "));
+    assert_eq!(Err(LexError { position: 0 }), lexer.tokenize("x"));
+}
+
+#[test]
+fn tokenizes_empty_text() {
+    assert_eq!(Ok(vec![]), lexer().tokenize(""));
+}
+
+#[test]
+fn stops_when_limits_are_exceeded() {
+    assert_eq!(Ok(Ok(vec![(Kind::Keyword, 0..3), (Kind::Space, 3..4), (Kind::Ident, 4..5)])),
+               lexer().tokenize_limited("let x", Limits::new().bytes(5)));
+    assert_eq!(Ok(Err(LexError { position: 4 })), lexer().tokenize_limited("let ?", Limits::new().bytes(5)));
+    assert_eq!(Err(MatchError::BudgetExceeded), lexer().tokenize_limited("let x", Limits::new().bytes(4)));
+    assert_eq!(Err(MatchError::BudgetExceeded), lexer().tokenize_limited("let x", Limits::new().steps(3)));
+}
+
+#[test]
+fn compiles_within_limits() {
+    let limits = CompileLimits::new().states(5);
+    assert_eq!(Err(CompileError::TooManyStates(5)),
+               Lexer::with_limits(&[(Kind::Ident, "[abc]+"), (Kind::Number, "[012]+"), (Kind::Space, " +")], limits).map(|_| ()));
+    assert_eq!(Err(CompileError::TooManyStates(5)),
+               Lexer::with_limits(&[(Kind::Keyword, "let"), (Kind::Keyword, "while")], limits).map(|_| ()));
+
+    let lexer = Lexer::with_limits(&[(Kind::Keyword, "let"), (Kind::Keyword, "while"), (Kind::Space, " ")], CompileLimits::new().states(20)).unwrap();
+    assert_eq!(Ok(vec![(Kind::Keyword, 0..3), (Kind::Space, 3..4), (Kind::Keyword, 4..9)]), lexer.tokenize("let while"));
+}
@@ -1,14 +1,38 @@
 #![feature(unicode)]
 #![feature(test)]
+#![allow(clippy::upper_case_acronyms)] // automata go by their usual names, NFA and DFA
 
 pub mod regex;
+pub mod bytes;
+pub mod set;
+pub mod lexer;
+pub mod stream;
 
 mod expr;
 mod nfa;
 mod matcher;
+mod lazy;
+mod dfa;
+mod literal;
+mod aho_corasick;
+mod program;
+mod limits;
+mod format;
+mod pike;
+mod backtrack;
+mod onepass;
+mod glushkov;
+mod derivative;
 
 pub use regex::Regex;
+pub use set::RegexSet;
+pub use lexer::Lexer;
+pub use stream::StreamMatcher;
 pub use expr::Expr;
+pub use dfa::{DFA, DFAView};
+pub use derivative::DerivativeDFA;
+pub use matcher::{Observer, Trace, Step, Edge};
+pub use limits::{Limits, MatchError, CompileLimits, CompileError};
 
 #[cfg(test)] mod spec;
 
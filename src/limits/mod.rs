@@ -0,0 +1,220 @@
+use std::fmt;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+#[cfg(test)] mod spec;
+
+// the deadline is checked once every this many steps, as reading the clock
+// costs more than a step
+const STEPS_PER_CLOCK_READ: usize = 256;
+
+// defaults for compiling, see Expr for why nesting is bounded
+const MAX_NESTING_DEPTH: usize = 250;
+const MAX_STATES: usize = 10000;
+const MAX_CLASS_SIZE: usize = 1 << 16;
+
+// the default states limit for patterns made only of chars, groups and
+// alternation, such as a list of keywords to block. Their NFA is about as
+// long as the pattern and takes time in proportion to it to build, so
+// they are allowed many more states than patterns with repetitions
+const MAX_LITERAL_STATES: usize = 1 << 20;
+
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+pub enum MatchError {
+    BudgetExceeded // a search ran out of steps, bytes or time before it could answer
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MatchError::BudgetExceeded => write!(f, "match budget exceeded")
+        }
+    }
+}
+
+impl Error for MatchError {}
+
+// bounds on the work of one search, none by default. A step is a thread
+// the engine moves over a byte, or a state for the lazy DFA, and bytes
+// bound the offsets of text it reads
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub struct Limits {
+    steps: Option<usize>,
+    bytes: Option<usize>,
+    deadline: Option<Instant>
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::new()
+    }
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits {
+            steps: None,
+            bytes: None,
+            deadline: None
+        }
+    }
+
+    pub fn steps(mut self, steps: usize) -> Limits {
+        self.steps = Some(steps);
+        self
+    }
+
+    pub fn bytes(mut self, bytes: usize) -> Limits {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Instant) -> Limits {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // a deadline this long from now
+    pub fn timeout(self, timeout: Duration) -> Limits {
+        self.deadline(Instant::now() + timeout)
+    }
+}
+
+// what a search has used of its limits
+pub struct Budget {
+    limits: Limits,
+    steps: usize,
+    clock_read_at: usize // steps taken when the deadline is next checked
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Budget {
+        Budget {
+            limits,
+            steps: 0,
+            clock_read_at: 0
+        }
+    }
+
+    pub fn unlimited() -> Budget {
+        Self::new(Limits::new())
+    }
+
+    pub fn step(&mut self) -> Result<(), MatchError> {
+        self.take(1)
+    }
+
+    // takes several steps at once, e.g. for the threads of a set of NFA
+    // states moved over a byte together
+    pub fn take(&mut self, steps: usize) -> Result<(), MatchError> {
+        if self.limits.steps.is_some_and(|limit| self.steps + steps > limit) {
+            return Err(MatchError::BudgetExceeded);
+        }
+        if self.steps >= self.clock_read_at {
+            if let Some(deadline) = self.limits.deadline {
+                if Instant::now() >= deadline {
+                    return Err(MatchError::BudgetExceeded);
+                }
+            }
+            self.clock_read_at = self.steps + STEPS_PER_CLOCK_READ;
+        }
+
+        self.steps += steps;
+        Ok(())
+    }
+
+    // before reading the byte at position
+    pub fn read(&self, position: usize) -> Result<(), MatchError> {
+        match self.limits.bytes {
+            Some(bytes) if position >= bytes => Err(MatchError::BudgetExceeded),
+            _ => Ok(())
+        }
+    }
+}
+
+#[derive(PartialEq,Eq,Debug,Clone)]
+pub enum CompileError {
+    Pattern(String), // malformed or unsupported
+    NestingTooDeep(usize), // each holds the limit it exceeded
+    TooManyStates(usize),
+    ClassTooLarge(usize)
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::Pattern(ref message) => write!(f, "{}", message),
+            CompileError::NestingTooDeep(limit) => write!(f, "groups and repetitions nest deeper than {}", limit),
+            CompileError::TooManyStates(limit) => write!(f, "pattern needs more than {} NFA states", limit),
+            CompileError::ClassTooLarge(limit) => write!(f, "char class has more than {} members", limit)
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<String> for CompileError {
+    fn from(message: String) -> CompileError {
+        CompileError::Pattern(message)
+    }
+}
+
+// bounds on compiling a pattern. Nesting counts groups and repetitions
+// around each part of the pattern. Unless states are set, the limit on them
+// depends on whether the pattern is only literals
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub struct CompileLimits {
+    nesting_depth: usize,
+    states: Option<usize>,
+    class_size: usize
+}
+
+impl Default for CompileLimits {
+    fn default() -> CompileLimits {
+        CompileLimits::new()
+    }
+}
+
+impl CompileLimits {
+    pub fn new() -> CompileLimits {
+        CompileLimits {
+            nesting_depth: MAX_NESTING_DEPTH,
+            states: None,
+            class_size: MAX_CLASS_SIZE
+        }
+    }
+
+    pub fn nesting_depth(mut self, depth: usize) -> CompileLimits {
+        self.nesting_depth = depth;
+        self
+    }
+
+    // for every pattern, literal or not
+    pub fn states(mut self, states: usize) -> CompileLimits {
+        self.states = Some(states);
+        self
+    }
+
+    pub fn class_size(mut self, size: usize) -> CompileLimits {
+        self.class_size = size;
+        self
+    }
+
+    pub(crate) fn max_nesting_depth(&self) -> usize {
+        self.nesting_depth
+    }
+
+    // the states allowed for a pattern, or patterns compiled together, that
+    // are only literals or not
+    pub(crate) fn max_states(&self, literal: bool) -> usize {
+        match self.states {
+            Some(states) => states,
+            None if literal => MAX_LITERAL_STATES,
+            None => MAX_STATES
+        }
+    }
+
+    pub(crate) fn max_class_size(&self) -> usize {
+        self.class_size
+    }
+}
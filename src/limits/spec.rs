@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+use super::{Limits, Budget, MatchError};
+
+#[test]
+fn counts_steps() {
+    let mut budget = Budget::new(Limits::new().steps(2));
+    assert_eq!(Ok(()), budget.step());
+    assert_eq!(Ok(()), budget.step());
+    assert_eq!(Err(MatchError::BudgetExceeded), budget.step());
+
+    let mut budget = Budget::new(Limits::new().steps(5));
+    assert_eq!(Ok(()), budget.take(3));
+    assert_eq!(Err(MatchError::BudgetExceeded), budget.take(3));
+    assert_eq!(Ok(()), budget.take(2));
+}
+
+#[test]
+fn bounds_bytes_read() {
+    let budget = Budget::new(Limits::new().bytes(3));
+    assert_eq!(Ok(()), budget.read(2));
+    assert_eq!(Err(MatchError::BudgetExceeded), budget.read(3));
+}
+
+#[test]
+fn checks_deadline() {
+    let mut budget = Budget::new(Limits::new().deadline(Instant::now()));
+    assert_eq!(Err(MatchError::BudgetExceeded), budget.step());
+
+    let mut budget = Budget::new(Limits::new().timeout(Duration::from_secs(60)));
+    for _ in 0..1000 {
+        assert_eq!(Ok(()), budget.step());
+    }
+
+    let mut budget = Budget::unlimited();
+    for _ in 0..1000 {
+        assert_eq!(Ok(()), budget.step());
+    }
+    assert_eq!(Ok(()), budget.read(usize::MAX - 1));
+}
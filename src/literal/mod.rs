@@ -0,0 +1,443 @@
+use std::cmp;
+use std::collections::VecDeque;
+
+use ::expr::Expr;
+use ::aho_corasick::AhoCorasick;
+use ::format::{self, Reader};
+
+#[cfg(test)] mod spec;
+
+// more alternative literals than this are not worth searching for
+const MAX_LITERALS: usize = 16;
+
+// literal patterns with more alternatives than this go through the NFA
+const MAX_ALTERNATIVES: usize = 10000;
+
+// Horspool checks every literal at each candidate, beyond this many the
+// Aho-Corasick automaton is faster
+const MAX_HORSPOOL_LITERALS: usize = 16;
+
+// bytes that a match starts with. A complete literal is the whole match
+#[derive(PartialEq,Eq,PartialOrd,Ord,Clone,Debug)]
+pub struct Literal {
+    pub bytes: Vec<u8>,
+    pub complete: bool
+}
+
+impl Literal {
+    fn complete(bytes: Vec<u8>) -> Literal {
+        Literal {
+            bytes,
+            complete: true
+        }
+    }
+
+    fn cut(self) -> Literal {
+        Literal {
+            bytes: self.bytes,
+            complete: false
+        }
+    }
+}
+
+// literals such that every match of expr starts with one of them
+pub fn prefixes(expr: &Expr) -> Vec<Literal> {
+    match *expr {
+        Expr::Single(c) => {
+            let mut buf = [0; 4];
+            vec![Literal::complete(c.encode_utf8(&mut buf).as_bytes().to_vec())]
+        },
+        Expr::Byte(b) => vec![Literal::complete(vec![b])],
+        Expr::Class(ref chars) if chars.len() <= MAX_LITERALS => {
+            chars.iter().flat_map(|&c| prefixes(&Expr::Single(c))).collect()
+        },
+        Expr::ByteClass(ref bytes) if bytes.len() <= MAX_LITERALS => {
+            bytes.iter().map(|&b| Literal::complete(vec![b])).collect()
+        },
+        Expr::Class(_) | Expr::ByteClass(_) | Expr::Any | Expr::AnyByte => {
+            vec![Literal::complete(Vec::new()).cut()]
+        },
+        Expr::Sequence(..) => {
+            // sequences nest to the right, so their parts are joined in a
+            // loop from the left, where the literals grow in place
+            let mut result = Vec::new();
+            let mut rest = expr;
+            while let Expr::Sequence(ref part, ref next) = *rest {
+                result = if result.is_empty() { prefixes(part) } else { join(result, &prefixes(part)) };
+                if !result.iter().any(|literal| literal.complete) {
+                    return result;
+                }
+                rest = next;
+            }
+            join(result, &prefixes(rest))
+        },
+        Expr::Or(..) => {
+            // alternatives nest to the left
+            let mut alternatives = Vec::new();
+            let mut first = expr;
+            while let Expr::Or(ref left, ref right) = *first {
+                alternatives.push(&**right);
+                first = left;
+            }
+
+            let mut result = prefixes(first);
+            for alternative in alternatives.into_iter().rev() {
+                result.extend(prefixes(alternative));
+            }
+            result
+        },
+        Expr::Group(ref expr) => prefixes(expr),
+        Expr::Optional(ref expr) => {
+            let mut result = prefixes(expr);
+            result.push(Literal::complete(Vec::new()));
+            result
+        },
+        Expr::ZeroOrMore(ref expr) => {
+            let mut result = prefixes(expr).into_iter().map(Literal::cut).collect::<Vec<_>>();
+            result.push(Literal::complete(Vec::new()));
+            result
+        },
+        Expr::OneOrMore(ref expr) => {
+            prefixes(expr).into_iter().map(Literal::cut).collect()
+        }
+    }
+}
+
+// each complete literal of left followed by each literal of right, or left
+// cut short if that would make too many
+fn join(left: Vec<Literal>, right: &[Literal]) -> Vec<Literal> {
+    let num_complete = left.iter().filter(|literal| literal.complete).count();
+    if left.len() - num_complete + num_complete * right.len() > MAX_LITERALS {
+        return left.into_iter().map(Literal::cut).collect();
+    }
+
+    let mut result = Vec::new();
+    for literal in left {
+        let (last, others) = match right.split_last() {
+            Some(split) if literal.complete => split,
+            _ => {
+                result.push(literal);
+                continue;
+            }
+        };
+
+        for next in others {
+            let mut bytes = literal.bytes.clone();
+            bytes.extend_from_slice(&next.bytes);
+            result.push(Literal {
+                bytes,
+                complete: next.complete
+            });
+        }
+
+        // the last is extended in place, so a long literal is not copied
+        // at every part
+        let mut bytes = literal.bytes;
+        bytes.extend_from_slice(&last.bytes);
+        result.push(Literal {
+            bytes,
+            complete: last.complete
+        });
+    }
+    result
+}
+
+// the literals matched by a pattern made only of chars and alternations,
+// in the order the NFA matcher tries them: at each alternation the side
+// with the smaller first byte goes first, the left side on a tie
+pub fn alternatives(expr: &Expr) -> Option<Vec<Vec<u8>>> {
+    let literals = match *expr {
+        Expr::Single(c) => {
+            let mut buf = [0; 4];
+            vec![c.encode_utf8(&mut buf).as_bytes().to_vec()]
+        },
+        Expr::Byte(b) => vec![vec![b]],
+        Expr::Group(ref expr) => alternatives(expr)?,
+        Expr::Sequence(..) => {
+            let mut result = vec![Vec::new()];
+            let mut rest = Some(expr);
+            while let Some(part) = rest {
+                let (part, next) = match *part {
+                    Expr::Sequence(ref part, ref next) => (&**part, Some(&**next)),
+                    ref part => (part, None)
+                };
+                let right = alternatives(part)?;
+                if result.len() * right.len() > MAX_ALTERNATIVES {
+                    return None;
+                }
+
+                let (last, others) = right.split_last().expect("every literal pattern has a literal");
+                let mut joined = Vec::new();
+                for literal in result {
+                    for next in others {
+                        let mut bytes = literal.clone();
+                        bytes.extend_from_slice(next);
+                        joined.push(bytes);
+                    }
+
+                    // as in join, the literal is extended in place
+                    let mut bytes = literal;
+                    bytes.extend_from_slice(last);
+                    joined.push(bytes);
+                }
+                result = joined;
+                rest = next;
+            }
+            result
+        },
+        Expr::Or(..) => {
+            let mut others = Vec::new();
+            let mut first = expr;
+            while let Expr::Or(ref left, ref right) = *first {
+                others.push(&**right);
+                first = left;
+            }
+
+            // each alternative goes before the ones left of it if its first
+            // byte is smaller
+            let first_byte = |literals: &[Vec<u8>]| literals.iter().map(|literal| literal[0]).min();
+            let first = alternatives(first)?;
+            let mut min = first_byte(&first);
+            let mut order = VecDeque::new();
+            order.push_back(first);
+            for alternative in others.into_iter().rev() {
+                let alternative = alternatives(alternative)?;
+                let byte = first_byte(&alternative);
+                if byte < min {
+                    order.push_front(alternative);
+                    min = byte;
+                } else {
+                    order.push_back(alternative);
+                }
+            }
+            order.into_iter().flatten().collect()
+        },
+        _ => return None
+    };
+
+    if literals.len() > MAX_ALTERNATIVES {
+        None
+    } else {
+        Some(literals)
+    }
+}
+
+// searches for the literals of a literal pattern, in order of preference
+#[derive(PartialEq,Debug,Clone)]
+pub enum Searcher {
+    Horspool(Horspool),
+    AhoCorasick(AhoCorasick)
+}
+
+impl Searcher {
+    pub fn new(literals: Vec<Vec<u8>>) -> Searcher {
+        if literals.len() <= MAX_HORSPOOL_LITERALS {
+            Searcher::Horspool(Horspool::new(literals))
+        } else {
+            Searcher::AhoCorasick(AhoCorasick::new(&literals))
+        }
+    }
+
+    // the literals, then whichever searcher they need is rebuilt on read
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let literals = match *self {
+            Searcher::Horspool(ref horspool) => horspool.literals.clone(),
+            Searcher::AhoCorasick(ref automaton) => automaton.patterns()
+        };
+        write_literals(out, &literals);
+    }
+
+    pub fn read(reader: &mut Reader) -> Result<Searcher, String> {
+        Ok(Searcher::new(read_literals(reader)?))
+    }
+
+    pub fn match_at(&self, text: &[u8], start: usize) -> Option<usize> {
+        match *self {
+            Searcher::Horspool(ref horspool) => horspool.match_at(text, start),
+            Searcher::AhoCorasick(ref automaton) => automaton.match_at(text, start)
+        }
+    }
+
+    pub fn find(&self, text: &[u8]) -> Option<(usize, usize)> {
+        match *self {
+            Searcher::Horspool(ref horspool) => horspool.find(text),
+            Searcher::AhoCorasick(ref automaton) => automaton.find(text)
+        }
+    }
+}
+
+// Horspool's substring search generalised to several literals, shifting
+// by the shortest literal so that no occurrence of any of them is skipped
+#[derive(PartialEq,Debug,Clone)]
+pub struct Horspool {
+    literals: Vec<Vec<u8>>, // in order of preference
+    min_len: usize,
+    shifts: Vec<usize> // indexed by the last byte of the window
+}
+
+impl Horspool {
+    // literals must be non-empty
+    pub fn new(literals: Vec<Vec<u8>>) -> Horspool {
+        let min_len = literals.iter().map(|literal| literal.len()).min().unwrap_or(1);
+
+        let mut shifts = vec![min_len; 256];
+        for literal in &literals {
+            for (i, &b) in literal[..min_len - 1].iter().enumerate() {
+                shifts[b as usize] = cmp::min(shifts[b as usize], min_len - 1 - i);
+            }
+        }
+
+        Horspool {
+            literals,
+            min_len,
+            shifts
+        }
+    }
+
+    // end of the preferred literal occurring at start
+    pub fn match_at(&self, text: &[u8], start: usize) -> Option<usize> {
+        let rest = &text[start..];
+        self.literals.iter()
+            .find(|literal| rest.starts_with(literal))
+            .map(|literal| start + literal.len())
+    }
+
+    // the leftmost occurrence, as start and end offsets
+    pub fn find(&self, text: &[u8]) -> Option<(usize, usize)> {
+        let mut start = 0;
+        while start + self.min_len <= text.len() {
+            if let Some(end) = self.match_at(text, start) {
+                return Some((start, end));
+            }
+            start += self.shifts[text[start + self.min_len - 1] as usize];
+        }
+
+        None
+    }
+}
+
+// jumps to the positions where a match can start, found by searching for
+// the literal prefixes of the pattern
+#[derive(PartialEq,Debug,Clone)]
+pub struct Prefilter {
+    prefixes: Vec<Vec<u8>>,
+    first_bytes: Vec<bool> // indexed by byte
+}
+
+impl Prefilter {
+    // prefixes must be non-empty
+    pub fn new(prefixes: Vec<Vec<u8>>) -> Prefilter {
+        let mut first_bytes = vec![false; 256];
+        for prefix in &prefixes {
+            first_bytes[prefix[0] as usize] = true;
+        }
+
+        Prefilter {
+            prefixes,
+            first_bytes
+        }
+    }
+
+    // none if some match can start with any byte
+    pub fn from_expr(expr: &Expr) -> Option<Prefilter> {
+        let mut literals = prefixes(expr).into_iter().map(|literal| literal.bytes).collect::<Vec<_>>();
+        if literals.is_empty() || literals.iter().any(|bytes| bytes.is_empty()) {
+            return None;
+        }
+
+        // a literal that starts with another one finds no extra candidates.
+        // Sorted, no other prefix is kept between a literal and the one it
+        // starts with
+        literals.sort();
+        literals.dedup();
+        let mut prefixes: Vec<Vec<u8>> = Vec::new();
+        for literal in literals {
+            if !prefixes.last().is_some_and(|prefix| literal.starts_with(prefix)) {
+                prefixes.push(literal);
+            }
+        }
+
+        Some(Prefilter::new(prefixes))
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        write_literals(out, &self.prefixes);
+    }
+
+    pub fn read(reader: &mut Reader) -> Result<Prefilter, String> {
+        Ok(Prefilter::new(read_literals(reader)?))
+    }
+
+    // the first position from `start` at which one of the prefixes occurs
+    pub fn find(&self, text: &[u8], start: usize) -> Option<usize> {
+        let mut start = start;
+        while start < text.len() {
+            let candidate = start + match self.prefixes.len() {
+                1 => memchr(self.prefixes[0][0], &text[start..])?,
+                _ => text[start..].iter().position(|&b| self.first_bytes[b as usize])?
+            };
+
+            let rest = &text[candidate..];
+            if self.prefixes.iter().any(|prefix| rest.starts_with(prefix)) {
+                return Some(candidate);
+            }
+            start = candidate + 1;
+        }
+
+        None
+    }
+}
+
+// position of the first occurrence of byte, skipping a word at a time
+// while no byte of the word matches
+fn memchr(byte: u8, text: &[u8]) -> Option<usize> {
+    const LO: u64 = 0x0101010101010101;
+    const HI: u64 = 0x8080808080808080;
+    let repeated = LO * byte as u64;
+
+    let mut offset = 0;
+    for chunk in text.chunks(8) {
+        if chunk.len() == 8 {
+            let mut word = [0; 8];
+            word.copy_from_slice(chunk);
+
+            // has a zero byte where the chunk equals byte
+            let x = u64::from_le_bytes(word) ^ repeated;
+            if x.wrapping_sub(LO) & !x & HI == 0 {
+                offset += 8;
+                continue;
+            }
+        }
+
+        if let Some(i) = chunk.iter().position(|&b| b == byte) {
+            return Some(offset + i);
+        }
+        offset += chunk.len();
+    }
+
+    None
+}
+
+fn write_literals(out: &mut Vec<u8>, literals: &[Vec<u8>]) {
+    format::write_u32(out, literals.len());
+    for literal in literals {
+        format::write_block(out, literal);
+    }
+}
+
+// searches assume at least one literal and no empty ones
+fn read_literals(reader: &mut Reader) -> Result<Vec<Vec<u8>>, String> {
+    let len = reader.u32()?;
+    if len == 0 {
+        return Err("no literals".to_owned());
+    }
+    let mut literals = Vec::new();
+    for _ in 0..len {
+        let literal = reader.block()?;
+        if literal.is_empty() {
+            return Err("empty literal".to_owned());
+        }
+        literals.push(literal.to_vec());
+    }
+    Ok(literals)
+}
@@ -0,0 +1,136 @@
+use ::expr::Expr;
+use super::{prefixes, memchr, Literal, Prefilter, Horspool};
+
+fn expr(pattern: &str) -> Expr {
+    pattern.parse::<Expr>().unwrap()
+}
+
+fn prefilter(pattern: &str) -> Option<Prefilter> {
+    Prefilter::from_expr(&expr(pattern))
+}
+
+fn literal(bytes: &str, complete: bool) -> Literal {
+    Literal {
+        bytes: bytes.as_bytes().to_vec(),
+        complete
+    }
+}
+
+#[test]
+fn extracts_literal_sequence() {
+    assert_eq!(vec![literal("abc", true)], prefixes(&expr("abc")));
+    assert_eq!(vec![literal("abc", false), literal("ab", true)], prefixes(&expr("abc*")));
+    assert_eq!(vec![literal("ERROR: ", false), literal("ERROR: timeout", true)],
+               prefixes(&expr("ERROR: .*timeout")));
+}
+
+#[test]
+fn extracts_alternatives() {
+    assert_eq!(vec![literal("ac", true), literal("bc", true)], prefixes(&expr("(a|b)c")));
+    assert_eq!(vec![literal("xa", true), literal("xb", true), literal("xc", true)],
+               prefixes(&expr("x[abc]")));
+    assert_eq!(vec![literal("ab", true), literal("b", true)], prefixes(&expr("a?b")));
+}
+
+#[test]
+fn extracts_utf8_bytes() {
+    assert_eq!(vec![literal("é", false)], prefixes(&expr("é+")));
+}
+
+#[test]
+fn stops_at_too_many_alternatives() {
+    assert_eq!(vec![literal("x", false)], prefixes(&expr("x[abcdefghijklmnopqrstuvwxyz]")));
+    assert_eq!(vec![literal("ab", false), literal("cd", false)],
+               prefixes(&expr("(ab|cd)[abcdefghij]")));
+}
+
+#[test]
+fn has_no_prefilter_when_match_can_start_anywhere() {
+    assert_eq!(None, prefilter(".*a"));
+    assert_eq!(None, prefilter("ab|c*"));
+    assert_eq!(None, prefilter("[abcdefghijklmnopqrstuvwxyz]+"));
+    assert!(prefilter("a+b").is_some());
+    assert!(prefilter("a*b").is_some());
+}
+
+#[test]
+fn drops_prefixes_covered_by_shorter_ones() {
+    let prefilter = prefilter("ab|abc|b").unwrap();
+    assert_eq!(vec![b"ab".to_vec(), b"b".to_vec()], prefilter.prefixes);
+}
+
+#[test]
+fn finds_candidate_positions() {
+    let prefilter = prefilter("ERROR: .*timeout").unwrap();
+    let text = b"INFO: ok\nERROR ERROR: timeout";
+
+    assert_eq!(Some(15), prefilter.find(text, 0));
+    assert_eq!(Some(15), prefilter.find(text, 15));
+    assert_eq!(None, prefilter.find(text, 16));
+
+    let prefilter = self::prefilter("foo|bar").unwrap();
+    assert_eq!(Some(4), prefilter.find(b"fo bbar foo", 0));
+    assert_eq!(Some(8), prefilter.find(b"fo bbar foo", 5));
+}
+
+#[test]
+fn memchr_finds_byte_in_any_word() {
+    let text = (0..40).collect::<Vec<u8>>();
+    for byte in 0..40 {
+        assert_eq!(Some(byte as usize), memchr(byte, &text));
+    }
+    assert_eq!(None, memchr(40, &text));
+    assert_eq!(None, memchr(b'a', b""));
+    assert_eq!(Some(9), memchr(0x80, b"\x7f\x7f\x7f\x7f\x7f\x7f\x7f\x7f\x81\x80"));
+}
+
+fn alternatives(pattern: &str) -> Option<Vec<Vec<u8>>> {
+    super::alternatives(&expr(pattern))
+}
+
+fn bytes(literals: &[&str]) -> Vec<Vec<u8>> {
+    literals.iter().map(|literal| literal.as_bytes().to_vec()).collect()
+}
+
+#[test]
+fn detects_literal_patterns() {
+    assert_eq!(Some(bytes(&["abc"])), alternatives("abc"));
+    assert_eq!(Some(bytes(&["é"])), alternatives("é"));
+    assert_eq!(Some(vec![vec![0xFF, b'a']]), alternatives("(?-u)\\xFFa"));
+    assert_eq!(None, alternatives("ab*"));
+    assert_eq!(None, alternatives("a[bc]"));
+    assert_eq!(None, alternatives("a."));
+}
+
+#[test]
+fn orders_alternatives_like_the_matcher() {
+    assert_eq!(Some(bytes(&["foo", "fob"])), alternatives("foo|fob"));
+    assert_eq!(Some(bytes(&["bar", "foo"])), alternatives("foo|bar"));
+    assert_eq!(Some(bytes(&["ab", "b", "cd"])), alternatives("cd|(ab|b)"));
+    assert_eq!(Some(bytes(&["xa", "xb", "ya", "yb"])), alternatives("(y|x)(a|b)"));
+}
+
+#[test]
+fn gives_up_on_too_many_alternatives() {
+    let group = "(a|b|c|d|e|f|g|h|i|j)";
+    assert!(alternatives(&group.repeat(4)).is_some());
+    assert_eq!(None, alternatives(&group.repeat(5)));
+}
+
+#[test]
+fn horspool_finds_leftmost_occurrence() {
+    let horspool = Horspool::new(bytes(&["needle"]));
+    assert_eq!(Some((13, 19)), horspool.find(b"needl eneedl needle needle"));
+    assert_eq!(None, horspool.find(b"needl"));
+    assert_eq!(None, horspool.find(b""));
+}
+
+#[test]
+fn horspool_finds_any_of_several_literals() {
+    let horspool = Horspool::new(bytes(&["abcd", "xy", "cdx"]));
+    assert_eq!(Some((0, 4)), horspool.find(b"abcdxy"));
+    assert_eq!(Some((2, 5)), horspool.find(b"_bcdxy"));
+    assert_eq!(Some((5, 7)), horspool.find(b"abc__xy"));
+    assert_eq!(Some((1, 3)), horspool.match_at(b"_xy", 1).map(|end| (1, end)));
+    assert_eq!(None, horspool.match_at(b"_xy", 0));
+}
@@ -1,130 +1,82 @@
+use std::fmt;
 use std::collections::HashSet;
+use std::mem;
+use std::slice;
 
-use ::nfa::{State, Transition, NFA, Condition};
+use ::nfa::{self, State, Transition, NFA, Condition};
+use ::literal::Prefilter;
+use ::limits::{Budget, MatchError};
 
 #[cfg(test)] mod spec;
 
-#[derive(Clone,PartialEq,Eq,Hash,Debug)]
+#[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
 struct PotentialMatch {
-    current_state: Option<State>,
-    text: Vec<u8>
+    current_state: Option<usize>,
+    position: usize // offset of the next byte to consume
 }
 
 impl PotentialMatch {
-    pub fn advance(&self, nfa: &NFA) -> Vec<PotentialMatch> {
-        if self.current_state.is_none() {
-            return vec![self.clone()];
+    // pushes the successors of this potential match onto result, in order of greediness
+    pub fn advance(&self, nfa: &NFA, text: &[u8], result: &mut Vec<PotentialMatch>) {
+        let id = match self.current_state {
+            None => return result.push(*self),
+            Some(id) => id
+        };
+
+        // chains of empty edges are walked once, when the NFA is built
+        if let Some(closure) = nfa.epsilon_closure(id) {
+            result.extend(closure.iter().filter_map(|out| self.next_for_edge(&Condition::None, out, text)));
+            return;
         }
 
-        let current_state = self.current_state.clone().unwrap();
-
-        match current_state {
+        match *nfa.get_state(id).unwrap() {
             State::State{ref condition, ref out} => {
-
-                let mut result = Vec::new();
-                Self::push_option(
-                    &mut result,
-                    self.next_for_edge(nfa, condition, out));
-                result
+                Self::push_option(result, self.next_for_edge(condition, out, text));
             },
             State::Split{ref out1, ref out2} => {
-                let c1_next = self.next_for_edge(nfa, &Condition::None, out1);
-                let c2_next = self.next_for_edge(nfa, &Condition::None, out2);
-
-                let mut result = Vec::new();
-                Self::push_option(&mut result, c1_next);
-                Self::push_option(&mut result, c2_next);
-
-                result.sort_by_key(|item| {
-                    match &item.current_state {
-                        &None => usize::max_value(), // this is an end state
-                        &Some(ref state) => state.get_priority_key(&nfa)
+                let first = result.len();
+                Self::push_option(result, self.next_for_edge(&Condition::None, out1, text));
+                Self::push_option(result, self.next_for_edge(&Condition::None, out2, text));
+
+                result[first..].sort_by_key(|item| {
+                    match item.current_state {
+                        None => usize::max_value(), // this is an end state
+                        Some(id) => nfa.priority(id)
                     }
                 });
-
-                result
-            }
-        }
-    }
-
-    fn next_for_edge(&self, nfa: &NFA, condition: &Condition, out: &Transition) -> Option<PotentialMatch> {
-        match condition {
-            &Condition::One(val) => {
-                if self.text.is_empty() {
-                    // no character to consume, this potential match cannot continue
-                    return None;
-                }
-
-                if val == self.text[0] {
-                    // can consume char and advance along edge
-                    match out {
-                        &Transition::End => {
-                            Some(self.with_state_and_increment(None))
-                        },
-                        &Transition::Id(id) => {
-                            Some(self.with_state_and_increment(nfa.get_state(id)))
-                        },
-                        _ => panic!()
-                    }
-                } else { 
-                    // potential match cannot proceed, mismatched character
-                    None
-                }
             },
-            &Condition::None => {
-                // can advance along empty edge
-                match out {
-                    &Transition::End => {
-                        Some(self.with_state(None))
-                    },
-                    &Transition::Id(id) => {
-                        Some(self.with_state(nfa.get_state(id)))
-                    },
-                    _ => panic!("cannot evaluate incomplete NFA")
-                }
+            State::Save{ref out, ..} => {
+                Self::push_option(result, self.next_for_edge(&Condition::None, out, text));
             },
-            &Condition::Any => {
-                if self.text.is_empty() {
-                    return None;
+            State::Fork{ref condition, ref outs} => {
+                for out in outs {
+                    Self::push_option(result, self.next_for_edge(condition, out, text));
                 }
+            }
+        }
+    }
 
-                if self.text[0] as char == '\n' {
-                    return None; // `.` should not match newline
+    fn next_for_edge(&self, condition: &Condition, out: &Transition, text: &[u8]) -> Option<PotentialMatch> {
+        let position = match condition {
+            &Condition::None => self.position, // can advance along empty edge
+            condition => {
+                match text.get(self.position) {
+                    Some(&byte) if condition.matches(byte) => self.position + 1,
+                    _ => return None // no matching byte to consume, this potential match cannot continue
                 }
+            }
+        };
 
-                match out {
-                    &Transition::End => {
-                        Some(self.with_state_and_increment(None))
-                    },
-                    &Transition::Id(id) => {
-                        Some(self.with_state_and_increment(nfa.get_state(id)))
-                    },
-                    _ => panic!()
-                }
-            },
-            &Condition::Class(ref chars) => {
-                if self.text.is_empty() {
-                    // no character to consume, this potential match cannot continue
-                    return None;
-                }
+        let state = match out {
+            &Transition::Id(id) => Some(id),
+            &Transition::End | &Transition::Match(_) => None,
+            &Transition::Detached => panic!("cannot evaluate incomplete NFA")
+        };
 
-                if chars.contains(&self.text[0]) {
-                    // can consume char and advance along edge
-                    match out {
-                        &Transition::End => {
-                            Some(self.with_state_and_increment(None))
-                        },
-                        &Transition::Id(id) => {
-                            Some(self.with_state_and_increment(nfa.get_state(id)))
-                        },
-                        _ => panic!()
-                    }
-                } else { 
-                    // potential match cannot proceed, mismatched character
-                    None
-                }
-            }
-        }
+        Some(PotentialMatch {
+            current_state: state,
+            position
+        })
     }
 
     pub fn push_option<T>(vec: &mut Vec<T>, item: Option<T>) {
@@ -138,79 +90,340 @@ impl PotentialMatch {
         self.current_state.is_none()
     }
 
-    pub fn new(state: Option<State>, text: &str) -> Self {
+    pub fn new(state: Option<usize>, position: usize) -> Self {
         PotentialMatch {
             current_state: state,
-            text: Vec::from(text.to_owned().as_bytes())
+            position
         }
     }
+}
 
-    fn with_state(&self, state: Option<State>) -> Self {
-        PotentialMatch {
-            current_state: state,
-            text: self.text.clone()
+// a consuming state that `Matcher::find` has reached, and where its match started
+#[derive(Clone,Copy,Debug)]
+struct Thread {
+    state: usize,
+    start: usize
+}
+
+// scratch space for `Matcher::run` and `Matcher::find`, cleared and reused
+// between searches
+#[derive(Debug)]
+pub struct Cache {
+    stack: Vec<PotentialMatch>,
+    visited: HashSet<PotentialMatch>,
+    successors: Vec<PotentialMatch>,
+    threads: Vec<Thread>, // in the order run() would try them
+    next_threads: Vec<Thread>,
+    added: Vec<usize>, // per state, the last position it got a thread at
+    edges: Vec<Transition>
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            stack: Vec::new(),
+            visited: HashSet::new(),
+            successors: Vec::new(),
+            threads: Vec::new(),
+            next_threads: Vec::new(),
+            added: Vec::new(),
+            edges: Vec::new()
         }
     }
 
-    fn with_state_and_increment(&self, state: Option<State>) -> Self {
-        PotentialMatch {
-            current_state: state,
-            text: Vec::from(&self.text[1..])
+    fn clear(&mut self) {
+        self.stack.clear();
+        self.visited.clear();
+        self.successors.clear();
+        self.threads.clear();
+        self.next_threads.clear();
+        self.added.clear();
+        self.edges.clear();
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+// told what `Matcher::run_observed` does as it searches. A thread is a
+// state waiting at a position, and is explored at most once
+pub trait Observer {
+    fn thread(&mut self, _state: usize, _position: usize) {}
+
+    // an edge from a thread, which consumed byte unless it is empty. `to`
+    // is none for the end of the pattern
+    fn transition(&mut self, _from: usize, _position: usize, _to: Option<usize>, _byte: Option<u8>) {}
+
+    fn matched(&mut self, _position: usize) {}
+}
+
+// observes nothing, for plain runs
+impl Observer for () {}
+
+// what a run did at each position of the text, in the order it did it.
+// The search is depth first, so it may come back to a position
+#[derive(PartialEq,Debug,Clone)]
+pub struct Trace {
+    pub steps: Vec<Step>, // indexed by position
+    pub matched: Option<usize>
+}
+
+#[derive(PartialEq,Debug,Clone)]
+pub struct Step {
+    pub threads: Vec<usize>, // states explored at this position
+    pub edges: Vec<Edge>
+}
+
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Option<usize>,
+    pub byte: Option<u8>
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace {
+            steps: Vec::new(),
+            matched: None
         }
     }
+
+    fn step(&mut self, position: usize) -> &mut Step {
+        while self.steps.len() <= position {
+            self.steps.push(Step { threads: Vec::new(), edges: Vec::new() });
+        }
+        &mut self.steps[position]
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Trace {
+        Trace::new()
+    }
 }
 
+impl Observer for Trace {
+    fn thread(&mut self, state: usize, position: usize) {
+        self.step(position).threads.push(state);
+    }
+
+    fn transition(&mut self, from: usize, position: usize, to: Option<usize>, byte: Option<u8>) {
+        self.step(position).edges.push(Edge { from, to, byte });
+    }
+
+    fn matched(&mut self, position: usize) {
+        self.matched = Some(position);
+    }
+}
 
-pub struct Matcher {
-    nfa: NFA,
-    text: String
+// a line for each position, e.g. `1: threads 1, 0; 1 -> 0, 1 -> end, 0 -b-> 2`
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (position, step) in self.steps.iter().enumerate() {
+            let threads = step.threads.iter().map(|state| state.to_string()).collect::<Vec<_>>();
+            let edges = step.edges.iter()
+                .map(|edge| {
+                    let to = edge.to.map_or("end".to_owned(), |state| state.to_string());
+                    match edge.byte {
+                        Some(byte) => format!("{} -{}-> {}", edge.from, nfa::bytes_label(&[byte]), to),
+                        None => format!("{} -> {}", edge.from, to)
+                    }
+                })
+                .collect::<Vec<_>>();
+            writeln!(f, "{}: threads {}; {}", position, threads.join(", "), edges.join(", "))?;
+        }
+        match self.matched {
+            Some(position) => writeln!(f, "match at {}", position),
+            None => writeln!(f, "no match")
+        }
+    }
 }
 
-impl Matcher {
-    pub fn new(nfa: NFA, text: &str) -> Matcher {
+pub struct Matcher<'a> {
+    nfa: &'a NFA,
+    text: &'a [u8]
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new<T: AsRef<[u8]> + ?Sized>(nfa: &'a NFA, text: &'a T) -> Matcher<'a> {
         Matcher {
-            nfa: nfa,
-            text: text.to_owned()
+            nfa,
+            text: text.as_ref()
         }
     }
 
-    pub fn run(&mut self) -> Option<usize> { // return optional end offset of match
+    pub fn run(&self, cache: &mut Cache) -> Option<usize> { // return optional end offset of match
+        self.run_observed(cache, &mut ())
+    }
+
+    pub fn run_observed<O: Observer>(&self, cache: &mut Cache, observer: &mut O) -> Option<usize> {
+        self.search(cache, observer, &mut Budget::unlimited()).unwrap() // cannot run out
+    }
+
+    // a step is a thread explored
+    pub fn run_limited(&self, cache: &mut Cache, budget: &mut Budget) -> Result<Option<usize>, MatchError> {
+        self.search(cache, &mut (), budget)
+    }
+
+    fn search<O: Observer>(&self, cache: &mut Cache, observer: &mut O, budget: &mut Budget) -> Result<Option<usize>, MatchError> {
 
         if self.nfa.num_states() == 0 { // regex is empty
-            return Some(0);
+            observer.matched(0);
+            return Ok(Some(0));
         }
 
-        let first_potential_match = PotentialMatch::new(
-            Some(self.nfa.get_start().unwrap()),
-                 &self.text);
-
+        cache.clear();
+        cache.stack.push(PotentialMatch::new(Some(self.nfa.start()), 0));
 
-        let mut set = HashSet::new();
-        set.insert(first_potential_match.clone());
-        let mut states = vec![first_potential_match];
+        while let Some(state) = cache.stack.pop() {
 
-        while states.len() > 0 {
-            let state = states.pop().unwrap();
+            // marked when explored rather than when pushed, so that a state
+            // reachable from both outputs of a split is tried from the first
+            if !cache.visited.insert(state) {
+                continue;
+            }
 
             if state.is_match() {
-                let num_chars_remaining = state.text.len();
-                return Some(self.text.len() - num_chars_remaining);
+                observer.matched(state.position);
+                return Ok(Some(state.position));
             }
 
-            let mut new_states = state.advance(&self.nfa);
+            let id = state.current_state.unwrap();
+            budget.step()?;
+            if state.position < self.text.len() && self.reads(id) {
+                budget.read(state.position)?;
+            }
+
+            observer.thread(id, state.position);
+            state.advance(self.nfa, self.text, &mut cache.successors);
+            for next in &cache.successors {
+                let byte = if next.position > state.position { Some(self.text[state.position]) } else { None };
+                observer.transition(id, state.position, next.current_state, byte);
+            }
             // states are in order of greediness
-            new_states.reverse(); 
+            while let Some(state) = cache.successors.pop() {
+                if !cache.visited.contains(&state) {
+                    cache.stack.push(state);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // whether a thread at state id looks at the next byte
+    fn reads(&self, id: usize) -> bool {
+        if self.nfa.epsilon_closure(id).is_some() {
+            return false;
+        }
+        match *self.nfa.get_state(id).unwrap() {
+            State::State{ref condition, ..} | State::Fork{ref condition, ..} => *condition != Condition::None,
+            State::Split{..} | State::Save{..} => false
+        }
+    }
+
+    // the leftmost match starting at or after `first`, as start and end
+    // offsets. Rather than running from each start in turn, the threads of
+    // every start step through text together, ordered as run() would try
+    // them, so a thread reaching a state another already holds is dropped.
+    // Starts before the next prefilter candidate are skipped while no
+    // thread is alive. A step is a thread moved over a byte
+    pub fn find_limited(&self, cache: &mut Cache, first: usize, prefilter: Option<&Prefilter>,
+                        budget: &mut Budget) -> Result<Option<(usize, usize)>, MatchError> {
+        if self.nfa.num_states() == 0 { // regex is empty
+            return Ok(Some((first, first)));
+        }
+
+        cache.clear();
+        cache.added.resize(self.nfa.num_states(), usize::MAX);
+
+        let mut found = None;
+        let mut position = first;
+        loop {
+            // a match starting later is never leftmost
+            if found.is_none() {
+                if cache.threads.is_empty() {
+                    if let Some(prefilter) = prefilter {
+                        position = match prefilter.find(self.text, position) {
+                            Some(position) => position,
+                            None => return Ok(None)
+                        };
+                    }
+                }
 
-            for state in new_states {
+                if self.add_threads(cache, false, position, Transition::Id(self.nfa.start()), position) {
+                    found = Some((position, position));
+                }
+            }
 
-                if !set.contains(&state) {
-                    states.push(state.clone());
-                    set.insert(state);
+            if cache.threads.is_empty() || position == self.text.len() {
+                return Ok(found);
+            }
+
+            budget.take(cache.threads.len())?;
+            budget.read(position)?;
+            let byte = self.text[position];
+            for i in 0..cache.threads.len() {
+                let thread = cache.threads[i];
+                let outs = match *self.nfa.get_state(thread.state).unwrap() {
+                    State::State{ref condition, ref out} if condition.matches(byte) => slice::from_ref(out),
+                    State::Fork{ref condition, ref outs} if condition.matches(byte) => &outs[..],
+                    _ => continue
+                };
+
+                // the threads after this one only lead to worse matches
+                if outs.iter().any(|out| self.add_threads(cache, true, thread.start, out.clone(), position + 1)) {
+                    found = Some((thread.start, position + 1));
+                    break;
                 }
             }
+
+            mem::swap(&mut cache.threads, &mut cache.next_threads);
+            cache.next_threads.clear();
+            position += 1;
+        }
+    }
+
+    // follows the empty edges from `out` in the order run() tries them,
+    // giving each consuming state reached a thread at `position` unless it
+    // has one. Returns whether an end was reached, which stops the walk
+    fn add_threads(&self, cache: &mut Cache, next: bool, start: usize, out: Transition, position: usize) -> bool {
+        let threads = if next { &mut cache.next_threads } else { &mut cache.threads };
+
+        cache.edges.clear();
+        cache.edges.push(out);
+        while let Some(out) = cache.edges.pop() {
+            let id = match out {
+                Transition::Id(id) => id,
+                Transition::End | Transition::Match(_) => return true,
+                Transition::Detached => panic!("cannot evaluate incomplete NFA")
+            };
+
+            if cache.added[id] == position {
+                continue;
+            }
+            cache.added[id] = position;
+
+            match *self.nfa.get_state(id).unwrap() {
+                State::Split{ref out1, ref out2} => {
+                    let (first, second) = self.nfa.split_order(out1, out2);
+                    cache.edges.push(second.clone());
+                    cache.edges.push(first.clone());
+                },
+                State::State{condition: Condition::None, ref out} | State::Save{ref out, ..} => {
+                    cache.edges.push(out.clone());
+                },
+                State::Fork{condition: Condition::None, ref outs} => {
+                    cache.edges.extend(outs.iter().rev().cloned());
+                },
+                State::State{..} | State::Fork{..} => threads.push(Thread { state: id, start })
+            }
         }
 
-        None
+        false
     }
 }
-
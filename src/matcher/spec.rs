@@ -1,24 +1,27 @@
+use ::expr::Expr;
+use ::literal::Prefilter;
 use ::nfa::{NFA, State, Transition, Condition};
-use super::{Matcher, PotentialMatch};
+use ::limits::{Limits, Budget, MatchError};
+use super::{Matcher, PotentialMatch, Cache, Observer, Trace, Edge};
+
+fn advance(m: PotentialMatch, nfa: &NFA, text: &str) -> Vec<PotentialMatch> {
+    let mut result = Vec::new();
+    m.advance(nfa, text.as_bytes(), &mut result);
+    result
+}
 
 #[test]
 fn is_match() {
-    let m = PotentialMatch::new(None, "");
+    let m = PotentialMatch::new(None, 0);
     assert_eq!(true, m.is_match());
 
     let nfa = NFA::new();
-    assert_eq!(vec![m.clone()], m.advance(&nfa));
+    assert_eq!(vec![m], advance(m, &nfa, ""));
 }
 
 #[test]
 fn is_state_match() {
-    let m = PotentialMatch::new(Some(State::state(Condition::one('a'), Transition::End)), "");
-    assert_eq!(false, m.is_match());
-}
-
-#[test]
-fn is_inconclusive() {
-    let m = PotentialMatch::new(Some(State::state(Condition::None, Transition::End)), "a");
+    let m = PotentialMatch::new(Some(0), 0);
     assert_eq!(false, m.is_match());
 }
 
@@ -28,12 +31,9 @@ fn advance_to_end() {
         State::state(Condition::one('a'), Transition::End)
     ]);
 
-    let m = PotentialMatch::new(nfa.get_start(),
-                                "a");
-    assert_eq!(vec![PotentialMatch::new(None,"")], m.advance(&nfa));
-    let m = PotentialMatch::new(nfa.get_start(),
-                                "");
-    assert_eq!(Vec::<PotentialMatch>::new(), m.advance(&nfa));
+    let m = PotentialMatch::new(Some(nfa.start()), 0);
+    assert_eq!(vec![PotentialMatch::new(None, 1)], advance(m, &nfa, "a"));
+    assert_eq!(Vec::<PotentialMatch>::new(), advance(m, &nfa, ""));
 }
 
 #[test]
@@ -43,16 +43,13 @@ fn advance_to_next() {
         State::state(Condition::one('b'), Transition::End)
     ]);
 
-    let expected_state = nfa.get_state(1).clone().unwrap();
-    let m = PotentialMatch::new(nfa.get_start(), "ab");
-    assert_eq!(vec![PotentialMatch::new(Some(expected_state),
-                                        "b")],
-                                        m.advance(&nfa));
+    let m = PotentialMatch::new(Some(nfa.start()), 0);
+    assert_eq!(vec![PotentialMatch::new(Some(1), 1)],
+               advance(m, &nfa, "ab"));
 
-    let m = PotentialMatch::new(nfa.get_state(1), "b");
-    let final_state = m.advance(&nfa);
+    let final_state = advance(PotentialMatch::new(Some(1), 1), &nfa, "ab");
 
-    assert_eq!(vec![PotentialMatch::new(None, "")],
+    assert_eq!(vec![PotentialMatch::new(None, 2)],
         final_state);
     assert!(final_state[0].is_match());
 }
@@ -64,15 +61,12 @@ fn advance_option_greedily() {
         State::state(Condition::one('a'), Transition::End),
     ]);
 
-    let expected_state = nfa.get_state(1).clone().unwrap();
-    let m = PotentialMatch::new(nfa.get_start(), "a");
+    let m = PotentialMatch::new(Some(nfa.start()), 0);
 
-    let actual = m.advance(&nfa);
-    println!("{:#?}", actual);
-    assert_eq!(PotentialMatch::new(Some(expected_state), "a"),
-               actual[0]);
-    assert_eq!(PotentialMatch::new(None, "a"),
-               actual[1]);
+    let actual = advance(m, &nfa, "a");
+    assert_eq!(vec![PotentialMatch::new(Some(1), 0),
+                    PotentialMatch::new(None, 0)],
+               actual);
 }
 
 #[test]
@@ -83,17 +77,13 @@ fn advance_greedily() {
         State::state(Condition::None, Transition::End),
     ]);
 
-    let state_1 = nfa.get_state(1).clone().unwrap();
-    let state_2 = nfa.get_state(2).clone().unwrap();
-
-    let m = PotentialMatch::new(nfa.get_start(), "a");
+    let m = PotentialMatch::new(Some(nfa.start()), 0);
 
-    let actual = m.advance(&nfa);
-    println!("{:#?}", actual);
-    assert_eq!(PotentialMatch::new(Some(state_1), "a"),
-               actual[0]);
-    assert_eq!(PotentialMatch::new(Some(state_2), "a"),
-               actual[1]);
+    // the empty state is skipped by the precomputed closure
+    let actual = advance(m, &nfa, "a");
+    assert_eq!(vec![PotentialMatch::new(Some(1), 0),
+                    PotentialMatch::new(None, 0)],
+               actual);
 }
 
 #[test]
@@ -101,8 +91,19 @@ fn advance_fail() {
     let nfa = NFA::from_states(vec![
         State::state(Condition::one('b'), Transition::End)
     ]);
-    let m = PotentialMatch::new(Some(nfa.get_state(0).clone().unwrap()), "a");
-    assert_eq!(Vec::<PotentialMatch>::new(), m.advance(&nfa));
+    let m = PotentialMatch::new(Some(0), 0);
+    assert_eq!(Vec::<PotentialMatch>::new(), advance(m, &nfa, "a"));
+}
+
+#[test]
+fn advance_appends_to_result() {
+    let nfa = NFA::from_states(vec![
+        State::state(Condition::one('a'), Transition::End)
+    ]);
+
+    let mut result = vec![PotentialMatch::new(None, 0)];
+    PotentialMatch::new(Some(0), 0).advance(&nfa, b"a", &mut result);
+    assert_eq!(vec![PotentialMatch::new(None, 0), PotentialMatch::new(None, 1)], result);
 }
 
 #[test]
@@ -114,15 +115,14 @@ fn advances_with_split() { // '(a|b)c'
         State::state(Condition::one('c'), Transition::End)
     ]);
 
-    let m = PotentialMatch::new(nfa.get_start(), "ac");
+    let m = PotentialMatch::new(Some(nfa.start()), 0);
     assert_eq!(vec![
-               PotentialMatch::new(nfa.get_state(3), "c")
-    ], m.advance(&nfa)[0].advance(&nfa));
+               PotentialMatch::new(Some(3), 1)
+    ], advance(advance(m, &nfa, "ac")[0], &nfa, "ac"));
 
-    let m = PotentialMatch::new(nfa.get_start(), "bc");
     assert_eq!(vec![
-               PotentialMatch::new(nfa.get_state(3), "c")
-    ], m.advance(&nfa)[1].advance(&nfa))
+               PotentialMatch::new(Some(3), 1)
+    ], advance(advance(m, &nfa, "bc")[1], &nfa, "bc"))
 }
 
 
@@ -132,17 +132,16 @@ fn is_match_with_split() {
         State::split(Transition::End, Transition::End)
     ]);
 
-    let m = PotentialMatch::new(nfa.get_start(), "a");
-    assert!(m.advance(&nfa)[0].is_match());
-    let m = PotentialMatch::new(nfa.get_start(), "b");
-    assert!(m.advance(&nfa)[0].is_match())
+    let m = PotentialMatch::new(Some(nfa.start()), 0);
+    assert!(advance(m, &nfa, "a")[0].is_match());
+    assert!(advance(m, &nfa, "b")[0].is_match())
 }
 
 #[test]
 fn empty_nfa_matches() {
     let nfa = NFA::from_states(vec![]);
 
-    assert!(Matcher::new(nfa, "").run().is_some());
+    assert!(Matcher::new(&nfa, "").run(&mut Cache::new()).is_some());
 }
 
 #[test]
@@ -151,7 +150,7 @@ fn single_state_matches() {
         State::state(Condition::None, Transition::End)
     ]);
 
-    assert!(Matcher::new(nfa, "a").run().is_some());
+    assert!(Matcher::new(&nfa, "a").run(&mut Cache::new()).is_some());
 }
 
 #[test]
@@ -160,8 +159,8 @@ fn single_char_nfa_matches() {
         State::state(Condition::one('a'), Transition::End)
     ]);
 
-    assert!(Matcher::new(nfa.clone(), "a").run().is_some());
-    assert!(!Matcher::new(nfa.clone(), "").run().is_some());
+    assert!(Matcher::new(&nfa, "a").run(&mut Cache::new()).is_some());
+    assert!(!Matcher::new(&nfa, "").run(&mut Cache::new()).is_some());
 }
 
 #[test]
@@ -171,8 +170,8 @@ fn null_edge_matches() {
         State::state(Condition::one('a'), Transition::End)
     ]);
 
-    assert!(Matcher::new(nfa.clone(), "a").run().is_some());
-    assert!(!Matcher::new(nfa.clone(), "").run().is_some());
+    assert!(Matcher::new(&nfa, "a").run(&mut Cache::new()).is_some());
+    assert!(!Matcher::new(&nfa, "").run(&mut Cache::new()).is_some());
 }
 
 #[test]
@@ -181,8 +180,111 @@ fn char_class_matches() {
         State::state(Condition::class(vec!['a', 'b']),
            Transition::End)]);
 
-    assert!(Matcher::new(nfa.clone(), "a").run().is_some());
-    assert!(Matcher::new(nfa, "b").run().is_some());
+    assert!(Matcher::new(&nfa, "a").run(&mut Cache::new()).is_some());
+    assert!(Matcher::new(&nfa, "b").run(&mut Cache::new()).is_some());
+}
+
+#[test]
+fn reuses_cache_between_runs() {
+    let nfa = NFA::from_states(vec![
+        State::split(Transition::Id(1), Transition::End),
+        State::state(Condition::one('a'), Transition::Id(0))
+    ]);
+
+    let mut cache = Cache::new();
+    assert_eq!(Some(3), Matcher::new(&nfa, "aaab").run(&mut cache));
+    assert_eq!(Some(1), Matcher::new(&nfa, "ab").run(&mut cache));
+    assert_eq!(Some(0), Matcher::new(&nfa, "").run(&mut cache));
+}
+
+// the leftmost match found by running from every start in turn
+fn find_by_starts(nfa: &NFA, text: &[u8]) -> Option<(usize, usize)> {
+    (0..text.len() + 1).filter_map(|start| {
+        Matcher::new(nfa, &text[start..]).run(&mut Cache::new()).map(|end| (start, start + end))
+    }).next()
+}
+
+#[test]
+fn find_agrees_with_run_from_each_start() {
+    let patterns = ["b+", "a*b", "(ab|a)(bc|c)*", "x*", "é.", "ab|a", "a|ab", "(a|b)*c", "ca?|cab"];
+    let texts = ["", "aab", "xabcbcd", "aéé", "ccc", "bbabcab", "cabca"];
+
+    let mut cache = Cache::new();
+    for pattern in patterns.iter() {
+        let expr = pattern.parse::<Expr>().unwrap();
+        let nfa = NFA::from_expr(&expr);
+        let prefilter = Prefilter::from_expr(&expr);
+
+        for text in texts.iter() {
+            let expected = find_by_starts(&nfa, text.as_bytes());
+            assert_eq!(Ok(expected), Matcher::new(&nfa, text).find_limited(&mut cache, 0, None, &mut Budget::unlimited()), "{} on {:?}", pattern, text);
+            assert_eq!(Ok(expected), Matcher::new(&nfa, text).find_limited(&mut cache, 0, prefilter.as_ref(), &mut Budget::unlimited()), "{} on {:?}", pattern, text);
+        }
+    }
 }
 
+#[test]
+fn find_starts_at_first() {
+    let nfa = NFA::from_expr(&"ab".parse::<Expr>().unwrap());
+    assert_eq!(Ok(Some((3, 5))), Matcher::new(&nfa, "ab ab").find_limited(&mut Cache::new(), 1, None, &mut Budget::unlimited()));
+    assert_eq!(Ok(None), Matcher::new(&nfa, "ab ab").find_limited(&mut Cache::new(), 4, None, &mut Budget::unlimited()));
+}
 
+#[test]
+fn traces_threads_and_transitions() {
+    let nfa = NFA::from_expr(&"a*b".parse::<Expr>().unwrap());
+    let mut trace = Trace::new();
+    assert_eq!(Some(3), Matcher::new(&nfa, "aab").run_observed(&mut Cache::new(), &mut trace));
+
+    assert_eq!(vec![1, 0, 2], trace.steps[2].threads);
+    assert_eq!(Edge { from: 2, to: None, byte: Some(b'b') }, *trace.steps[2].edges.last().unwrap());
+    assert_eq!("0: threads 1, 0; 1 -> 0, 1 -> 2, 0 -a-> 1\n\
+                1: threads 1, 0; 1 -> 0, 1 -> 2, 0 -a-> 1\n\
+                2: threads 1, 0, 2; 1 -> 0, 1 -> 2, 2 -b-> end\n\
+                match at 3\n", trace.to_string());
+
+    let mut trace = Trace::new();
+    assert_eq!(None, Matcher::new(&nfa, "c").run_observed(&mut Cache::new(), &mut trace));
+    assert_eq!("0: threads 1, 0, 2; 1 -> 0, 1 -> 2\nno match\n", trace.to_string());
+}
+
+#[test]
+fn observes_with_custom_observer() {
+    // counts the threads explored at each position
+    struct Counter(Vec<usize>);
+    impl Observer for Counter {
+        fn thread(&mut self, _state: usize, position: usize) {
+            if self.0.len() <= position {
+                self.0.resize(position + 1, 0);
+            }
+            self.0[position] += 1;
+        }
+    }
+
+    let nfa = NFA::from_expr(&"(a|ab)c".parse::<Expr>().unwrap());
+    let mut counter = Counter(Vec::new());
+    assert_eq!(Some(3), Matcher::new(&nfa, "abc").run_observed(&mut Cache::new(), &mut counter));
+    assert_eq!(vec![3, 3, 2], counter.0);
+}
+
+#[test]
+fn stops_when_limits_are_exceeded() {
+    let nfa = NFA::from_expr(&"a*b".parse::<Expr>().unwrap());
+    let matcher = Matcher::new(&nfa, "aaaab");
+    assert_eq!(Ok(Some(5)), matcher.run_limited(&mut Cache::new(), &mut Budget::new(Limits::new().steps(20).bytes(5))));
+    assert_eq!(Err(MatchError::BudgetExceeded), matcher.run_limited(&mut Cache::new(), &mut Budget::new(Limits::new().steps(5))));
+    assert_eq!(Err(MatchError::BudgetExceeded), matcher.run_limited(&mut Cache::new(), &mut Budget::new(Limits::new().bytes(4))));
+
+    // a match found without reading past the limit is still returned
+    let nfa = NFA::from_expr(&"ab".parse::<Expr>().unwrap());
+    assert_eq!(Ok(Some(2)), Matcher::new(&nfa, "abc").run_limited(&mut Cache::new(), &mut Budget::new(Limits::new().bytes(2))));
+}
+
+#[test]
+fn stops_finding_when_limits_are_exceeded() {
+    let nfa = NFA::from_expr(&"a*b".parse::<Expr>().unwrap());
+    let matcher = Matcher::new(&nfa, "xxaab");
+    assert_eq!(Ok(Some((2, 5))), matcher.find_limited(&mut Cache::new(), 0, None, &mut Budget::new(Limits::new().bytes(5))));
+    assert_eq!(Err(MatchError::BudgetExceeded), matcher.find_limited(&mut Cache::new(), 0, None, &mut Budget::new(Limits::new().steps(3))));
+    assert_eq!(Err(MatchError::BudgetExceeded), matcher.find_limited(&mut Cache::new(), 0, None, &mut Budget::new(Limits::new().bytes(4))));
+}
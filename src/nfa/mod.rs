@@ -1,6 +1,11 @@
+use std::fmt;
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 
 use ::expr::Expr;
+use ::glushkov::Positions;
+use ::limits::{CompileLimits, CompileError};
+use ::format::{self, Reader};
 
 #[cfg(test)] mod spec;
 
@@ -27,6 +32,56 @@ impl Condition {
 
         Condition::Class(ascii_bytes)
     }
+
+    pub fn matches(&self, byte: u8) -> bool {
+        match *self {
+            Condition::One(c) => c == byte,
+            Condition::Class(ref bytes) => bytes.contains(&byte),
+            Condition::Any => byte != b'\n', // `.` should not match newline
+            Condition::None => false
+        }
+    }
+
+    // key by greediness and lexographical order of condition char
+    pub fn priority(&self) -> usize {
+        match *self {
+            Condition::One(c) => c as usize, // there is a cost
+            Condition::Any | Condition::Class(_) => 0, // prioritize any
+            Condition::None => usize::MAX // state terminates with no cost
+        }
+    }
+
+    fn label(&self) -> String {
+        match *self {
+            Condition::One(byte) => bytes_label(&[byte]),
+            Condition::Class(ref bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.sort();
+                bytes.dedup();
+                bytes_label(&bytes)
+            },
+            Condition::Any => ".".to_owned(),
+            Condition::None => "ε".to_owned()
+        }
+    }
+}
+
+// sorted bytes written as in a pattern, e.g. `a` or `[0-9a-f\x80]`
+pub fn bytes_label(bytes: &[u8]) -> String {
+    let escape = |byte: u8| match byte {
+        b'\\' | b'[' | b']' | b'-' | b'.' => format!("\\{}", byte as char),
+        byte if byte.is_ascii_graphic() => (byte as char).to_string(),
+        byte => format!("\\x{:02X}", byte)
+    };
+    if bytes.len() == 1 {
+        return escape(bytes[0]);
+    }
+    format!("[{}]", byte_ranges(bytes, escape, ""))
+}
+
+// a quoted string in graphviz, where backslashes start escapes
+pub fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
 }
 
 
@@ -34,14 +89,18 @@ impl Condition {
 pub enum Transition {
     Id(usize),
     Detached,
-    End
+    End,
+    Match(usize) // end of the pattern with this index in an NFA built from several
 }
 
 
 #[derive(PartialEq,Debug,Clone,Eq,Hash)]
+#[allow(clippy::enum_variant_names)] // a State::State consumes a byte
 pub enum State {
     State{condition: Condition, out: Transition},
-    Split{out1: Transition, out2: Transition}
+    Split{out1: Transition, out2: Transition},
+    Save{slot: usize, out: Transition}, // records the position in a capture slot
+    Fork{condition: Condition, outs: Vec<Transition>} // continues at each of outs in turn
 }
 
 impl State {
@@ -54,201 +113,1245 @@ impl State {
                      out2: out2}
     }
 
-    pub fn get_priority_key(&self, nfa: &NFA) -> usize { 
-        // key by greediness and lexographical order of condition char
-       
-        match self {
-            &State::State{ref condition, ref out} => {
-                Self::get_transition_priority_key(condition, out, nfa)
-            },
-            &State::Split{ref out1, ref out2} => {
-                cmp::min(
-                    Self::get_transition_priority_key(&Condition::None, out1, nfa),
-                    Self::get_transition_priority_key(&Condition::None, out2, nfa))
+    pub fn save(slot: usize, out: Transition) -> State {
+        State::Save{slot, out}
+    }
+
+    pub fn fork(condition: Condition, outs: Vec<Transition>) -> State {
+        State::Fork{condition, outs}
+    }
+
+    // the condition a byte is consumed on, none for states left along empty edges
+    fn consumes(&self) -> Option<&Condition> {
+        match *self {
+            State::State{condition: Condition::None, ..} | State::Fork{condition: Condition::None, ..} => None,
+            State::State{ref condition, ..} | State::Fork{ref condition, ..} => Some(condition),
+            State::Split{..} | State::Save{..} => None
+        }
+    }
+
+    fn outs(&self) -> Vec<&Transition> {
+        match *self {
+            State::State{ref out, ..} | State::Save{ref out, ..} => vec![out],
+            State::Split{ref out1, ref out2} => vec![out1, out2],
+            State::Fork{ref outs, ..} => outs.iter().collect()
+        }
+    }
+
+    fn outs_mut(&mut self) -> Vec<&mut Transition> {
+        match *self {
+            State::State{ref mut out, ..} | State::Save{ref mut out, ..} => vec![out],
+            State::Split{ref mut out1, ref mut out2} => vec![out1, out2],
+            State::Fork{ref mut outs, ..} => outs.iter_mut().collect()
+        }
+    }
+}
+
+// the consuming states reachable from a set of transitions along empty edges
+#[derive(PartialEq,Debug,Clone,Eq,Hash)]
+pub struct Closure {
+    pub states: Vec<usize>, // sorted ids of states with a non-empty condition
+    pub matches: Vec<usize> // sorted indices of the patterns whose end is reachable
+}
+
+impl Closure {
+    pub fn is_match(&self) -> bool {
+        !self.matches.is_empty()
+    }
+}
+
+// partition of the 256 byte values into classes that no condition distinguishes
+#[derive(PartialEq,Clone)]
+pub struct ByteClasses {
+    classes: Vec<u8>, // class of each byte, numbered in order of their smallest byte
+    num_classes: usize
+}
+
+impl ByteClasses {
+    pub fn from_conditions(conditions: &[&Condition]) -> ByteClasses {
+        // bytes are in the same class when every condition agrees on them.
+        // A long pattern repeats a few conditions, so only distinct ones
+        // are asked
+        let conditions = conditions.iter().collect::<HashSet<_>>();
+        let mut ids = HashMap::new();
+        let classes = (0..256)
+            .map(|byte| {
+                let signature = conditions.iter()
+                    .map(|condition| condition.matches(byte as u8))
+                    .collect::<Vec<_>>();
+                let next_id = ids.len();
+                *ids.entry(signature).or_insert(next_id) as u8
+            })
+            .collect();
+
+        ByteClasses {
+            classes,
+            num_classes: ids.len()
+        }
+    }
+
+    // the class of each byte, numbered as from_conditions numbers them
+    pub fn from_table(classes: &[u8]) -> ByteClasses {
+        ByteClasses {
+            classes: classes.to_vec(),
+            num_classes: classes.iter().map(|&class| class as usize + 1).max().unwrap_or(0)
+        }
+    }
+
+    pub fn get(&self, byte: u8) -> usize {
+        self.classes[byte as usize] as usize
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    pub fn members(&self, class: usize) -> Vec<u8> {
+        (0..256)
+            .map(|byte| byte as u8)
+            .filter(|&byte| self.get(byte) == class)
+            .collect()
+    }
+
+    // the smallest byte of each class
+    pub fn representatives(&self) -> Vec<u8> {
+        let mut representatives = Vec::with_capacity(self.num_classes);
+        for byte in 0..256 {
+            if self.get(byte as u8) == representatives.len() {
+                representatives.push(byte as u8);
             }
         }
+        representatives
     }
+}
 
-    fn get_transition_priority_key(condition: &Condition, out: &Transition, nfa: &NFA) -> usize {
-        match condition {
-            &Condition::One(c) => c as usize, // there is a cost
-            &Condition::Any => 0, // prioritize any
-            &Condition::None => {
-                match out {
-                    &Transition::Id(id) => {
-                        let next_state = nfa.get_state(id).unwrap();
-                        next_state.get_priority_key(&nfa)
-                    },
-                    _ => usize::max_value() // state terminates with no cost
-                }
-            },
-            &Condition::Class(ref chars) => 0
+impl fmt::Debug for ByteClasses {
+    // lists each class as its ranges of bytes, e.g. `[0-96, 99-255] [97] [98]`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for class in 0..self.num_classes {
+            if class > 0 {
+                write!(f, " ")?;
+            }
+
+            write!(f, "[{}]", byte_ranges(&self.members(class), |byte| byte.to_string(), ", "))?;
+        }
+        Ok(())
+    }
+}
+
+// sorted bytes as runs of consecutive bytes, each written as its first and
+// last byte joined by `-`, e.g. `0-9, 97` with a separator of `, `
+pub fn byte_ranges<F: Fn(u8) -> String>(bytes: &[u8], write: F, separator: &str) -> String {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for i in 1..bytes.len() + 1 {
+        if i == bytes.len() || bytes[i] != bytes[i - 1] + 1 {
+            ranges.push(if start == i - 1 {
+                write(bytes[start])
+            } else {
+                format!("{}-{}", write(bytes[start]), write(bytes[i - 1]))
+            });
+            start = i;
+        }
+    }
+    ranges.join(separator)
+}
+
+// the conditions a leaf reads in turn, for each of its alternatives. A
+// char reads the bytes of its utf-8 encoding, and a non-ascii class or `.`
+// tries each encoding
+pub fn byte_sequences(expr: &Expr) -> Vec<Vec<Condition>> {
+    match *expr {
+        Expr::Single(c) => vec![utf8_conditions(c)],
+        Expr::Class(ref chars) => {
+            let (ascii, other): (Vec<char>, Vec<char>) = chars.iter().partition(|c| c.is_ascii());
+
+            let mut alternatives = Vec::new();
+            if !ascii.is_empty() || other.is_empty() {
+                alternatives.push(vec![Condition::class(ascii)]);
+            }
+            alternatives.extend(other.into_iter().map(utf8_conditions));
+            alternatives
+        },
+        Expr::Any => {
+            // any utf-8 encoded char but newline, checked loosely by its lead byte
+            let continuation = Condition::Class((0x80..0xC0).collect());
+            let ascii = (0..0x80).filter(|&b| b != b'\n').collect();
+
+            vec![
+                vec![Condition::Class(ascii)],
+                vec![Condition::Class((0xC2..0xE0).collect()), continuation.clone()],
+                vec![Condition::Class((0xE0..0xF0).collect()), continuation.clone(), continuation.clone()],
+                vec![Condition::Class((0xF0..0xF5).collect()), continuation.clone(), continuation.clone(), continuation]
+            ]
+        },
+        Expr::Byte(b) => vec![vec![Condition::One(b)]],
+        Expr::ByteClass(ref bytes) => vec![vec![Condition::Class(bytes.clone())]],
+        Expr::AnyByte => vec![vec![Condition::Any]],
+        _ => panic!("not a leaf")
+    }
+}
+
+// the states a leaf is built into: a state per condition and a split
+// between each of its alternatives
+pub fn leaf_states(expr: &Expr) -> usize {
+    match *expr {
+        Expr::Class(ref chars) => {
+            // counted without building the conditions, as a class can be large
+            let other = chars.iter().filter(|c| !c.is_ascii()).map(|c| c.len_utf8()).collect::<Vec<_>>();
+            let ascii = if chars.len() > other.len() || other.is_empty() { 1 } else { 0 };
+            ascii + other.iter().sum::<usize>() + ascii + other.len() - 1
+        },
+        _ => {
+            let alternatives = byte_sequences(expr);
+            alternatives.iter().map(|conditions| conditions.len()).sum::<usize>() + alternatives.len() - 1
         }
     }
 }
 
+fn utf8_conditions(c: char) -> Vec<Condition> {
+    c.encode_utf8(&mut [0; 4]).bytes().map(Condition::One).collect()
+}
+
+const NFA_MAGIC: &[u8; 4] = b"RXNF";
+
+// conditions and transitions are a tag byte, then their contents
+fn write_condition(out: &mut Vec<u8>, condition: &Condition) {
+    match *condition {
+        Condition::One(byte) => out.extend_from_slice(&[0, byte]),
+        Condition::Class(ref bytes) => {
+            out.push(1);
+            format::write_u32(out, bytes.len());
+            out.extend_from_slice(bytes);
+        },
+        Condition::Any => out.push(2),
+        Condition::None => out.push(3)
+    }
+}
+
+fn read_condition(reader: &mut Reader) -> Result<Condition, String> {
+    match reader.u8()? {
+        0 => Ok(Condition::One(reader.u8()?)),
+        1 => {
+            let len = reader.u32()?;
+            Ok(Condition::Class(reader.bytes(len)?.to_vec()))
+        },
+        2 => Ok(Condition::Any),
+        3 => Ok(Condition::None),
+        tag => Err(format!("unknown condition tag {}", tag))
+    }
+}
+
+fn write_transition(out: &mut Vec<u8>, transition: &Transition) {
+    match *transition {
+        Transition::Id(id) => {
+            out.push(0);
+            format::write_u32(out, id);
+        },
+        Transition::End => out.push(1),
+        Transition::Match(pattern) => {
+            out.push(2);
+            format::write_u32(out, pattern);
+        },
+        Transition::Detached => panic!("cannot serialize incomplete NFA")
+    }
+}
+
+fn read_transition(reader: &mut Reader, num_states: usize) -> Result<Transition, String> {
+    match reader.u8()? {
+        0 => Ok(Transition::Id(reader.index(num_states, "state")?)),
+        1 => Ok(Transition::End),
+        2 => Ok(Transition::Match(reader.u32()?)),
+        tag => Err(format!("unknown transition tag {}", tag))
+    }
+}
+
 #[derive(PartialEq,Debug,Clone)]
 pub struct NFA {
     start: usize,
-    states: Vec<State>
+    states: Vec<State>,
+    classes: ByteClasses,
+    captures: usize, // capture groups, including group 0 for the whole match
+    priorities: Vec<usize>, // priority key of each state, see split_order
+    closures: Vec<Option<Vec<Transition>>> // ordered closures of the states searches enter
 }
 
+// the most states walked to precompute an epsilon closure. Longer walks,
+// e.g. along the splits of `a?a?a?...`, would keep a closure per state as
+// long as the NFA
+const MAX_CLOSURE_WALK: usize = 64;
+
 impl NFA {
 
     pub fn new() -> NFA {
         NFA {
             start: 0,
-            states: Vec::new()
+            states: Vec::new(),
+            classes: ByteClasses::from_conditions(&[]),
+            captures: 1,
+            priorities: Vec::new(),
+            closures: Vec::new()
         }
     }
 
     pub fn from_states(states: Vec<State>) -> NFA {
-        NFA {
-            start: 0,
-            states: states
+        let mut nfa = NFA {
+            states,
+            ..NFA::new()
+        };
+        nfa.prepare();
+        nfa
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn get_start(&self) -> Option<&State> {
+        self.states.get(self.start)
+    }
+
+    pub fn get_state(&self, index: usize) -> Option<&State> {
+        self.states.get(index)
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    // two per capture group, the start and end of what it matched
+    pub fn num_slots(&self) -> usize {
+        2 * self.captures
+    }
+
+    pub fn byte_classes(&self) -> &ByteClasses {
+        &self.classes
+    }
+
+    pub fn priority(&self, id: usize) -> usize {
+        self.priorities[id]
+    }
+
+    // the consuming states and ends reached from state id along empty edges,
+    // in the order matchers try them. Only kept for the states a search
+    // enters, at the start or after consuming a byte, and when the walk is
+    // short. Otherwise searches follow the empty edges themselves
+    pub fn epsilon_closure(&self, id: usize) -> Option<&[Transition]> {
+        self.closures.get(id).and_then(|closure| closure.as_deref())
+    }
+
+    // everything that follows from the states
+    fn prepare(&mut self) {
+        self.classes = self.compute_byte_classes();
+        self.priorities = self.compute_priorities();
+        self.closures = self.compute_closures();
+    }
+
+    fn compute_byte_classes(&self) -> ByteClasses {
+        let conditions = self.states.iter()
+            .filter_map(State::consumes)
+            .collect::<Vec<_>>();
+
+        ByteClasses::from_conditions(&conditions)
+    }
+
+    // a consuming state is keyed by its condition, any other state by the
+    // least key it reaches along empty edges. Keys are handed back along
+    // empty edges from the least keyed consuming states, so each state is
+    // keyed once however the empty edges loop
+    fn compute_priorities(&self) -> Vec<usize> {
+        let mut predecessors = vec![Vec::new(); self.states.len()];
+        let mut sources = Vec::new();
+        for (id, state) in self.states.iter().enumerate() {
+            match state.consumes() {
+                Some(condition) => sources.push((condition.priority(), id)),
+                None => for out in state.outs() {
+                    if let Transition::Id(target) = *out {
+                        predecessors[target].push(id);
+                    }
+                }
+            }
+        }
+        sources.sort();
+
+        let mut priorities = vec![usize::MAX; self.states.len()];
+        let mut keyed = vec![false; self.states.len()];
+        for (priority, source) in sources {
+            priorities[source] = priority;
+            keyed[source] = true;
+
+            let mut stack = vec![source];
+            while let Some(id) = stack.pop() {
+                for &predecessor in &predecessors[id] {
+                    if !keyed[predecessor] {
+                        keyed[predecessor] = true;
+                        priorities[predecessor] = priority;
+                        stack.push(predecessor);
+                    }
+                }
+            }
         }
+
+        priorities
     }
 
-    pub fn get_start(&self) -> Option<State> {
-        if self.start >= self.states.len() {
-            None
-        } else {
-            Some(self.states[self.start].clone())
+    fn compute_closures(&self) -> Vec<Option<Vec<Transition>>> {
+        let mut closures = vec![None; self.states.len()];
+        if self.states.is_empty() {
+            return closures;
         }
+
+        let mut entered = vec![self.start];
+        for state in &self.states {
+            if state.consumes().is_some() {
+                entered.extend(state.outs().into_iter().filter_map(|out| match *out {
+                    Transition::Id(id) => Some(id),
+                    _ => None
+                }));
+            }
+        }
+
+        let mut visited = vec![false; self.states.len()];
+        for id in entered {
+            if closures[id].is_none() && self.states[id].consumes().is_none() {
+                closures[id] = self.ordered_closure(&[Transition::Id(id)], &mut visited, MAX_CLOSURE_WALK);
+            }
+        }
+        closures
     }
 
-    pub fn get_state(&self, index: usize) -> Option<State> {
-        if index >= self.states.len() {
+    // the consuming states and ends reachable from `from` along empty edges,
+    // in order of priority, none if that visits more than limit states.
+    // Clears the visited marks it sets
+    fn ordered_closure(&self, from: &[Transition], visited: &mut [bool], limit: usize) -> Option<Vec<Transition>> {
+        let mut result = Vec::new();
+        let mut touched = Vec::new();
+
+        let mut stack = from.iter().rev().cloned().collect::<Vec<_>>();
+        while let Some(transition) = stack.pop() {
+            if touched.len() > limit {
+                break;
+            }
+
+            let id = match transition {
+                Transition::Id(id) => id,
+                Transition::Detached => panic!("cannot evaluate incomplete NFA"),
+                end => {
+                    if !result.contains(&end) {
+                        result.push(end);
+                    }
+                    continue;
+                }
+            };
+
+            if visited[id] {
+                continue;
+            }
+            visited[id] = true;
+            touched.push(id);
+
+            match self.states[id] {
+                State::Split{ref out1, ref out2} => {
+                    let (first, second) = self.split_order(out1, out2);
+                    stack.push(second.clone());
+                    stack.push(first.clone());
+                },
+                ref state if state.consumes().is_some() => result.push(Transition::Id(id)),
+                ref state => stack.extend(state.outs().into_iter().rev().cloned())
+            }
+        }
+
+        let complete = touched.len() <= limit;
+        for id in touched {
+            visited[id] = false;
+        }
+        if complete {
+            Some(result)
+        } else {
             None
+        }
+    }
+
+    // skips empty edges that make no difference to matching, then drops
+    // the states that can no longer be reached
+    fn simplify(&mut self) {
+        if self.states.is_empty() {
+            return;
+        }
+        self.priorities = self.compute_priorities();
+
+        let bypasses = self.bypasses();
+        for state in self.states.iter_mut() {
+            for out in state.outs_mut() {
+                if let Transition::Id(id) = *out {
+                    *out = bypasses[id].clone();
+                }
+            }
+        }
+        if let Transition::Id(start) = bypasses[self.start] {
+            self.start = start;
+        }
+
+        // reachable states keep their order
+        let mut reachable = vec![false; self.states.len()];
+        reachable[self.start] = true;
+        let mut stack = vec![self.start];
+        while let Some(id) = stack.pop() {
+            for out in self.states[id].outs() {
+                if let Transition::Id(target) = *out {
+                    if !reachable[target] {
+                        reachable[target] = true;
+                        stack.push(target);
+                    }
+                }
+            }
+        }
+
+        let mut ids = vec![0; self.states.len()];
+        let mut states = Vec::new();
+        for (id, state) in self.states.drain(..).enumerate() {
+            if reachable[id] {
+                ids[id] = states.len();
+                states.push(state);
+            }
+        }
+        for state in states.iter_mut() {
+            for out in state.outs_mut() {
+                if let Transition::Id(id) = *out {
+                    *out = Transition::Id(ids[id]);
+                }
+            }
+        }
+        self.start = ids[self.start];
+        self.states = states;
+    }
+
+    // where a transition to each state can go instead: past empty states,
+    // splits with one output, and splits whose preferred output is a split
+    // that also leads to their other output, as from nested `?`. A state is
+    // walked past once, and the states on a walk share where it ended, as
+    // reversing a long alternation chains as many empty states
+    fn bypasses(&self) -> Vec<Transition> {
+        let mut bypasses: Vec<Option<Transition>> = vec![None; self.states.len()];
+        let mut walking = vec![false; self.states.len()];
+
+        for id in 0..self.states.len() {
+            let mut walked = Vec::new();
+            let mut transition = Transition::Id(id);
+            while let Transition::Id(id) = transition {
+                if let Some(ref bypass) = bypasses[id] {
+                    transition = bypass.clone();
+                    break;
+                }
+                if walking[id] { // empty edges can loop
+                    break;
+                }
+                walking[id] = true;
+                walked.push(id);
+
+                match self.skip(id) {
+                    Some(next) => transition = next,
+                    None => break
+                }
+            }
+
+            for id in walked {
+                walking[id] = false;
+                bypasses[id] = Some(transition.clone());
+            }
+        }
+
+        bypasses.into_iter().map(Option::unwrap).collect()
+    }
+
+    // the transition a transition to state id can take instead, if any
+    fn skip(&self, id: usize) -> Option<Transition> {
+        match self.states[id] {
+            State::State{condition: Condition::None, ref out} => Some(out.clone()),
+            State::Split{ref out1, ref out2} if out1 == out2 => Some(out1.clone()),
+            State::Split{ref out1, ref out2} => {
+                let (first, second) = self.split_order(out1, out2);
+                match *first {
+                    Transition::Id(inner) => match self.states[inner] {
+                        State::Split{ref out1, ref out2} if out1 == second || out2 == second => Some(first.clone()),
+                        _ => None
+                    },
+                    _ => None
+                }
+            },
+            _ => None
+        }
+    }
+
+    pub fn start_closure(&self) -> Closure {
+        if self.states.is_empty() { // regex is empty
+            return Closure {
+                states: Vec::new(),
+                matches: vec![0]
+            };
+        }
+
+        self.closure(&[Transition::Id(self.start)])
+    }
+
+    pub fn closure(&self, from: &[Transition]) -> Closure {
+        let mut visited = vec![false; self.states.len()];
+        let mut states = Vec::new();
+        let mut matches = Vec::new();
+
+        let mut stack = from.iter().rev().cloned().collect::<Vec<_>>();
+        while let Some(transition) = stack.pop() {
+            let id = match transition {
+                Transition::Id(id) => id,
+                Transition::End => {
+                    matches.push(0);
+                    continue;
+                },
+                Transition::Match(pattern) => {
+                    matches.push(pattern);
+                    continue;
+                },
+                Transition::Detached => panic!("cannot evaluate incomplete NFA")
+            };
+
+            if visited[id] {
+                continue;
+            }
+            visited[id] = true;
+
+            if let Some(closure) = self.epsilon_closure(id) {
+                for out in closure {
+                    match *out {
+                        Transition::Id(id) if !visited[id] => {
+                            visited[id] = true;
+                            states.push(id);
+                        },
+                        Transition::Id(_) => (),
+                        Transition::End => matches.push(0),
+                        Transition::Match(pattern) => matches.push(pattern),
+                        Transition::Detached => panic!("cannot evaluate incomplete NFA")
+                    }
+                }
+                continue;
+            }
+
+            match self.states[id] {
+                State::Split{ref out1, ref out2} => {
+                    stack.push(out2.clone());
+                    stack.push(out1.clone());
+                },
+                State::State{condition: Condition::None, ref out} | State::Save{ref out, ..} => {
+                    stack.push(out.clone());
+                },
+                State::Fork{condition: Condition::None, ref outs} => {
+                    stack.extend(outs.iter().rev().cloned());
+                },
+                State::State{..} | State::Fork{..} => states.push(id)
+            }
+        }
+
+        states.sort();
+        matches.sort();
+        matches.dedup();
+        Closure {
+            states,
+            matches
+        }
+    }
+
+    // closure of the states entered by consuming `byte` from any of `states`
+    pub fn step(&self, states: &[usize], byte: u8) -> Closure {
+        let next = states.iter()
+            .flat_map(|&id| match self.states[id] {
+                State::State{ref condition, ref out} if condition.matches(byte) => vec![out.clone()],
+                State::Fork{ref condition, ref outs} if condition.matches(byte) => outs.clone(),
+                _ => Vec::new()
+            })
+            .collect::<Vec<_>>();
+
+        self.closure(&next)
+    }
+
+    // the start of an unanchored leftmost-first search, see leftmost_step
+    pub fn leftmost_start(&self) -> Closure {
+        self.leftmost_closure(&[Transition::Id(self.start)], true)
+    }
+
+    // like step, for searches that find the match the matcher prefers. The
+    // states are in the order the matcher tries them, and the ones after a
+    // reachable end are dropped, as they only lead to matches it would not
+    // take. While no match has ended, the states finish with
+    // `self.num_states()`, a thread that starts a new match after each byte
+    pub fn leftmost_step(&self, states: &[usize], byte: u8) -> Closure {
+        let mut restart = false;
+        let next = states.iter()
+            .flat_map(|&id| match self.states.get(id) {
+                Some(&State::State{ref condition, ref out}) if condition.matches(byte) => vec![out.clone()],
+                Some(&State::Fork{ref condition, ref outs}) if condition.matches(byte) => outs.clone(),
+                Some(_) => Vec::new(),
+                None => {
+                    restart = true;
+                    vec![Transition::Id(self.start)]
+                }
+            })
+            .collect::<Vec<_>>();
+
+        self.leftmost_closure(&next, restart)
+    }
+
+    fn leftmost_closure(&self, from: &[Transition], restart: bool) -> Closure {
+        if self.states.is_empty() { // regex is empty
+            return Closure {
+                states: Vec::new(),
+                matches: vec![0]
+            };
+        }
+
+        let mut visited = vec![false; self.states.len()];
+        let mut states = Vec::new();
+
+        let mut stack = from.iter().rev().cloned().collect::<Vec<_>>();
+        while let Some(transition) = stack.pop() {
+            let id = match transition {
+                Transition::Id(id) => id,
+                Transition::End | Transition::Match(_) => {
+                    return Closure {
+                        states,
+                        matches: vec![0]
+                    };
+                },
+                Transition::Detached => panic!("cannot evaluate incomplete NFA")
+            };
+
+            if visited[id] {
+                continue;
+            }
+            visited[id] = true;
+
+            match self.states[id] {
+                State::Split{ref out1, ref out2} => {
+                    let (first, second) = self.split_order(out1, out2);
+                    stack.push(second.clone());
+                    stack.push(first.clone());
+                },
+                State::State{condition: Condition::None, ref out} | State::Save{ref out, ..} => {
+                    stack.push(out.clone());
+                },
+                State::Fork{condition: Condition::None, ref outs} => {
+                    stack.extend(outs.iter().rev().cloned());
+                },
+                State::State{..} | State::Fork{..} => states.push(id)
+            }
+        }
+
+        if restart {
+            states.push(self.states.len());
+        }
+        Closure {
+            states,
+            matches: Vec::new()
+        }
+    }
+
+    // the outputs of a split in the order matchers try them: by the priority
+    // key of the state each leads to, the first output on a tie
+    pub fn split_order<'t>(&self, out1: &'t Transition, out2: &'t Transition) -> (&'t Transition, &'t Transition) {
+        let key = |out: &Transition| match *out {
+            Transition::Id(id) => self.priorities[id],
+            _ => usize::MAX // this is an end state
+        };
+
+        if key(out2) < key(out1) {
+            (out2, out1)
         } else {
-            Some(self.states[index].clone())
+            (out1, out2)
+        }
+    }
+
+    // an NFA for the reversed language, matching text read backwards
+    pub fn reverse(&self) -> NFA {
+        if self.states.is_empty() {
+            return NFA::new();
+        }
+
+        // every edge of this NFA is reversed into an option of the state it
+        // led to. `end` stands for the end of a match
+        let end = self.states.len();
+        let mut options = vec![Vec::new(); end + 1];
+        options[self.start].push(State::state(Condition::None, Transition::End));
+
+        for (id, state) in self.states.iter().enumerate() {
+            let (condition, outs) = match *state {
+                State::State{ref condition, ref out} => (condition.clone(), vec![out]),
+                State::Split{ref out1, ref out2} => (Condition::None, vec![out1, out2]),
+                State::Save{ref out, ..} => (Condition::None, vec![out]), // captures are not reversed
+                State::Fork{ref condition, ref outs} => (condition.clone(), outs.iter().collect())
+            };
+            for out in outs {
+                let target = match *out {
+                    Transition::Id(target) => target,
+                    Transition::End | Transition::Match(_) => end,
+                    Transition::Detached => panic!("cannot reverse incomplete NFA")
+                };
+                options[target].push(State::state(condition.clone(), Transition::Id(id)));
+            }
+        }
+
+        // state i of the reversed NFA tries the options of state i, so
+        // reversed edges can point at ids before all of them are built
+        let mut nfa = NFA::new();
+        nfa.states = vec![State::state(Condition::Class(Vec::new()), Transition::End); end + 1];
+        for (id, mut options) in options.into_iter().enumerate() {
+            if options.len() > 1 {
+                let ids = options.into_iter().map(|option| nfa.push_state(option)).collect::<Vec<_>>();
+                let rest = nfa.build_splits(&ids[1..]);
+                nfa.states[id] = State::split(Transition::Id(ids[0]), Transition::Id(rest));
+            } else if let Some(option) = options.pop() {
+                nfa.states[id] = option;
+            }
         }
 
+        nfa.start = end;
+        nfa.simplify();
+        nfa.prepare();
+        nfa
     }
 
-    pub fn num_states(&self) -> usize {
-        self.states.len()
+    // a graphviz digraph of the states. Empty edges are dashed, and the
+    // edges leaving a split or fork are numbered in the order matchers try them
+    pub fn to_dot(&self) -> String {
+        let node = |out: &Transition| match *out {
+            Transition::Id(id) => id.to_string(),
+            Transition::End => "end".to_owned(),
+            Transition::Match(pattern) => format!("match{}", pattern),
+            Transition::Detached => "detached".to_owned()
+        };
+
+        // source, target, label and whether the edge is empty
+        let mut edges = Vec::new();
+        for (id, state) in self.states.iter().enumerate() {
+            match *state {
+                State::State{ref condition, ref out} => {
+                    edges.push((id, out, condition.label(), *condition == Condition::None));
+                },
+                State::Split{ref out1, ref out2} => {
+                    let (first, second) = self.split_order(out1, out2);
+                    edges.push((id, first, "ε 1".to_owned(), true));
+                    edges.push((id, second, "ε 2".to_owned(), true));
+                },
+                State::Save{slot, ref out} => edges.push((id, out, format!("save {}", slot), true)),
+                State::Fork{ref condition, ref outs} => for (i, out) in outs.iter().enumerate() {
+                    let label = if outs.len() == 1 {
+                        condition.label()
+                    } else {
+                        format!("{} {}", condition.label(), i + 1)
+                    };
+                    edges.push((id, out, label, *condition == Condition::None));
+                }
+            }
+        }
+
+        let mut ends = edges.iter()
+            .filter_map(|&(_, out, _, _)| match *out {
+                Transition::End | Transition::Match(_) => Some(node(out)),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        ends.sort();
+        ends.dedup();
+
+        let mut dot = "digraph nfa {\n    rankdir=LR;\n    node [shape=circle];\n    start [shape=point];\n".to_owned();
+        if self.states.is_empty() { // regex is empty
+            dot.push_str("    end [shape=doublecircle];\n    start -> end;\n");
+        } else {
+            for end in ends {
+                dot.push_str(&format!("    {} [shape=doublecircle];\n", end));
+            }
+            dot.push_str(&format!("    start -> {};\n", self.start));
+        }
+        for (id, out, label, empty) in edges {
+            let style = if empty { ", style=dashed" } else { "" };
+            dot.push_str(&format!("    {} -> {} [label={}{}];\n", id, node(out), dot_string(&label), style));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn push_state(&mut self, state: State) -> usize {
+        self.states.push(state);
+        self.states.len() - 1
     }
 
     pub fn from_expr(expr: &Expr) -> NFA {
+        Self::try_from_expr(expr, usize::MAX).unwrap()
+    }
+
+    // fails once the NFA would have more than max_states states
+    pub fn try_from_expr(expr: &Expr, max_states: usize) -> Result<NFA, CompileError> {
         let mut nfa = Self::new();
 
-        let start = nfa.build_expr(expr);
-        nfa.update_outputs(start, Transition::End);
+        let fragment = nfa.build_expr(expr, max_states)?;
+        nfa.patch(&fragment.ends, Transition::End);
+        nfa.start = fragment.start;
+        nfa.simplify();
+        nfa.prepare();
+        Ok(nfa)
+    }
+
+    // an NFA matching any of `exprs`, which ends in `Transition::Match` with
+    // the index of the expression that matched
+    pub fn from_exprs(exprs: &[Expr]) -> NFA {
+        Self::try_from_exprs(exprs, usize::MAX).unwrap()
+    }
+
+    pub fn try_from_exprs(exprs: &[Expr], max_states: usize) -> Result<NFA, CompileError> {
+        let mut nfa = Self::new();
+
+        let mut starts = Vec::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            let fragment = nfa.build_expr(expr, max_states)?;
+            nfa.patch(&fragment.ends, Transition::Match(i));
+            starts.push(fragment.start);
+        }
+
+        if !starts.is_empty() {
+            nfa.start = nfa.build_splits(&starts);
+        }
+        if nfa.states.len() > max_states {
+            return Err(CompileError::TooManyStates(max_states));
+        }
+        nfa.simplify();
+        nfa.prepare();
+        Ok(nfa)
+    }
+
+    // matches wherever a match of this NFA starts, by allowing any bytes before it
+    pub fn unanchored(&self) -> NFA {
+        if self.states.is_empty() {
+            return NFA::new();
+        }
+
+        let mut nfa = self.clone();
+
+        let start = nfa.states.len();
+        nfa.states.push(State::split(Transition::Id(self.start), Transition::Id(start + 1)));
+        nfa.states.push(State::state(Condition::Class((0..=255).collect()), Transition::Id(start)));
         nfa.start = start;
+        nfa.prepare();
         nfa
     }
 
-    fn build_expr(&mut self, expr: &Expr) -> usize {
-        let id = match expr {
-            &Expr::Any => {
-                let s = State::state(Condition::Any, Transition::Detached);
-                self.states.push(s);
+    // the states in the versioned binary format of ::format, e.g. to compile
+    // patterns ahead of time. Derived tables are rebuilt on load
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        format::write_header(&mut out, NFA_MAGIC);
+        format::write_u32(&mut out, self.start);
+        format::write_u32(&mut out, self.captures);
+        format::write_u32(&mut out, self.states.len());
 
-                self.states.len() - 1
-            },
-            &Expr::Single(c) => {
-                let s = State::state(Condition::one(c), Transition::Detached);
-                self.states.push(s);
+        for state in &self.states {
+            match *state {
+                State::State{ref condition, out: ref next} => {
+                    out.push(0);
+                    write_condition(&mut out, condition);
+                    write_transition(&mut out, next);
+                },
+                State::Split{ref out1, ref out2} => {
+                    out.push(1);
+                    write_transition(&mut out, out1);
+                    write_transition(&mut out, out2);
+                },
+                State::Save{slot, out: ref next} => {
+                    out.push(2);
+                    format::write_u32(&mut out, slot);
+                    write_transition(&mut out, next);
+                },
+                State::Fork{ref condition, ref outs} => {
+                    out.push(3);
+                    write_condition(&mut out, condition);
+                    format::write_u32(&mut out, outs.len());
+                    for next in outs {
+                        write_transition(&mut out, next);
+                    }
+                }
+            }
+        }
+        out
+    }
 
-                self.states.len() - 1
-            },
-            &Expr::Class(ref chars) => {
-                let s = State::state(Condition::class(chars.clone()),
-                                     Transition::Detached);
-                self.states.push(s);
+    // fails on anything to_bytes could not have written, so that searches
+    // never index out of the states
+    pub fn from_bytes(bytes: &[u8]) -> Result<NFA, String> {
+        let mut reader = Reader::new(bytes, NFA_MAGIC)?;
+        let start = reader.u32()?;
+        let captures = reader.u32()?;
+        let num_states = reader.u32()?;
+        if captures == 0 {
+            return Err("no capture group for the whole match".to_owned());
+        }
+        if start >= num_states && !(start == 0 && num_states == 0) {
+            return Err(format!("start state {} out of range", start));
+        }
 
-                self.states.len() - 1
-            },
-            &Expr::Sequence(ref a, ref b) => {
-                let left_id = self.build_expr(a);
-                let right_id = self.build_expr(b);
-                self.update_outputs(left_id, Transition::Id(right_id));
+        // each state takes at least two bytes, which bounds the allocation
+        let mut states = Vec::with_capacity(cmp::min(num_states, bytes.len() / 2));
+        for _ in 0..num_states {
+            let state = match reader.u8()? {
+                0 => {
+                    let condition = read_condition(&mut reader)?;
+                    State::state(condition, read_transition(&mut reader, num_states)?)
+                },
+                1 => {
+                    let out1 = read_transition(&mut reader, num_states)?;
+                    State::split(out1, read_transition(&mut reader, num_states)?)
+                },
+                2 => {
+                    let slot = reader.index(2 * captures, "capture slot")?;
+                    State::save(slot, read_transition(&mut reader, num_states)?)
+                },
+                3 => {
+                    let condition = read_condition(&mut reader)?;
+                    let len = reader.u32()?;
+                    let mut outs = Vec::new();
+                    for _ in 0..len {
+                        outs.push(read_transition(&mut reader, num_states)?);
+                    }
+                    State::fork(condition, outs)
+                },
+                tag => return Err(format!("unknown state tag {}", tag))
+            };
+            states.push(state);
+        }
+        reader.finish()?;
+
+        let mut nfa = NFA {
+            start,
+            states,
+            captures,
+            ..NFA::new()
+        };
+        nfa.prepare();
+        Ok(nfa)
+    }
+
+    // the position automaton: a state for each position, going straight to
+    // the positions that can follow it, and a start state going to the first
+    // ones. Only the start has empty edges, and captures are left out
+    fn from_positions(positions: Positions) -> NFA {
+        let start = positions.conditions.len();
+        let mut states = positions.conditions.into_iter()
+            .zip(positions.follow)
+            .map(|(condition, follow)| State::fork(condition, follow))
+            .collect::<Vec<_>>();
+        states.push(State::fork(Condition::None, positions.first));
+
+        let mut nfa = NFA {
+            start,
+            states,
+            ..NFA::new()
+        };
+        nfa.prepare();
+        nfa
+    }
+
+    // a split to each of `starts` in turn
+    fn build_splits(&mut self, starts: &[usize]) -> usize {
+        let last = starts[starts.len() - 1];
+
+        starts.iter().rev().skip(1).fold(last, |rest, &start| {
+            self.states.push(State::split(Transition::Id(start), Transition::Id(rest)));
+            self.states.len() - 1
+        })
+    }
+
+    // a state consuming each condition in turn
+    fn build_conditions(&mut self, conditions: Vec<Condition>) -> Fragment {
+        let first = self.states.len();
+        let len = conditions.len();
+
+        for (i, condition) in conditions.into_iter().enumerate() {
+            let out = if i + 1 < len { Transition::Id(first + i + 1) } else { Transition::Detached };
+            self.states.push(State::state(condition, out));
+        }
+        Fragment::state(first, first + len - 1)
+    }
+
+    fn build_alternatives(&mut self, alternatives: Vec<Vec<Condition>>) -> Fragment {
+        let fragments = alternatives.into_iter()
+            .map(|conditions| self.build_conditions(conditions))
+            .collect::<Vec<_>>();
 
-                left_id
+        Fragment {
+            start: self.build_splits(&fragments.iter().map(|fragment| fragment.start).collect::<Vec<_>>()),
+            ends: fragments.into_iter().flat_map(|fragment| fragment.ends).collect()
+        }
+    }
+
+    // fails once there are more than max_states states, checked before
+    // each part is built, so a pattern too large is given up on early
+    fn build_expr(&mut self, expr: &Expr, max_states: usize) -> Result<Fragment, CompileError> {
+        if self.states.len() > max_states {
+            return Err(CompileError::TooManyStates(max_states));
+        }
+
+        Ok(match *expr {
+            Expr::Single(_) | Expr::Class(_) | Expr::Any |
+            Expr::Byte(_) | Expr::ByteClass(_) | Expr::AnyByte => {
+                // a class can expand to many more states than it has members
+                if self.states.len() + leaf_states(expr) > max_states {
+                    return Err(CompileError::TooManyStates(max_states));
+                }
+                self.build_alternatives(byte_sequences(expr))
             },
-            &Expr::Optional(ref expr) => {
-                let expr_id = self.build_expr(expr);
-                let s = State::split(Transition::Id(expr_id), Transition::Detached);
-                self.states.push(s);
+            Expr::Sequence(ref first, ref rest) => {
+                // each part is joined to the ends of the one before
+                let mut fragment = self.build_expr(first, max_states)?;
+                let mut rest = &**rest;
+                loop {
+                    let (part, next) = match *rest {
+                        Expr::Sequence(ref part, ref next) => (&**part, Some(&**next)),
+                        ref part => (part, None)
+                    };
+                    let part = self.build_expr(part, max_states)?;
+                    self.patch(&fragment.ends, Transition::Id(part.start));
+                    fragment.ends = part.ends;
 
-                self.states.len() - 1
+                    match next {
+                        Some(next) => rest = next,
+                        None => return Ok(fragment)
+                    }
+                }
             },
-            &Expr::OneOrMore(ref expr) => {
-                let expr_id = self.build_expr(expr);
-                let s = State::split(Transition::Id(expr_id), Transition::Detached);
+            Expr::Group(ref expr) => {
+                let group = self.captures;
+                self.captures += 1;
 
-                self.states.push(s);
-                let split_id = self.states.len() - 1;
-                self.update_outputs(expr_id, Transition::Id(split_id));
+                let open = self.push_state(State::save(2 * group, Transition::Detached));
+                let inner = self.build_expr(expr, max_states)?;
+                let close = self.push_state(State::save(2 * group + 1, Transition::Detached));
+                self.states[open] = State::save(2 * group, Transition::Id(inner.start));
+                self.patch(&inner.ends, Transition::Id(close));
 
-                expr_id
+                Fragment::state(open, close)
             },
-            &Expr::ZeroOrMore(ref expr) => {
-                let expr_id = self.build_expr(expr);
-                let s = State::split(Transition::Id(expr_id), Transition::Detached);
-
-                self.states.push(s);
-                let split_id = self.states.len() - 1;
-                self.update_outputs(expr_id, Transition::Id(split_id));
+            Expr::Optional(ref expr) => {
+                let mut inner = self.build_expr(expr, max_states)?;
+                let split = self.push_state(State::split(Transition::Id(inner.start), Transition::Detached));
+                inner.ends.push(split);
 
-                split_id
+                Fragment {
+                    start: split,
+                    ends: inner.ends
+                }
             },
-            &Expr::Or(ref expr1, ref expr2) => {
-                let expr1_id = self.build_expr(expr1);
-                let expr2_id = self.build_expr(expr2);
+            Expr::OneOrMore(ref expr) => {
+                let inner = self.build_expr(expr, max_states)?;
+                let split = self.push_state(State::split(Transition::Id(inner.start), Transition::Detached));
+                self.patch(&inner.ends, Transition::Id(split));
 
-                let s = State::split(Transition::Id(expr1_id),
-                                     Transition::Id(expr2_id));
+                Fragment::state(inner.start, split)
+            },
+            Expr::ZeroOrMore(ref expr) => {
+                let inner = self.build_expr(expr, max_states)?;
+                let split = self.push_state(State::split(Transition::Id(inner.start), Transition::Detached));
+                self.patch(&inner.ends, Transition::Id(split));
 
-                self.states.push(s);
+                Fragment::state(split, split)
+            },
+            Expr::Or(..) => {
+                let mut alternatives = Vec::new();
+                let mut first = expr;
+                while let Expr::Or(ref expr1, ref expr2) = *first {
+                    alternatives.push(&**expr2);
+                    first = expr1;
+                }
 
-                self.states.len() - 1
+                let mut fragment = self.build_expr(first, max_states)?;
+                for alternative in alternatives.into_iter().rev() {
+                    let alternative = self.build_expr(alternative, max_states)?;
+                    fragment.start = self.push_state(State::split(Transition::Id(fragment.start), Transition::Id(alternative.start)));
+                    fragment.ends.extend(alternative.ends);
+                }
+                fragment
             }
-        };
-
-        id
+        })
     }
 
-    fn update_outputs(&mut self, start_id: usize, new_edge: Transition) {
-        self.update_outputs_rec(start_id, &mut vec![start_id], new_edge);
+    // points the detached outputs of the end states of a fragment at edge
+    fn patch(&mut self, ends: &[usize], edge: Transition) {
+        for &id in ends {
+            for out in self.states[id].outs_mut() {
+                if *out == Transition::Detached {
+                    *out = edge.clone();
+                }
+            }
+        }
     }
+}
 
-    fn update_outputs_rec(&mut self, start_id: usize, visited: &mut Vec<usize>, new_edge: Transition) {
-        let state = self.states[start_id].clone();
-        let state = match state {
-            State::State{ref condition, ref out} => {
-                State::state(condition.clone(), 
-                             self.replace_edge(out.clone(), new_edge, visited)
-)
-            },
-            State::Split{ref out1, ref out2} => {
-                let edge1 = self.replace_edge(out1.clone(), new_edge.clone(), visited);
-                let edge2 = self.replace_edge(out2.clone(), new_edge.clone(), visited);
+// a part of an NFA under construction: the state it starts at, and the
+// states with the detached outputs that whatever follows is joined to
+struct Fragment {
+    start: usize,
+    ends: Vec<usize>
+}
 
-                State::split(edge1,
-                             edge2)
-            }
-        };
-        self.states[start_id] = state;
+impl Fragment {
+    fn state(start: usize, end: usize) -> Fragment {
+        Fragment {
+            start,
+            ends: vec![end]
+        }
     }
+}
 
-    fn replace_edge(&mut self, edge: Transition, replacement: Transition, visited: &mut Vec<usize>) -> Transition {
-        match &edge {
-            &Transition::Detached => {
-                replacement
-            },
-            &Transition::Id(id) => {
-                if !visited.contains(&id) { // don't recurse further
-                    visited.push(id);
-                    self.update_outputs_rec(id, visited, replacement);
-                }
+// how an NFA is built from an expression
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum Construction {
+    Thompson, // empty edges join the parts of the expression
+    Glushkov // a state per byte the expression reads, without empty edges past the start
+}
 
-                edge
-            },
-            _ => edge
+pub struct Builder {
+    construction: Construction,
+    limits: CompileLimits
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            construction: Construction::Thompson,
+            limits: CompileLimits::new()
         }
     }
 
+    pub fn construction(mut self, construction: Construction) -> Builder {
+        self.construction = construction;
+        self
+    }
+
+    pub fn limits(mut self, limits: CompileLimits) -> Builder {
+        self.limits = limits;
+        self
+    }
+
+    // fails if the NFA would have more states than the limits allow. A
+    // Glushkov NFA is charged its transitions, as each position can be
+    // followed by every other, e.g. in `a?a?a?...`. It has no capture groups
+    pub fn try_build(&self, expr: &Expr) -> Result<NFA, CompileError> {
+        let max_states = self.limits.max_states(expr.is_literal());
+        match self.construction {
+            Construction::Thompson => NFA::try_from_expr(expr, max_states),
+            Construction::Glushkov => Positions::from_expr(expr, max_states)
+                .map(NFA::from_positions)
+                .ok_or(CompileError::TooManyStates(max_states))
+        }
+    }
 }
 
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
@@ -1,5 +1,8 @@
 use ::expr::Expr;
-use super::{State, Transition, NFA, Condition};
+use ::dfa::DFA;
+use ::matcher::{self, Matcher};
+use ::limits::{CompileLimits, CompileError};
+use super::{State, Transition, NFA, Condition, Builder, Construction, byte_ranges, bytes_label, leaf_states};
 
 #[test]
 fn build_single() {
@@ -119,56 +122,115 @@ fn build_or() {
 
 #[test]
 fn build_any() {
-    let nfa = NFA::from_expr(&Expr::Any);
+    let nfa = NFA::from_expr(&Expr::AnyByte);
 
     assert_eq!(vec![
         State::state(Condition::Any, Transition::End)
     ], nfa.states);
 }
 
+#[test]
+fn build_any_char() {
+    let nfa = NFA::from_expr(&Expr::Any);
+    let start = nfa.start_closure();
+
+    assert_eq!(4, start.states.len());
+    assert!(nfa.step(&start.states, b'a').is_match());
+    assert!(!nfa.step(&start.states, b'\n').is_match());
+
+    let lead = nfa.step(&start.states, 0xC3); // 'é' is C3 A9
+    assert!(!lead.is_match());
+    assert!(nfa.step(&lead.states, 0xA9).is_match());
+    assert!(nfa.step(&start.states, 0xA9).states.is_empty());
+}
+
+#[test]
+fn build_non_ascii_single() {
+    let nfa = NFA::from_expr(&Expr::Single('é'));
+
+    assert_eq!(vec![
+        State::state(Condition::One(0xC3), Transition::Id(1)),
+        State::state(Condition::One(0xA9), Transition::End)
+    ], nfa.states);
+}
+
+#[test]
+fn build_non_ascii_class() {
+    let nfa = NFA::from_expr(&Expr::Class(vec!['a', 'é', 'b']));
+
+    assert_eq!(vec![
+        State::state(Condition::class(vec!['a', 'b']), Transition::End),
+        State::state(Condition::One(0xC3), Transition::Id(2)),
+        State::state(Condition::One(0xA9), Transition::End),
+        State::split(Transition::Id(0), Transition::Id(1))
+    ], nfa.states);
+    assert_eq!(3, nfa.start);
+}
+
+#[test]
+fn build_byte_class() {
+    let nfa = NFA::from_expr(&Expr::ByteClass(vec![b'a', 0xFF]));
+
+    assert_eq!(vec![
+        State::state(Condition::Class(vec![b'a', 0xFF]), Transition::End)
+    ], nfa.states);
+}
+
 #[test]
 fn prioritizes_state() {
-    let s = State::state(Condition::one('a'), Transition::End);
-    assert_eq!(97, s.get_priority_key(&NFA::new()));
+    let nfa = NFA::from_states(vec![State::state(Condition::one('a'), Transition::End)]);
+    assert_eq!(97, nfa.priority(0));
 
-    let s = State::state(Condition::Any, Transition::End);
-    assert_eq!(0, s.get_priority_key(&NFA::new()));
+    let nfa = NFA::from_states(vec![State::state(Condition::Any, Transition::End)]);
+    assert_eq!(0, nfa.priority(0));
 
-    let s = State::state(Condition::None, Transition::End);
-    assert_eq!(usize::max_value(), s.get_priority_key(&NFA::new()));
+    let nfa = NFA::from_states(vec![State::state(Condition::None, Transition::End)]);
+    assert_eq!(usize::max_value(), nfa.priority(0));
 
     // recursive
-    let s = State::state(Condition::None, Transition::Id(0));
-    assert_eq!(98, s.get_priority_key(&NFA::from_states(vec![
+    let nfa = NFA::from_states(vec![
         State::state(Condition::None, Transition::Id(1)),
+        State::state(Condition::None, Transition::Id(2)),
         State::state(Condition::one('b'), Transition::End)
-    ])));
+    ]);
+    assert_eq!(98, nfa.priority(0));
 
-    // does not infinitely loop (assuming no free cycles)
-    let s = State::state(Condition::None, Transition::Id(0));
-    assert_eq!(0, s.get_priority_key(&NFA::from_states(vec![
-        State::state(Condition::Any, Transition::Id(0))
-    ])));
+    // does not infinitely loop
+    let nfa = NFA::from_states(vec![
+        State::state(Condition::None, Transition::Id(1)),
+        State::state(Condition::Any, Transition::Id(1))
+    ]);
+    assert_eq!(0, nfa.priority(0));
 }
 
 #[test]
 fn prioritizes_split() {
     let nfa = NFA::from_states(vec![
+        State::split(Transition::Id(1), Transition::End),
         State::state(Condition::one('a'), Transition::End)
     ]);
-    let s = State::split(Transition::Id(0), Transition::End);
-    assert_eq!(97, s.get_priority_key(&nfa));
+    assert_eq!(97, nfa.priority(0));
 
-    let s = State::split(Transition::End, Transition::End);
-    assert_eq!(usize::max_value(), s.get_priority_key(&NFA::new()));
+    let nfa = NFA::from_states(vec![State::split(Transition::End, Transition::End)]);
+    assert_eq!(usize::max_value(), nfa.priority(0));
 
     // recursive
-    let s = State::split(Transition::Id(0), Transition::End);
-    assert_eq!(0, s.get_priority_key(&NFA::from_states(vec![
-        State::split(Transition::Id(1), Transition::Id(2)),
+    let nfa = NFA::from_states(vec![
+        State::split(Transition::Id(1), Transition::End),
+        State::split(Transition::Id(2), Transition::Id(3)),
         State::state(Condition::one('a'), Transition::End),
         State::state(Condition::Any, Transition::End)
-    ])));
+    ]);
+    assert_eq!(0, nfa.priority(0));
+
+    // through a loop of empty edges, as in `(a*)*`
+    let nfa = NFA::from_states(vec![
+        State::split(Transition::Id(1), Transition::End),
+        State::split(Transition::Id(2), Transition::Id(0)),
+        State::state(Condition::one('a'), Transition::Id(1))
+    ]);
+    assert_eq!(97, nfa.priority(0));
+    assert_eq!(97, nfa.priority(1));
 }
 
 #[test]
@@ -180,3 +242,325 @@ fn build_char_class() {
     ], nfa.states);
 }
 
+
+#[test]
+fn computes_closure() {
+    let nfa = NFA::from_expr(&"a?b*c".parse::<Expr>().unwrap());
+
+    let closure = nfa.start_closure();
+    assert_eq!(3, closure.states.len());
+    assert!(!closure.is_match());
+
+    let closure = nfa.closure(&[Transition::End]);
+    assert_eq!(Vec::<usize>::new(), closure.states);
+    assert!(closure.is_match());
+}
+
+#[test]
+fn steps_over_byte() {
+    let nfa = NFA::from_expr(&"a?b*c".parse::<Expr>().unwrap());
+    let start = nfa.start_closure();
+
+    let after_a = nfa.step(&start.states, b'a');
+    assert_eq!(2, after_a.states.len());
+    assert_eq!(after_a, nfa.step(&after_a.states, b'b'));
+    assert!(nfa.step(&start.states, b'c').is_match());
+    assert!(nfa.step(&start.states, b'd').states.is_empty());
+}
+
+#[test]
+fn computes_byte_classes() {
+    let nfa = NFA::from_expr(&"a[bc]".parse::<Expr>().unwrap());
+    let classes = nfa.byte_classes();
+
+    assert_eq!(3, classes.num_classes());
+    assert_eq!(classes.get(b'b'), classes.get(b'c'));
+    assert_eq!(classes.get(b'x'), classes.get(b'\n'));
+    assert!(classes.get(b'a') != classes.get(b'b'));
+    assert_eq!(vec![0, b'a', b'b'], classes.representatives());
+    assert_eq!(vec![b'b', b'c'], classes.members(classes.get(b'b')));
+}
+
+#[test]
+fn any_distinguishes_newline() {
+    let nfa = NFA::from_expr(&"(?-u)a.".parse::<Expr>().unwrap());
+    let classes = nfa.byte_classes();
+
+    assert_eq!(3, classes.num_classes());
+    assert!(classes.get(b'\n') != classes.get(b'x'));
+    assert_eq!("[0-9, 11-96, 98-255] [10] [97]", format!("{:?}", classes));
+}
+
+#[test]
+fn writes_bytes_as_ranges() {
+    assert_eq!("1-3; 7; 9-10", byte_ranges(&[1, 2, 3, 7, 9, 10], |byte| byte.to_string(), "; "));
+    assert_eq!("", byte_ranges(&[], |byte| byte.to_string(), ", "));
+}
+
+#[test]
+fn build_several_exprs() {
+    let nfa = NFA::from_exprs(&[Expr::Single('a'), Expr::Single('b'), Expr::AnyByte]);
+
+    assert_eq!(vec![
+        State::state(Condition::one('a'), Transition::Match(0)),
+        State::state(Condition::one('b'), Transition::Match(1)),
+        State::state(Condition::Any, Transition::Match(2)),
+        State::split(Transition::Id(1), Transition::Id(2)),
+        State::split(Transition::Id(0), Transition::Id(3))
+    ], nfa.states);
+    assert_eq!(4, nfa.start);
+
+    let start = nfa.start_closure();
+    assert_eq!(vec![0, 2], nfa.step(&start.states, b'a').matches);
+    assert_eq!(Vec::<usize>::new(), nfa.step(&start.states, b'\n').matches);
+}
+
+// whether the nfa matches all of text
+fn accepts(nfa: &NFA, text: &[u8]) -> bool {
+    let mut closure = nfa.start_closure();
+    for &byte in text {
+        closure = nfa.step(&closure.states, byte);
+    }
+    closure.is_match()
+}
+
+#[test]
+fn reverse_matches_reversed_text() {
+    let nfa = NFA::from_expr(&"a(bc|d)*e?".parse::<Expr>().unwrap());
+    let reverse = nfa.reverse();
+
+    for text in ["a", "abc", "adbce", "ade"].iter() {
+        let reversed = text.bytes().rev().collect::<Vec<_>>();
+        assert!(accepts(&nfa, text.as_bytes()));
+        assert!(accepts(&reverse, &reversed), "{:?}", text);
+    }
+    assert!(accepts(&reverse, b"cba"));
+    assert!(!accepts(&reverse, b"abc"));
+    assert!(!accepts(&reverse, b""));
+}
+
+#[test]
+fn reverse_reverses_utf8_sequences() {
+    let reverse = NFA::from_expr(&"é.".parse::<Expr>().unwrap()).reverse();
+    let text = "éñ".bytes().rev().collect::<Vec<_>>();
+    assert!(accepts(&reverse, &text));
+    assert!(!accepts(&reverse, "éñ".as_bytes()));
+}
+
+#[test]
+fn reverse_twice_accepts_same_language() {
+    for pattern in ["(a|b)*abb", "a?b+[cd]", "(ab|a)(bc|c)*"].iter() {
+        let nfa = NFA::from_expr(&pattern.parse::<Expr>().unwrap());
+        assert!(DFA::from_nfa(&nfa).is_equivalent(&DFA::from_nfa(&nfa.reverse().reverse())), "{}", pattern);
+    }
+}
+
+#[test]
+fn unanchored_matches_after_any_bytes() {
+    let nfa = NFA::from_expr(&"ab".parse::<Expr>().unwrap()).unanchored();
+    assert!(accepts(&nfa, b"ab"));
+    assert!(accepts(&nfa, b"x\n\xFFab"));
+    assert!(!accepts(&nfa, b"abx"));
+}
+
+#[test]
+fn build_glushkov_without_empty_edges() {
+    let expr = "(ab)*c?".parse::<Expr>().unwrap();
+    let nfa = Builder::new().construction(Construction::Glushkov).try_build(&expr).unwrap();
+
+    assert_eq!(vec![
+        State::fork(Condition::one('a'), vec![Transition::Id(1)]),
+        State::fork(Condition::one('b'), vec![Transition::Id(0), Transition::Id(2), Transition::End]),
+        State::fork(Condition::one('c'), vec![Transition::End]),
+        State::fork(Condition::None, vec![Transition::Id(0), Transition::Id(2), Transition::End])],
+        nfa.states);
+    assert_eq!(3, nfa.start);
+}
+
+#[test]
+fn build_glushkov_state_per_byte() {
+    let glushkov = Builder::new().construction(Construction::Glushkov);
+    for (pattern, positions) in [("a(b|c)*d", 4), ("(a*)?b+", 2), ("é.", 12), ("[ab]?[cd]+", 2)].iter() {
+        let nfa = glushkov.try_build(&pattern.parse::<Expr>().unwrap()).unwrap();
+        assert_eq!(positions + 1, nfa.num_states(), "{}", pattern);
+    }
+}
+
+#[test]
+fn limits_glushkov_transitions() {
+    // each `a?` is followed by every position after it
+    let glushkov = Builder::new().construction(Construction::Glushkov);
+    assert_eq!(Err(CompileError::TooManyStates(10000)), glushkov.try_build(&"a?".repeat(200).parse::<Expr>().unwrap()));
+    assert!(glushkov.try_build(&"a?".repeat(100).parse::<Expr>().unwrap()).is_ok());
+}
+
+#[test]
+fn glushkov_accepts_same_language() {
+    let glushkov = Builder::new().construction(Construction::Glushkov);
+    for pattern in ["(a|b)*abb", "a?b+[cd]", "(ab|a)(bc|c)*", "é.x?", "(?-u)\\xFF+a"].iter() {
+        let expr = pattern.parse::<Expr>().unwrap();
+        let thompson = Builder::new().try_build(&expr).unwrap();
+        assert!(DFA::from_nfa(&thompson).is_equivalent(&DFA::from_nfa(&glushkov.try_build(&expr).unwrap())), "{}", pattern);
+    }
+}
+
+#[test]
+fn collapses_nested_options() {
+    let nfa = NFA::from_expr(&Expr::optional(Expr::optional(Expr::Single('a'))));
+    assert_eq!(vec![
+        State::state(Condition::one('a'), Transition::End),
+        State::split(Transition::Id(0), Transition::End)
+    ], nfa.states);
+    assert_eq!(1, nfa.start);
+}
+
+#[test]
+fn skips_empty_states() {
+    let mut nfa = NFA::from_states(vec![
+        State::state(Condition::one('a'), Transition::Id(1)),
+        State::state(Condition::None, Transition::Id(2)),
+        State::state(Condition::one('b'), Transition::End)
+    ]);
+    nfa.simplify();
+    assert_eq!(vec![
+        State::state(Condition::one('a'), Transition::Id(1)),
+        State::state(Condition::one('b'), Transition::End)
+    ], nfa.states);
+}
+
+#[test]
+fn precomputes_ordered_closures() {
+    let nfa = NFA::from_expr(&"(a*)*b?".parse::<Expr>().unwrap());
+    let closure = nfa.epsilon_closure(nfa.start()).unwrap();
+    let conditions = closure.iter()
+        .map(|out| match *out {
+            Transition::Id(id) => match *nfa.get_state(id).unwrap() {
+                State::State{ref condition, ..} => Some(condition.clone()),
+                _ => panic!("closures hold consuming states")
+            },
+            _ => None
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(vec![Some(Condition::one('a')), Some(Condition::one('b')), None], conditions);
+}
+
+#[test]
+fn keeps_only_short_closures() {
+    let nfa = NFA::from_expr(&"a?".repeat(200).parse::<Expr>().unwrap());
+    assert!(nfa.epsilon_closure(nfa.start).is_none());
+    assert!(nfa.closures.iter().flatten().all(|closure| closure.len() <= super::MAX_CLOSURE_WALK));
+
+    let nfa = NFA::from_expr(&"(a|b)*c".parse::<Expr>().unwrap());
+    assert!(nfa.epsilon_closure(nfa.start).is_some());
+}
+
+#[test]
+fn matches_the_same_without_closures() {
+    let patterns = ["(a|b)*abb", "a?a?aa", "(a*)?b", "((abc|acc)b)+", "(ab|a)(bc|c)*", "(a|ab)(c|bcd)", "x*(y?z?)*"];
+    let texts = ["", "aa", "abb", "abcbaccb", "ababbcd", "abcd", "aab", "b", "xxyzzy"];
+
+    for pattern in patterns.iter() {
+        let nfa = NFA::from_expr(&pattern.parse::<Expr>().unwrap());
+        let walked = NFA {
+            closures: vec![None; nfa.num_states()],
+            ..nfa.clone()
+        };
+        for text in texts.iter() {
+            assert_eq!(Matcher::new(&nfa, text).run(&mut matcher::Cache::new()),
+                       Matcher::new(&walked, text).run(&mut matcher::Cache::new()),
+                       "{} on {:?}", pattern, text);
+        }
+        assert_eq!(nfa.start_closure(), walked.start_closure(), "{}", pattern);
+    }
+}
+
+#[test]
+fn labels_bytes_by_their_ranges() {
+    assert_eq!("[0-9a\\-]", bytes_label(b"0123456789a-"));
+    assert_eq!("\\x00", bytes_label(&[0]));
+}
+
+#[test]
+fn exports_dot() {
+    let nfa = NFA::from_expr(&"a*\\\\".parse::<Expr>().unwrap());
+    assert_eq!("digraph nfa {\n    \
+                    rankdir=LR;\n    \
+                    node [shape=circle];\n    \
+                    start [shape=point];\n    \
+                    end [shape=doublecircle];\n    \
+                    start -> 1;\n    \
+                    0 -> 1 [label=\"a\"];\n    \
+                    1 -> 2 [label=\"ε 1\", style=dashed];\n    \
+                    1 -> 0 [label=\"ε 2\", style=dashed];\n    \
+                    2 -> end [label=\"\\\\\\\\\"];\n\
+                }\n", nfa.to_dot());
+
+    let nfa = Builder::new().construction(Construction::Glushkov).try_build(&"[abc.]b?".parse::<Expr>().unwrap()).unwrap();
+    let dot = nfa.to_dot();
+    assert!(dot.contains("0 -> 1 [label=\"[\\\\.a-c] 1\"];"), "{}", dot);
+    assert!(dot.contains("2 -> 0 [label=\"ε\", style=dashed];"), "{}", dot);
+
+    assert!(NFA::new().to_dot().contains("start -> end;"));
+}
+
+#[test]
+fn limits_states() {
+    let expr = "(ab)*c".parse::<Expr>().unwrap();
+    let nfa = NFA::from_expr(&expr);
+    let num_states = nfa.num_states();
+    let limits = CompileLimits::new().states(num_states);
+    assert_eq!(Ok(nfa), Builder::new().limits(limits).try_build(&expr));
+
+    let limits = CompileLimits::new().states(num_states - 1);
+    assert_eq!(Err(CompileError::TooManyStates(num_states - 1)), Builder::new().limits(limits).try_build(&expr));
+}
+
+#[test]
+fn counts_leaf_states_without_expanding() {
+    let leaves = [Expr::Single('a'), Expr::Single('é'), Expr::Class(vec!['a', 'b', 'é', '中']), Expr::Class(vec!['é']),
+                  Expr::Class(Vec::new()), Expr::Any, Expr::Byte(0xFF), Expr::AnyByte];
+    for leaf in leaves.iter() {
+        assert_eq!(NFA::from_expr(leaf).num_states(), leaf_states(leaf), "{:?}", leaf);
+    }
+}
+
+#[test]
+fn limits_states_while_building() {
+    // the class alone expands to more states than allowed
+    let class = Expr::Class((0x80..0x800).filter_map(::std::char::from_u32).collect());
+    let expr = Expr::sequence(Expr::Single('a'), class);
+    let limits = CompileLimits::new().states(100);
+    assert_eq!(Err(CompileError::TooManyStates(100)), Builder::new().limits(limits).try_build(&expr));
+    assert_eq!(Err(CompileError::TooManyStates(100)), NFA::try_from_exprs(&[Expr::Single('a'), expr], 100));
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    for pattern in &["(a|b)*c", "[abc.]b?", "(?-u)\\xFF+", "x(y(z))"] {
+        let nfa = NFA::from_expr(&pattern.parse::<Expr>().unwrap());
+        assert_eq!(Ok(nfa.clone()), NFA::from_bytes(&nfa.to_bytes()));
+    }
+
+    let glushkov = Builder::new().construction(Construction::Glushkov).try_build(&"a+b|ac".parse::<Expr>().unwrap()).unwrap();
+    assert_eq!(Ok(glushkov.clone()), NFA::from_bytes(&glushkov.to_bytes()));
+    assert_eq!(Ok(NFA::new()), NFA::from_bytes(&NFA::new().to_bytes()));
+}
+
+#[test]
+fn rejects_invalid_bytes() {
+    let bytes = NFA::from_expr(&"(a)b".parse::<Expr>().unwrap()).to_bytes();
+
+    assert_eq!(Some("unexpected end of input".to_owned()), NFA::from_bytes(&bytes[..bytes.len() - 1]).err());
+    assert_eq!(Some("not a serialized RXNF".to_owned()), NFA::from_bytes(b"RXDF").err());
+
+    // the first state is the save opening group 1, out of two groups
+    let mut invalid = bytes.clone();
+    invalid[20] = 9;
+    assert_eq!(Some("unknown state tag 9".to_owned()), NFA::from_bytes(&invalid).err());
+    let mut invalid = bytes.clone();
+    invalid[21] = 4;
+    assert_eq!(Some("capture slot 4 out of range".to_owned()), NFA::from_bytes(&invalid).err());
+    let mut invalid = bytes.clone();
+    invalid[26] = 7;
+    assert_eq!(Some("state 7 out of range".to_owned()), NFA::from_bytes(&invalid).err());
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::slice;
+
+use ::nfa::{NFA, State, Transition, Condition, ByteClasses};
+
+#[cfg(test)] mod spec;
+
+// stands for the start in place of the state a node follows
+const START: usize = usize::MAX;
+
+// a consuming state or the end of the match, reached along empty edges
+struct Entry {
+    state: Option<usize>, // none for the end
+    saves: Vec<usize> // capture slots set on the way
+}
+
+#[derive(PartialEq,Debug,Clone)]
+struct Step {
+    node: usize,
+    saves: Vec<usize>, // slots to set to the position of the consumed byte
+    priority: usize // place among the entries of its node, the end wins if before it
+}
+
+#[derive(PartialEq,Debug,Clone)]
+struct End {
+    saves: Vec<usize>,
+    priority: usize
+}
+
+#[derive(PartialEq,Debug,Clone)]
+struct Node {
+    steps: Vec<Option<Step>>, // one per byte class
+    end: Option<End>
+}
+
+// a DFA for an NFA in which at most one thread survives each byte. Its
+// transitions record the capture slots, so captures cost no more than
+// matching
+#[derive(PartialEq,Debug,Clone)]
+pub struct OnePass {
+    classes: ByteClasses,
+    nodes: Vec<Node>, // the first is the start
+    num_slots: usize
+}
+
+impl OnePass {
+    // none unless the NFA is one-pass
+    pub fn new(nfa: &NFA) -> Option<OnePass> {
+        if nfa.num_states() == 0 {
+            return None;
+        }
+
+        let classes = nfa.byte_classes().clone();
+        let representatives = classes.representatives();
+
+        // nodes stand for the point after each consuming state, plus the start
+        let mut ids = HashMap::new();
+        ids.insert(START, 0);
+        let mut pending = vec![START];
+        let mut nodes = Vec::new();
+        let mut visited = vec![usize::MAX; nfa.num_states()]; // the node that last reached each state
+
+        while nodes.len() < pending.len() {
+            let entries = match pending[nodes.len()] {
+                START => Self::entries(nfa, &[Transition::Id(nfa.start())], &mut visited, nodes.len()),
+                id => match *nfa.get_state(id).unwrap() {
+                    State::State{ref out, ..} => Self::entries(nfa, slice::from_ref(out), &mut visited, nodes.len()),
+                    State::Fork{ref outs, ..} => Self::entries(nfa, outs, &mut visited, nodes.len()),
+                    _ => unreachable!()
+                }
+            };
+            let mut node = Node {
+                steps: vec![None; classes.num_classes()],
+                end: None
+            };
+
+            for (priority, entry) in entries.into_iter().enumerate() {
+                let (id, condition) = match entry.state {
+                    None => {
+                        node.end = Some(End {
+                            saves: entry.saves,
+                            priority
+                        });
+                        continue;
+                    },
+                    Some(id) => match *nfa.get_state(id).unwrap() {
+                        State::State{ref condition, ..} | State::Fork{ref condition, ..} => (id, condition),
+                        _ => unreachable!()
+                    }
+                };
+
+                let next = match ids.get(&id) {
+                    Some(&next) => next,
+                    None => {
+                        let next = pending.len();
+                        ids.insert(id, next);
+                        pending.push(id);
+                        next
+                    }
+                };
+
+                for (class, &byte) in representatives.iter().enumerate() {
+                    if !condition.matches(byte) {
+                        continue;
+                    }
+                    if node.steps[class].is_some() {
+                        return None; // two threads could survive this byte
+                    }
+                    node.steps[class] = Some(Step {
+                        node: next,
+                        saves: entry.saves.clone(),
+                        priority
+                    });
+                }
+            }
+
+            nodes.push(node);
+        }
+
+        Some(OnePass {
+            classes,
+            nodes,
+            num_slots: nfa.num_slots()
+        })
+    }
+
+    // the consuming states and end reachable from transitions, in order of
+    // priority. States marked with `node` in visited are skipped, so the
+    // buffer is shared by every node without clearing it
+    fn entries(nfa: &NFA, transitions: &[Transition], visited: &mut [usize], node: usize) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut ended = false;
+        let mut stack = transitions.iter().rev().map(|transition| (transition.clone(), Vec::new())).collect::<Vec<_>>();
+
+        while let Some((transition, mut saves)) = stack.pop() {
+            let id = match transition {
+                Transition::Id(id) => id,
+                Transition::End | Transition::Match(_) => {
+                    if !ended {
+                        ended = true;
+                        entries.push(Entry { state: None, saves });
+                    }
+                    continue;
+                },
+                Transition::Detached => panic!("cannot evaluate incomplete NFA")
+            };
+
+            if visited[id] == node {
+                continue;
+            }
+            visited[id] = node;
+
+            match *nfa.get_state(id).unwrap() {
+                State::State{condition: Condition::None, ref out} => stack.push((out.clone(), saves)),
+                State::Fork{condition: Condition::None, ref outs} => {
+                    stack.extend(outs.iter().rev().map(|out| (out.clone(), saves.clone())));
+                },
+                State::State{..} | State::Fork{..} => entries.push(Entry { state: Some(id), saves }),
+                State::Split{ref out1, ref out2} => {
+                    let (first, second) = nfa.split_order(out1, out2);
+                    stack.push((second.clone(), saves.clone()));
+                    stack.push((first.clone(), saves));
+                },
+                State::Save{slot, ref out} => {
+                    saves.push(slot);
+                    stack.push((out.clone(), saves));
+                }
+            }
+        }
+
+        entries
+    }
+
+    // capture slots of the match at the start of text. The search follows
+    // the one surviving thread, noting the last position where a match
+    // ended, and only fills in slots for the match it returns
+    pub fn captures(&self, text: &[u8]) -> Option<Vec<Option<usize>>> {
+        let mut slots = vec![None; self.num_slots];
+        slots[0] = Some(0);
+        let mut matched = None;
+        let mut node = &self.nodes[0];
+        let mut position = 0;
+
+        while position < text.len() {
+            let step = node.steps[self.classes.get(text[position])].as_ref();
+
+            if let Some(ref end) = node.end {
+                match step {
+                    // kept in case the thread consuming the byte fails
+                    Some(step) if step.priority < end.priority => matched = Some((position, end)),
+                    _ => return Some(Self::finish(slots, end, position))
+                }
+            }
+
+            match step {
+                Some(step) => {
+                    Self::save(&mut slots, &step.saves, position);
+                    node = &self.nodes[step.node];
+                    position += 1;
+                },
+                None => break
+            }
+        }
+
+        match node.end {
+            Some(ref end) if position == text.len() => Some(Self::finish(slots, end, position)),
+            _ => {
+                // the steps past the earlier match may have overwritten its
+                // slots, so they are set again along its path
+                let (position, end) = matched?;
+                Some(Self::finish(self.replay(&text[..position]), end, position))
+            }
+        }
+    }
+
+    // the slots set by the steps over text, which is known to lead to a node
+    fn replay(&self, text: &[u8]) -> Vec<Option<usize>> {
+        let mut slots = vec![None; self.num_slots];
+        slots[0] = Some(0);
+        let mut node = &self.nodes[0];
+
+        for (position, &byte) in text.iter().enumerate() {
+            let step = node.steps[self.classes.get(byte)].as_ref().expect("a step on the path");
+            Self::save(&mut slots, &step.saves, position);
+            node = &self.nodes[step.node];
+        }
+
+        slots
+    }
+
+    fn finish(mut slots: Vec<Option<usize>>, end: &End, position: usize) -> Vec<Option<usize>> {
+        Self::save(&mut slots, &end.saves, position);
+        slots[1] = Some(position);
+        slots
+    }
+
+    fn save(slots: &mut [Option<usize>], saves: &[usize], position: usize) {
+        for &slot in saves {
+            slots[slot] = Some(position);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use ::expr::Expr;
+use ::nfa::NFA;
+use ::pike::{self, PikeVM};
+use ::program::Program;
+use super::OnePass;
+
+fn nfa(pattern: &str) -> NFA {
+    NFA::from_expr(&pattern.parse::<Expr>().unwrap())
+}
+
+#[test]
+fn detects_one_pass_nfas() {
+    for pattern in ["([0123456789]+)-([0123456789]+)", "a(b|c)d", "(ab)*c", "a?b", "x[abcdefghijklmnopqrstuvwxyz]*"].iter() {
+        assert!(OnePass::new(&nfa(pattern)).is_some(), "{}", pattern);
+    }
+    for pattern in ["a*a", "(ab|ac)", "[abcdefghijklmnopqrstuvwxyz]*x", "(a|ab)c", ".*c"].iter() {
+        assert!(OnePass::new(&nfa(pattern)).is_none(), "{}", pattern);
+    }
+}
+
+#[test]
+fn captures_groups() {
+    let onepass = OnePass::new(&nfa("([0123456789]+)-([0123456789]+)")).unwrap();
+    assert_eq!(Some(vec![Some(0), Some(6), Some(0), Some(3), Some(4), Some(6)]),
+               onepass.captures(b"123-45"));
+    assert_eq!(Some(vec![Some(0), Some(4), Some(0), Some(1), Some(2), Some(4)]),
+               onepass.captures(b"1-23x"));
+    assert_eq!(None, onepass.captures(b"123-"));
+    assert_eq!(None, onepass.captures(b"-1"));
+}
+
+#[test]
+fn falls_back_to_earlier_match() {
+    // the thread going on to `bc` fails, the match before it stands
+    let onepass = OnePass::new(&nfa("a(bc)?")).unwrap();
+    assert_eq!(Some(vec![Some(0), Some(1), None, None]), onepass.captures(b"abd"));
+    assert_eq!(Some(vec![Some(0), Some(3), Some(1), Some(3)]), onepass.captures(b"abc"));
+
+    // the failing thread moved the group on, the match keeps where it was
+    let onepass = OnePass::new(&nfa("(ab)*")).unwrap();
+    assert_eq!(Some(vec![Some(0), Some(2), Some(0), Some(2)]), onepass.captures(b"abac"));
+}
+
+#[test]
+fn agrees_with_pike_vm() {
+    let patterns = ["([0123456789]+)-([0123456789]+)", "(ab|c)+", "a(x)?b", "(a(b)?)*c", "[ab]?(cd)*", "x(y|z)*"];
+    let texts = ["", "12-34", "abcab", "ab", "axb", "ababc", "acd", "bcdcd", "xyzzy", "x"];
+
+    for pattern in patterns.iter() {
+        let nfa = nfa(pattern);
+        let onepass = OnePass::new(&nfa).unwrap();
+        for text in texts.iter() {
+            assert_eq!(PikeVM::new(&Program::from_nfa(&nfa)).captures(&mut pike::Cache::new(), text.as_bytes()), onepass.captures(text.as_bytes()),
+                       "{} on {:?}", pattern, text);
+        }
+    }
+}
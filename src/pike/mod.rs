@@ -0,0 +1,193 @@
+use std::mem;
+
+use ::program::{Program, Inst};
+use ::limits::{Budget, MatchError};
+
+#[cfg(test)] mod spec;
+
+// a thread waiting at a consuming instruction, or one that has reached a match
+#[derive(Debug,Clone,Copy)]
+enum Thread {
+    At(u32),
+    Matched
+}
+
+#[derive(Debug)]
+enum Frame {
+    Explore(u32),
+    Restore(usize, Option<usize>) // a capture slot and its value before a save
+}
+
+// the threads at one position, in order of priority. The capture slots of
+// the i-th thread are the i-th block of `slots`
+#[derive(Debug)]
+struct Threads {
+    list: Vec<Thread>,
+    slots: Vec<Option<usize>>,
+    visited: Vec<bool>, // instructions reached by a higher priority thread
+    ended: bool
+}
+
+impl Threads {
+    fn new() -> Threads {
+        Threads {
+            list: Vec::new(),
+            slots: Vec::new(),
+            visited: Vec::new(),
+            ended: false
+        }
+    }
+
+    fn clear(&mut self, len: usize) {
+        self.list.clear();
+        self.slots.clear();
+        self.visited.clear();
+        self.visited.resize(len, false);
+        self.ended = false;
+    }
+
+    fn push(&mut self, thread: Thread, slots: &[Option<usize>]) {
+        self.list.push(thread);
+        self.slots.extend_from_slice(slots);
+    }
+}
+
+// scratch space for `PikeVM::captures`, reused between searches. The two
+// thread lists swap at each position
+#[derive(Debug)]
+pub struct Cache {
+    current: Threads,
+    next: Threads,
+    stack: Vec<Frame>,
+    slots: Vec<Option<usize>> // the slots of the thread being followed
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            current: Threads::new(),
+            next: Threads::new(),
+            stack: Vec::new(),
+            slots: Vec::new()
+        }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+// simulates every thread of the program in lockstep, in order of priority,
+// so its memory does not grow with the text. Its captures are the
+// reference for the other engines
+pub struct PikeVM<'a> {
+    program: &'a Program
+}
+
+impl<'a> PikeVM<'a> {
+    pub fn new(program: &'a Program) -> PikeVM<'a> {
+        PikeVM {
+            program
+        }
+    }
+
+    // capture slots of the match at the start of text
+    pub fn captures(&self, cache: &mut Cache, text: &[u8]) -> Option<Vec<Option<usize>>> {
+        self.captures_limited(cache, text, &mut Budget::unlimited()).unwrap() // cannot run out
+    }
+
+    // a step is a thread moved over a byte
+    pub fn captures_limited(&self, cache: &mut Cache, text: &[u8],
+                            budget: &mut Budget) -> Result<Option<Vec<Option<usize>>>, MatchError> {
+        let num_slots = self.program.num_slots();
+        let Cache{ref mut current, ref mut next, ref mut stack, ref mut slots} = *cache;
+
+        slots.clear();
+        slots.resize(num_slots, None);
+        slots[0] = Some(0);
+
+        current.clear(self.program.len());
+        self.add(current, stack, slots, self.program.start(), 0);
+        let mut matched = None;
+
+        for position in 0..text.len() + 1 {
+            if current.list.is_empty() {
+                break;
+            }
+
+            next.clear(self.program.len());
+            for (i, &thread) in current.list.iter().enumerate() {
+                let thread_slots = &current.slots[i * num_slots..(i + 1) * num_slots];
+                match thread {
+                    Thread::Matched => {
+                        // lower priority threads can no longer win
+                        matched = Some(thread_slots.to_vec());
+                        break;
+                    },
+                    Thread::At(pc) => {
+                        if position == text.len() {
+                            continue;
+                        }
+                        budget.step()?;
+                        budget.read(position)?;
+                        if let Some(target) = self.program.next(self.program.inst(pc), text[position]) {
+                            slots.copy_from_slice(thread_slots);
+                            self.add(next, stack, slots, target, position + 1);
+                        }
+                    }
+                }
+            }
+            mem::swap(current, next);
+        }
+
+        Ok(matched)
+    }
+
+    // follows empty instructions from pc in order of priority, adding a
+    // thread for each consuming instruction not already reached. Saves are
+    // undone on the way back, so `slots` is as it was when this returns
+    fn add(&self, threads: &mut Threads, stack: &mut Vec<Frame>, slots: &mut [Option<usize>],
+           pc: u32, position: usize) {
+        stack.push(Frame::Explore(pc));
+
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+
+            if threads.visited[pc as usize] {
+                continue;
+            }
+            threads.visited[pc as usize] = true;
+
+            match self.program.inst(pc) {
+                Inst::Match(_) => {
+                    if !threads.ended {
+                        threads.ended = true;
+                        threads.push(Thread::Matched, slots);
+                        let end = threads.slots.len() - slots.len() + 1;
+                        threads.slots[end] = Some(position);
+                    }
+                },
+                Inst::Byte(..) | Inst::Range(..) | Inst::Class(..) => threads.push(Thread::At(pc), slots),
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Explore(second));
+                    stack.push(Frame::Explore(first));
+                },
+                Inst::Jump(target) => stack.push(Frame::Explore(target)),
+                Inst::Save(slot, target) => {
+                    let slot = slot as usize;
+                    stack.push(Frame::Restore(slot, slots[slot]));
+                    slots[slot] = Some(position);
+                    stack.push(Frame::Explore(target));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use ::expr::Expr;
+use ::nfa::{NFA, Builder, Construction};
+use ::matcher::{self, Matcher};
+use ::program::Program;
+use ::limits::{Limits, Budget, MatchError};
+use super::{PikeVM, Cache};
+
+fn nfa(pattern: &str) -> NFA {
+    NFA::from_expr(&pattern.parse::<Expr>().unwrap())
+}
+
+fn program(pattern: &str) -> Program {
+    Program::from_nfa(&nfa(pattern))
+}
+
+#[test]
+fn captures_groups() {
+    let program = program("(a+)(b|c)");
+    assert_eq!(Some(vec![Some(0), Some(3), Some(0), Some(2), Some(2), Some(3)]),
+               PikeVM::new(&program).captures(&mut Cache::new(), b"aacd"));
+    assert_eq!(None, PikeVM::new(&program).captures(&mut Cache::new(), b"aad"));
+}
+
+#[test]
+fn leaves_unmatched_groups_empty() {
+    let program = program("a(x)?b");
+    assert_eq!(Some(vec![Some(0), Some(2), None, None]), PikeVM::new(&program).captures(&mut Cache::new(), b"ab"));
+}
+
+#[test]
+fn captures_last_iteration_of_repeated_group() {
+    let program = program("(ab|c)+");
+    assert_eq!(Some(vec![Some(0), Some(5), Some(3), Some(5)]), PikeVM::new(&program).captures(&mut Cache::new(), b"abcab"));
+}
+
+#[test]
+fn matches_empty_nfa() {
+    assert_eq!(Some(vec![Some(0), Some(0)]), PikeVM::new(&Program::from_nfa(&NFA::new())).captures(&mut Cache::new(), b"a"));
+}
+
+#[test]
+fn agrees_with_matcher() {
+    let patterns = ["a?a?aa", "((abc|acc)b)+", "[ab]+c", ".*c", "(ab|bc)*d", "(a|ab)(c|bcd)", "(a*)*b?"];
+    let texts = ["", "aa", "a", "abcbaccb", "abbac", "xyzc", "ababbcd", "abcd", "aab"];
+
+    let mut cache = Cache::new(); // reused across NFAs and texts
+    for pattern in patterns.iter() {
+        let nfa = nfa(pattern);
+        for text in texts.iter() {
+            let expected = Matcher::new(&nfa, text).run(&mut matcher::Cache::new());
+            let captures = PikeVM::new(&Program::from_nfa(&nfa)).captures(&mut cache, text.as_bytes());
+            assert_eq!(expected, captures.map(|slots| slots[1].unwrap()), "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn glushkov_nfa_agrees_with_thompson() {
+    let patterns = ["a?a?aa", "((abc|acc)b)+", "[ab]+c", ".*c", "(ab|bc)*d", "(a|ab)(c|bcd)", "(a*)?b",
+                    "(z*a*)*", "((z)*)?((z)+)*(.)*"];
+    let texts = ["", "aa", "a", "abcbaccb", "abbac", "xyzc", "ababbcd", "abcd", "aab", "b", "zz", "zaz"];
+
+    let glushkov = Builder::new().construction(Construction::Glushkov);
+    let mut cache = Cache::new();
+    for pattern in patterns.iter() {
+        let expr = pattern.parse::<Expr>().unwrap();
+        let thompson = NFA::from_expr(&expr);
+        let nfa = glushkov.try_build(&expr).unwrap();
+        for text in texts.iter() {
+            let expected = Matcher::new(&thompson, text).run(&mut matcher::Cache::new());
+            assert_eq!(expected, Matcher::new(&nfa, text).run(&mut matcher::Cache::new()), "{} on {:?}", pattern, text);
+            assert_eq!(expected.map(|end| vec![Some(0), Some(end)]),
+                       PikeVM::new(&Program::from_nfa(&nfa)).captures(&mut cache, text.as_bytes()), "{} on {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn stops_when_limits_are_exceeded() {
+    let program = program("(a|b)*c");
+    let vm = PikeVM::new(&program);
+    let text = b"ababc";
+    assert_eq!(Ok(vm.captures(&mut Cache::new(), text)),
+               vm.captures_limited(&mut Cache::new(), text, &mut Budget::new(Limits::new().steps(100).bytes(5))));
+    assert_eq!(Err(MatchError::BudgetExceeded), vm.captures_limited(&mut Cache::new(), text, &mut Budget::new(Limits::new().steps(3))));
+    assert_eq!(Err(MatchError::BudgetExceeded), vm.captures_limited(&mut Cache::new(), text, &mut Budget::new(Limits::new().bytes(4))));
+}
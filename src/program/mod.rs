@@ -0,0 +1,241 @@
+use std::fmt;
+use std::collections::HashMap;
+
+use ::nfa::{self, NFA, State, Transition, Condition};
+
+#[cfg(test)] mod spec;
+
+#[derive(PartialEq,Debug,Clone,Copy)]
+pub enum Inst {
+    Byte(u8, u32), // consumes the byte, then goes to the target
+    Range(u8, u8, u32), // consumes a byte in the inclusive range
+    Class(u32, u32), // consumes a byte in a class of the pool
+    Split(u32, u32), // tries the first target, then the second
+    Jump(u32),
+    Save(u32, u32), // records the position in a slot
+    Match(u32) // end of the pattern with this index
+}
+
+// a set of bytes, a bit each
+#[derive(PartialEq,Eq,Hash,Clone,Copy)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    fn from_condition(condition: &Condition) -> ByteSet {
+        let mut bits = [0; 4];
+        for byte in 0..256 {
+            if condition.matches(byte as u8) {
+                bits[byte / 64] |= 1 << (byte % 64);
+            }
+        }
+        ByteSet(bits)
+    }
+
+    fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+}
+
+// the NFA as a flat list of instructions, with splits already in order of
+// priority and byte classes shared in one pool
+#[derive(PartialEq,Clone)]
+pub struct Program {
+    insts: Vec<Inst>,
+    classes: Vec<ByteSet>,
+    start: u32,
+    num_slots: usize
+}
+
+// the maps that share instructions and classes while compiling
+struct Compiler {
+    program: Program,
+    classes: HashMap<ByteSet, u32>,
+    matches: HashMap<usize, u32>, // by pattern
+    splits: HashMap<(u32, u32), u32>
+}
+
+impl Compiler {
+    fn consume(&mut self, condition: &Condition, target: u32) -> Inst {
+        let mut bytes = (0..256).map(|b| b as u8).filter(|&b| condition.matches(b));
+        let first = bytes.next();
+        let (count, last) = bytes.fold((1, first), |(count, _), b| (count + 1, Some(b)));
+
+        match (first, last) {
+            (Some(byte), _) if count == 1 => Inst::Byte(byte, target),
+            (Some(first), Some(last)) if last as usize - first as usize + 1 == count => Inst::Range(first, last, target),
+            _ => Inst::Class(self.class(ByteSet::from_condition(condition)), target)
+        }
+    }
+
+    fn class(&mut self, set: ByteSet) -> u32 {
+        let classes = &mut self.program.classes;
+        *self.classes.entry(set).or_insert_with(|| {
+            classes.push(set);
+            classes.len() as u32 - 1
+        })
+    }
+
+    fn push(&mut self, inst: Inst) -> u32 {
+        self.program.insts.push(inst);
+        self.program.insts.len() as u32 - 1
+    }
+
+    fn target(&mut self, out: &Transition) -> u32 {
+        let pattern = match *out {
+            Transition::Id(id) => return id as u32,
+            Transition::End => 0,
+            Transition::Match(pattern) => pattern,
+            Transition::Detached => panic!("cannot compile incomplete NFA")
+        };
+        if let Some(&pc) = self.matches.get(&pattern) {
+            return pc;
+        }
+        let pc = self.push(Inst::Match(pattern as u32));
+        self.matches.insert(pattern, pc);
+        pc
+    }
+
+    // a chain of splits trying each of outs in turn
+    fn targets(&mut self, outs: &[Transition]) -> u32 {
+        match outs.split_first() {
+            None => {
+                // nothing follows, so no byte is ever consumed
+                let class = self.class(ByteSet([0; 4]));
+                let pc = self.program.insts.len() as u32;
+                self.push(Inst::Class(class, pc))
+            },
+            Some((out, [])) => self.target(out),
+            Some((out, rest)) => {
+                let split = (self.target(out), self.targets(rest));
+                if let Some(&pc) = self.splits.get(&split) {
+                    return pc;
+                }
+                let pc = self.push(Inst::Split(split.0, split.1));
+                self.splits.insert(split, pc);
+                pc
+            }
+        }
+    }
+}
+
+impl Program {
+    // instruction i is NFA state i, followed by the matches and the splits
+    // that forks with several outputs take
+    pub fn from_nfa(nfa: &NFA) -> Program {
+        let program = Program {
+            insts: vec![Inst::Jump(0); nfa.num_states()],
+            classes: Vec::new(),
+            start: nfa.start() as u32,
+            num_slots: nfa.num_slots()
+        };
+        let mut compiler = Compiler {
+            program,
+            classes: HashMap::new(),
+            matches: HashMap::new(),
+            splits: HashMap::new()
+        };
+        if nfa.num_states() == 0 { // regex is empty
+            compiler.target(&Transition::End);
+            return compiler.program;
+        }
+
+        for id in 0..nfa.num_states() {
+            compiler.program.insts[id] = match *nfa.get_state(id).unwrap() {
+                State::State{condition: Condition::None, ref out} => Inst::Jump(compiler.target(out)),
+                State::State{ref condition, ref out} => {
+                    let target = compiler.target(out);
+                    compiler.consume(condition, target)
+                },
+                State::Split{ref out1, ref out2} => {
+                    let (first, second) = nfa.split_order(out1, out2);
+                    Inst::Split(compiler.target(first), compiler.target(second))
+                },
+                State::Save{slot, ref out} => Inst::Save(slot as u32, compiler.target(out)),
+                State::Fork{condition: Condition::None, ref outs} => Inst::Jump(compiler.targets(outs)),
+                State::Fork{ref condition, ref outs} => {
+                    let target = compiler.targets(outs);
+                    compiler.consume(condition, target)
+                }
+            };
+        }
+
+        compiler.program
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.insts.len()
+    }
+
+    pub fn num_slots(&self) -> usize {
+        self.num_slots
+    }
+
+    pub fn inst(&self, pc: u32) -> Inst {
+        self.insts[pc as usize]
+    }
+
+    // where a consuming instruction goes on byte, none if it does not match
+    pub fn next(&self, inst: Inst, byte: u8) -> Option<u32> {
+        match inst {
+            Inst::Byte(b, target) if b == byte => Some(target),
+            Inst::Range(first, last, target) if first <= byte && byte <= last => Some(target),
+            Inst::Class(class, target) if self.classes[class as usize].contains(byte) => Some(target),
+            _ => None
+        }
+    }
+}
+
+fn byte(b: u8) -> String {
+    if b.is_ascii_graphic() {
+        format!("'{}'", b as char)
+    } else {
+        format!("{:#04x}", b)
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inst::Byte(b, target) => write!(f, "byte {} -> {}", byte(b), target),
+            Inst::Range(first, last, target) => write!(f, "range {}-{} -> {}", byte(first), byte(last), target),
+            Inst::Class(class, target) => write!(f, "class {} -> {}", class, target),
+            Inst::Split(first, second) => write!(f, "split {}, {}", first, second),
+            Inst::Jump(target) => write!(f, "jump {}", target),
+            Inst::Save(slot, target) => write!(f, "save {} -> {}", slot, target),
+            Inst::Match(pattern) => write!(f, "match {}", pattern)
+        }
+    }
+}
+
+// one instruction a line, then the classes as ranges of bytes, e.g.
+//
+//   0 byte 'a' -> 1
+// > 1 split 0, 2
+//   2 class 0 -> 3
+//   3 match 0
+// class 0 ['0'-'9', 'a'-'f']
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.insts.len().saturating_sub(1).to_string().len();
+        for (pc, inst) in self.insts.iter().enumerate() {
+            let marker = if pc as u32 == self.start { ">" } else { " " };
+            writeln!(f, "{} {:>width$} {}", marker, pc, inst, width = width)?;
+        }
+
+        for (class, set) in self.classes.iter().enumerate() {
+            let members = (0..256).map(|b| b as u8).filter(|&b| set.contains(b)).collect::<Vec<_>>();
+            writeln!(f, "class {} [{}]", class, nfa::byte_ranges(&members, byte, ", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,52 @@
+use ::expr::Expr;
+use ::nfa::{NFA, Builder, Construction};
+use super::{Program, Inst};
+
+fn program(pattern: &str) -> Program {
+    Program::from_nfa(&NFA::from_expr(&pattern.parse::<Expr>().unwrap()))
+}
+
+#[test]
+fn disassembles() {
+    assert_eq!("  0 byte 'a' -> 1\n\
+                > 1 split 0, 2\n  \
+                  2 byte 'b' -> 3\n  \
+                  3 match 0\n", program("a*b").to_string());
+
+    assert_eq!("> 0 class 0 -> 1\n  \
+                  1 split 0, 3\n  \
+                  2 byte 'x' -> 4\n  \
+                  3 split 2, 4\n  \
+                  4 match 0\n\
+                class 0 ['0'-'9', 'a'-'f']\n", program("[0123456789abcdef]+x?").to_string());
+}
+
+#[test]
+fn compiles_conditions() {
+    let program = program("(?-u)[0123456789]\\xFF.");
+    assert_eq!(Inst::Range(b'0', b'9', 1), program.inst(0));
+    assert_eq!(Inst::Byte(0xFF, 2), program.inst(1));
+    assert_eq!(Inst::Class(0, 3), program.inst(2));
+    assert_eq!(Some(3), program.next(program.inst(2), b'a'));
+    assert_eq!(None, program.next(program.inst(2), b'\n'));
+}
+
+#[test]
+fn shares_classes_and_instructions() {
+    let program = program("[ac]b[ac]");
+    assert_eq!(4, program.len());
+    assert_eq!("class 0 ['a', 'c']\n", program.to_string().lines().last().map(|line| format!("{}\n", line)).unwrap());
+
+    // the follow lists of both last positions are the same chain of splits
+    let expr = "(ab|ac)*".parse::<Expr>().unwrap();
+    let program = Program::from_nfa(&Builder::new().construction(Construction::Glushkov).try_build(&expr).unwrap());
+    assert_eq!(8, program.len());
+    assert_eq!(1, program.to_string().matches("match").count());
+}
+
+#[test]
+fn compiles_empty_regex() {
+    let program = Program::from_nfa(&NFA::new());
+    assert_eq!(Inst::Match(0), program.inst(program.start()));
+    assert_eq!("> 0 match 0\n", format!("{:?}", program));
+}
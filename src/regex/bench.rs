@@ -41,3 +41,15 @@ fn bench_difficult_regex(b: &mut Bencher, size: usize) {
 }
 
 
+
+#[bench]
+fn bench_find_log_line(b: &mut Bencher) {
+    let mut text = "INFO: request served in 12ms\n".repeat(200);
+    text.push_str("ERROR: upstream timeout\n");
+
+    let regex = Regex::from("ERROR: .*timeout").unwrap();
+
+    b.iter(|| {
+        assert_eq!(Some((5800, 5823)), regex.find(&text));
+    });
+}
@@ -1,29 +1,126 @@
 use ::expr::Expr;
-use ::nfa::NFA;
-use ::matcher::Matcher;
+use ::bytes;
+use ::stream::StreamMatcher;
+use ::matcher::{Observer, Trace};
+use ::limits::{Limits, MatchError, CompileLimits, CompileError};
+use ::format::{self, Reader};
+
+pub use ::bytes::{Cache, Captures};
 
 #[cfg(test)] mod spec;
 #[cfg(test)] mod bench;
 
+// a serialized bytes::Regex may match invalid utf-8, so a Regex is written
+// under its own magic
+const REGEX_MAGIC: &[u8; 4] = b"RXRS";
+
 #[derive(PartialEq,Debug)]
 pub struct Regex {
-    nfa: NFA
+    bytes: bytes::Regex // never matches invalid utf-8, so offsets fall on char boundaries
 }
 
 impl Regex {
     pub fn from(pattern: &str) -> Result<Regex, String> {
-        let expr = pattern.parse::<Expr>();
-        expr.map(|expr| Regex {
-            nfa: NFA::from_expr(&expr)
+        Self::with_limits(pattern, CompileLimits::new()).map_err(|error| error.to_string())
+    }
+
+    pub fn with_limits(pattern: &str, limits: CompileLimits) -> Result<Regex, CompileError> {
+        let expr = Expr::parse(pattern, limits)?;
+        if expr.can_match_invalid_utf8() {
+            return Err(CompileError::Pattern("pattern can match invalid utf-8, use bytes::Regex".to_owned()));
+        }
+
+        Ok(Regex {
+            bytes: bytes::Regex::from_expr(&expr, limits)?
+        })
+    }
+
+    // the serialized bytes::Regex, see bytes::Regex::to_bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        format::write_header(&mut out, REGEX_MAGIC);
+        format::write_block(&mut out, &self.bytes.to_bytes());
+        out
+    }
+
+    // only loads what Regex::to_bytes wrote, which never matches invalid utf-8
+    pub fn from_bytes(bytes: &[u8]) -> Result<Regex, String> {
+        let mut reader = Reader::new(bytes, REGEX_MAGIC)?;
+        let regex = bytes::Regex::from_bytes(reader.block()?)?;
+        reader.finish()?;
+        Ok(Regex {
+            bytes: regex
         })
     }
 
     pub fn is_match(&self, text: &str) -> bool {
-        self.match_offset(text).is_some()
+        self.bytes.is_match(text.as_bytes())
     }
 
     pub fn match_offset(&self, text: &str) -> Option<usize> {
-        Matcher::new(self.nfa.clone(), text).run()
+        self.bytes.match_offset(text.as_bytes())
+    }
+
+    // capture groups of the match at the start of text, group 0 being the
+    // whole match. Offsets fall on char boundaries
+    pub fn captures(&self, text: &str) -> Option<Captures> {
+        self.bytes.captures(text.as_bytes())
+    }
+
+    // the leftmost match anywhere in text, as start and end offsets
+    pub fn find(&self, text: &str) -> Option<(usize, usize)> {
+        self.bytes.find(text.as_bytes())
+    }
+
+    pub fn is_match_limited(&self, text: &str, limits: Limits) -> Result<bool, MatchError> {
+        self.bytes.is_match_limited(text.as_bytes(), limits)
+    }
+
+    pub fn match_offset_limited(&self, text: &str, limits: Limits) -> Result<Option<usize>, MatchError> {
+        self.bytes.match_offset_limited(text.as_bytes(), limits)
+    }
+
+    pub fn captures_limited(&self, text: &str, limits: Limits) -> Result<Option<Captures>, MatchError> {
+        self.bytes.captures_limited(text.as_bytes(), limits)
     }
-}
 
+    pub fn find_limited(&self, text: &str, limits: Limits) -> Result<Option<(usize, usize)>, MatchError> {
+        self.bytes.find_limited(text.as_bytes(), limits)
+    }
+
+    pub fn is_match_with(&self, cache: &mut Cache, text: &str) -> bool {
+        self.bytes.is_match_with(cache, text.as_bytes())
+    }
+
+    pub fn match_offset_with(&self, cache: &mut Cache, text: &str) -> Option<usize> {
+        self.bytes.match_offset_with(cache, text.as_bytes())
+    }
+
+    pub fn captures_with(&self, cache: &mut Cache, text: &str) -> Option<Captures> {
+        self.bytes.captures_with(cache, text.as_bytes())
+    }
+
+    pub fn find_with(&self, cache: &mut Cache, text: &str) -> Option<(usize, usize)> {
+        self.bytes.find_with(cache, text.as_bytes())
+    }
+
+    pub fn stream(&self) -> StreamMatcher<'_> {
+        self.bytes.stream()
+    }
+
+    pub fn find_stream(&self) -> StreamMatcher<'_> {
+        self.bytes.find_stream()
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.bytes.to_dot()
+    }
+
+    pub fn trace(&self, text: &str) -> Trace {
+        self.bytes.trace(text.as_bytes())
+    }
+
+    pub fn trace_with<O: Observer>(&self, text: &str, observer: &mut O) -> Option<usize> {
+        self.bytes.trace_with(text.as_bytes(), observer)
+    }
+}
@@ -1,4 +1,10 @@
-use super::Regex;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ::{Step, Edge};
+use ::limits::{Limits, MatchError, CompileLimits, CompileError};
+use super::{Regex, Cache};
 
 #[test]
 fn only_matches_at_start_of_text() {
@@ -144,3 +150,242 @@ fn char_class_matches() {
     assert!(regex.is_match("adbd"));
 }
 
+
+#[test]
+fn matches_pattern_with_exponential_dfa() {
+    let mut pattern = "(a|b)*a".to_owned();
+    pattern.push_str(&"(a|b)".repeat(20));
+    let regex = Regex::from(&pattern).unwrap();
+
+    let text = "ab".repeat(50);
+    assert!(regex.is_match(&text));
+    assert!(!regex.is_match(&text[..20]));
+}
+
+#[test]
+fn matches_non_ascii_chars() {
+    let regex = Regex::from("é+[ñö]").unwrap();
+    assert_eq!(Some(6), regex.match_offset("ééö"));
+    assert!(!regex.is_match("e"));
+
+    assert_eq!(Some(3), Regex::from("..").unwrap().match_offset("aé"));
+}
+
+#[test]
+fn rejects_patterns_matching_invalid_utf8() {
+    assert!(Regex::from("(?-u).").is_err());
+    assert!(Regex::from("(?-u)\\xFF").is_err());
+    assert!(Regex::from("(?-u)a\\x7F").is_ok());
+}
+
+#[test]
+fn searches_with_explicit_cache() {
+    let first = Regex::from("a+b").unwrap();
+    let second = Regex::from("a+c").unwrap();
+    let mut cache = Cache::new();
+
+    assert!(first.is_match_with(&mut cache, "aab"));
+    assert!(!second.is_match_with(&mut cache, "aab")); // the cache is reset for another regex
+    assert!(second.is_match_with(&mut cache, "aac"));
+    assert_eq!(Some(3), first.match_offset_with(&mut cache, "aabc"));
+}
+
+#[test]
+fn is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Regex>();
+    assert_send_sync::<Cache>();
+}
+
+#[test]
+fn matches_from_many_threads() {
+    let regex = Arc::new(Regex::from("(a|b)*c[de]+").unwrap());
+
+    let threads = (0..8).map(|i| {
+        let regex = regex.clone();
+        thread::spawn(move || {
+            let text: String = "ab".repeat(i * 10) + "cdd";
+            for _ in 0..500 {
+                assert_eq!(Some(i * 20 + 3), regex.match_offset(&text));
+                assert!(regex.is_match(&text));
+                assert!(!regex.is_match(&text[..i * 20 + 1]));
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn finds_leftmost_match_anywhere() {
+    let regex = Regex::from("ERROR: .*timeout").unwrap();
+    assert_eq!(Some((9, 28)), regex.find("INFO: ok\nERROR: read timeout"));
+    assert_eq!(None, regex.find("ERROR: timed out"));
+
+    assert_eq!(Some((1, 4)), Regex::from("b+").unwrap().find("abbbc"));
+    assert_eq!(Some((0, 0)), Regex::from("x*").unwrap().find("abc"));
+    assert_eq!(Some((3, 5)), Regex::from("[0123456789]+").unwrap().find("ab 42"));
+    assert_eq!(None, Regex::from("[0123456789]+").unwrap().find(""));
+}
+
+#[test]
+fn finds_match_after_failed_candidate() {
+    let regex = Regex::from("ab(c|d)").unwrap();
+    assert_eq!(Some((4, 7)), regex.find("abx abd"));
+    assert_eq!(Some((8, 12)), Regex::from("é+ñ").unwrap().find("aéé ñéñ"));
+}
+
+#[test]
+fn captures_groups() {
+    let regex = Regex::from("([0123456789]+)-([0123456789]+)").unwrap();
+    let captures = regex.captures("12-345 rest").unwrap();
+
+    assert_eq!(3, captures.len());
+    assert_eq!(Some((0, 6)), captures.get(0));
+    assert_eq!(Some((0, 2)), captures.get(1));
+    assert_eq!(Some((3, 6)), captures.get(2));
+    assert_eq!(None, captures.get(3));
+    assert_eq!(None, regex.captures("12-"));
+}
+
+#[test]
+fn captures_same_groups_on_long_text() {
+    // not one-pass, so the long text goes through the Pike VM
+    let regex = Regex::from("(a|ab)*(c)").unwrap();
+    let text = "ab".repeat(20000) + "c";
+
+    let captures = regex.captures(&text).unwrap();
+    assert_eq!(Some((39998, 40000)), captures.get(1));
+    assert_eq!(Some((40000, 40001)), captures.get(2));
+    assert_eq!(Some(40001), regex.match_offset(&text));
+}
+
+#[test]
+fn exports_nfa_as_dot() {
+    let dot = Regex::from("ab").unwrap().to_dot();
+    assert!(dot.starts_with("digraph nfa {"));
+    assert!(dot.contains("0 -> 1 [label=\"a\"];"));
+    assert!(dot.contains("1 -> end [label=\"b\"];"));
+}
+
+#[test]
+fn traces_match() {
+    let regex = Regex::from("ab?").unwrap();
+    let trace = regex.trace("ac");
+    assert_eq!(Some(1), trace.matched);
+    assert_eq!(Some(1), regex.match_offset("ac"));
+    assert!(trace.to_string().ends_with("match at 1\n"));
+    assert_eq!(Step {
+        threads: vec![0],
+        edges: vec![Edge {
+            from: 0,
+            to: Some(2),
+            byte: Some(b'a')
+        }]
+    }, trace.steps[0]);
+}
+
+#[test]
+fn reports_exceeded_budget() {
+    let regex = Regex::from("(a|b)*c").unwrap();
+    let text = "ab".repeat(1000) + "c";
+
+    assert_eq!(Ok(true), regex.is_match_limited(&text, Limits::new()));
+    assert_eq!(Ok(Some(2001)), regex.match_offset_limited(&text, Limits::new().bytes(2001)));
+    assert_eq!(Ok(None), regex.match_offset_limited("abd", Limits::new().steps(10)));
+    assert_eq!(Err(MatchError::BudgetExceeded), regex.is_match_limited(&text, Limits::new().steps(100)));
+    assert_eq!(Err(MatchError::BudgetExceeded), regex.captures_limited(&text, Limits::new().bytes(100)));
+    assert_eq!(Err(MatchError::BudgetExceeded), regex.captures_limited(&text, Limits::new().deadline(Instant::now())));
+    assert_eq!(Some((1999, 2000)), regex.captures_limited(&text, Limits::new().timeout(Duration::from_secs(60)))
+                                        .unwrap().unwrap().get(1));
+    assert_eq!("match budget exceeded", MatchError::BudgetExceeded.to_string());
+}
+
+#[test]
+fn finds_within_limits() {
+    let regex = Regex::from("(a|b)*c").unwrap();
+    let text = "x".repeat(10) + "abc";
+
+    assert_eq!(Ok(Some((10, 13))), regex.find_limited(&text, Limits::new().bytes(13)));
+    assert_eq!(Err(MatchError::BudgetExceeded), regex.find_limited(&text, Limits::new().bytes(12)));
+    assert_eq!(Err(MatchError::BudgetExceeded), regex.find_limited(&text, Limits::new().steps(5)));
+    assert_eq!(Ok(None), regex.find_limited("xyz", Limits::new().bytes(3)));
+
+    let literal = Regex::from("abc").unwrap();
+    assert_eq!(Ok(Some((10, 13))), literal.find_limited(&text, Limits::new()));
+    assert_eq!(Err(MatchError::BudgetExceeded), literal.find_limited(&text, Limits::new().bytes(12)));
+}
+
+#[test]
+fn compiles_long_patterns_within_limits() {
+    let pattern = "ab".repeat(2000);
+    assert!(Regex::from(&pattern).unwrap().is_match(&pattern));
+
+    let pattern = format!("{}d", "abc|".repeat(2000));
+    assert_eq!(Some(1), Regex::from(&pattern).unwrap().match_offset("d"));
+
+    // only literals, so held to the higher default for literal patterns
+    let pattern = "ab".repeat(20000);
+    assert!(Regex::from(&pattern).unwrap().is_match(&pattern));
+
+    let pattern = format!("{}c*", pattern);
+    assert_eq!(Err(CompileError::TooManyStates(10000)), Regex::with_limits(&pattern, CompileLimits::new()));
+    assert_eq!(Err("pattern needs more than 10000 NFA states".to_owned()), Regex::from(&pattern));
+}
+
+#[test]
+fn holds_literal_patterns_to_explicit_limits() {
+    let limits = CompileLimits::new().states(4);
+    assert_eq!(Err(CompileError::TooManyStates(4)), Regex::with_limits("abcdefghijklmnop", limits));
+    assert_eq!(Err(CompileError::TooManyStates(4)), Regex::with_limits("abc|def", limits));
+    assert!(Regex::with_limits("ab|c", limits).is_ok());
+}
+
+#[test]
+fn rejects_large_classes_before_expanding_them() {
+    let class = (0x80..0x800).filter_map(::std::char::from_u32).collect::<String>();
+    let pattern = format!("[{}]", class).repeat(2);
+    assert_eq!(Err(CompileError::TooManyStates(10000)), Regex::with_limits(&pattern, CompileLimits::new()));
+}
+
+#[test]
+fn compiles_long_keyword_lists() {
+    let keywords = (0..3000).map(|i| format!("word{:05}", i)).collect::<Vec<_>>();
+    let regex = Regex::from(&keywords.join("|")).unwrap();
+    assert_eq!(Some((4, 13)), regex.find("the word02999 is blocked"));
+}
+
+#[test]
+fn rejects_deep_nesting() {
+    let pattern = format!("{}a{}", "(".repeat(100000), ")".repeat(100000));
+    assert_eq!(Err(CompileError::NestingTooDeep(250)), Regex::with_limits(&pattern, CompileLimits::new()));
+
+    let pattern = format!("a{}", "*".repeat(100000));
+    assert_eq!(Err(CompileError::NestingTooDeep(250)), Regex::with_limits(&pattern, CompileLimits::new()));
+
+    let limits = CompileLimits::new().nesting_depth(2);
+    assert!(Regex::with_limits("((a))b*", limits).is_ok());
+    assert_eq!(Err(CompileError::NestingTooDeep(2)), Regex::with_limits("((a)*)", limits));
+}
+
+#[test]
+fn rejects_large_classes() {
+    let limits = CompileLimits::new().class_size(10);
+    assert!(Regex::with_limits("[abcdefghij]", limits).is_ok());
+    assert_eq!(Err(CompileError::ClassTooLarge(10)), Regex::with_limits("[abcdefghijk]", limits));
+    assert_eq!(Err(CompileError::Pattern("unclosed char class".to_owned())), Regex::with_limits("[a", limits));
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    let regex = Regex::from("(é|b)+c?").unwrap();
+    let loaded = Regex::from_bytes(&regex.to_bytes()).unwrap();
+    assert_eq!(regex, loaded);
+    assert_eq!(Some((1, 6)), loaded.find("xébbc"));
+
+    // a bytes regex may match invalid utf-8, so it does not load as a Regex
+    let bytes = ::bytes::Regex::from("(é|b)+c?").unwrap().to_bytes();
+    assert_eq!(Some("not a serialized RXRS".to_owned()), Regex::from_bytes(&bytes).err());
+}
@@ -0,0 +1,187 @@
+use ::expr::Expr;
+use ::nfa::{NFA, Closure};
+use ::lazy::{self, LazyDFA, Interrupted};
+use ::limits::{Limits, Budget, MatchError, CompileLimits, CompileError};
+use ::literal;
+use ::aho_corasick::AhoCorasick;
+
+#[cfg(test)] mod spec;
+
+// matches several patterns at the start of text in a single scan
+#[derive(PartialEq,Debug)]
+pub struct RegexSet {
+    nfa: NFA,
+    literals: Option<LiteralSet>, // when every pattern is only literals
+    len: usize
+}
+
+#[derive(PartialEq,Debug)]
+struct LiteralSet {
+    automaton: AhoCorasick,
+    patterns: Vec<usize> // the pattern of each literal
+}
+
+impl LiteralSet {
+    fn new(exprs: &[Expr]) -> Option<LiteralSet> {
+        let mut literals = Vec::new();
+        let mut patterns = Vec::new();
+        for (pattern, expr) in exprs.iter().enumerate() {
+            for literal in literal::alternatives(expr)? {
+                literals.push(literal);
+                patterns.push(pattern);
+            }
+        }
+
+        Some(LiteralSet {
+            automaton: AhoCorasick::new(&literals),
+            patterns
+        })
+    }
+}
+
+impl RegexSet {
+    pub fn new(patterns: &[&str]) -> Result<RegexSet, String> {
+        Self::with_limits(patterns, CompileLimits::new()).map_err(|error| error.to_string())
+    }
+
+    // each pattern is held to limits as it is parsed, and the NFA of the
+    // patterns together, which only sets with a pattern that is not literal
+    // build, to the states limit
+    pub fn with_limits(patterns: &[&str], limits: CompileLimits) -> Result<RegexSet, CompileError> {
+        let exprs = patterns.iter()
+            .map(|pattern| Expr::parse(pattern, limits))
+            .collect::<Result<Vec<_>, _>>()?;
+        if exprs.iter().any(|expr| expr.can_match_invalid_utf8()) {
+            return Err(CompileError::Pattern("pattern can match invalid utf-8".to_owned()));
+        }
+
+        // a set of literal patterns never runs the NFA, so it is left empty
+        let literals = LiteralSet::new(&exprs);
+        let nfa = if literals.is_some() {
+            NFA::new()
+        } else {
+            NFA::try_from_exprs(&exprs, limits.max_states(false))?
+        };
+
+        Ok(RegexSet {
+            nfa,
+            literals,
+            len: exprs.len()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.is_match_within(text, &mut Budget::unlimited()).unwrap() // cannot run out
+    }
+
+    // indices of the patterns that match, in ascending order
+    pub fn matches(&self, text: &str) -> Vec<usize> {
+        self.matches_within(text, &mut Budget::unlimited()).unwrap() // cannot run out
+    }
+
+    // searches that give up with an error once they exceed limits
+    pub fn is_match_limited(&self, text: &str, limits: Limits) -> Result<bool, MatchError> {
+        self.is_match_within(text, &mut Budget::new(limits))
+    }
+
+    pub fn matches_limited(&self, text: &str, limits: Limits) -> Result<Vec<usize>, MatchError> {
+        self.matches_within(text, &mut Budget::new(limits))
+    }
+
+    fn is_match_within(&self, text: &str, budget: &mut Budget) -> Result<bool, MatchError> {
+        if self.is_empty() {
+            return Ok(false);
+        }
+        if let Some(ref literals) = self.literals {
+            return Ok(!literals.automaton.prefixes_of(text.as_bytes(), budget)?.is_empty());
+        }
+
+        let mut cache = lazy::Cache::new();
+        let dfa = LazyDFA::new(&self.nfa, lazy::DEFAULT_CACHE_SIZE);
+
+        match dfa.is_match(&mut cache, text.as_bytes(), budget) {
+            Ok(is_match) => Ok(is_match),
+            Err(Interrupted::CacheThrashed) => self.matches_within(text, budget).map(|matched| !matched.is_empty()),
+            Err(Interrupted::Exceeded(error)) => Err(error)
+        }
+    }
+
+    fn matches_within(&self, text: &str, budget: &mut Budget) -> Result<Vec<usize>, MatchError> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut matched = vec![false; self.len];
+        if let Some(ref literals) = self.literals {
+            for literal in literals.automaton.prefixes_of(text.as_bytes(), budget)? {
+                matched[literals.patterns[literal]] = true;
+            }
+            return Ok((0..self.len).filter(|&i| matched[i]).collect());
+        }
+
+        let mut cache = lazy::Cache::new();
+        let dfa = LazyDFA::new(&self.nfa, lazy::DEFAULT_CACHE_SIZE);
+
+        match self.scan_lazy(&dfa, &mut cache, text.as_bytes(), &mut matched, budget) {
+            Ok(()) => {},
+            Err(Interrupted::CacheThrashed) => self.scan_nfa(text.as_bytes(), &mut matched, budget)?,
+            Err(Interrupted::Exceeded(error)) => return Err(error)
+        }
+
+        Ok((0..self.len).filter(|&i| matched[i]).collect())
+    }
+
+    // a step is a state entered
+    fn scan_lazy(&self, dfa: &LazyDFA, cache: &mut lazy::Cache, text: &[u8],
+                 matched: &mut [bool], budget: &mut Budget) -> Result<(), Interrupted> {
+        let mut current = dfa.start_state(cache)?;
+
+        for (i, &byte) in text.iter().enumerate() {
+            if !Self::record(cache.closure(current), matched) {
+                return Ok(());
+            }
+            budget.step()?;
+            budget.read(i)?;
+            current = dfa.next_state(cache, current, byte)?;
+        }
+
+        Self::record(cache.closure(current), matched);
+        Ok(())
+    }
+
+    // steps through the NFA directly when the lazy DFA cache is thrashing.
+    // A step is an NFA state moved over a byte
+    fn scan_nfa(&self, text: &[u8], matched: &mut [bool], budget: &mut Budget) -> Result<(), MatchError> {
+        let mut closure = self.nfa.start_closure();
+
+        for (i, &byte) in text.iter().enumerate() {
+            if !Self::record(&closure, matched) {
+                return Ok(());
+            }
+            budget.take(closure.states.len())?;
+            budget.read(i)?;
+            closure = self.nfa.step(&closure.states, byte);
+        }
+
+        Self::record(&closure, matched);
+        Ok(())
+    }
+
+    // marks the patterns matched at this point, returning whether the scan
+    // could still find another one
+    fn record(closure: &Closure, matched: &mut [bool]) -> bool {
+        for &pattern in &closure.matches {
+            matched[pattern] = true;
+        }
+
+        !closure.states.is_empty() && matched.iter().any(|&m| !m)
+    }
+}
@@ -0,0 +1,115 @@
+use ::expr::Expr;
+use ::nfa::NFA;
+use ::limits::{Limits, Budget, MatchError, CompileLimits, CompileError};
+use super::RegexSet;
+
+#[test]
+fn creates_new() {
+    assert_eq!(3, RegexSet::new(&["a", "b+", "[cd]"]).unwrap().len());
+    assert!(RegexSet::new(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn returns_err_on_invalid_pattern() {
+    assert!(RegexSet::new(&["a", ""]).is_err());
+}
+
+#[test]
+fn reports_matching_patterns() {
+    let set = RegexSet::new(&["ERROR", "E.*", "WARN", "[EW][RA]"]).unwrap();
+
+    assert_eq!(vec![0, 1, 3], set.matches("ERROR: timeout"));
+    assert_eq!(vec![2, 3], set.matches("WARN: disk"));
+    assert_eq!(vec![1], set.matches("E"));
+    assert_eq!(Vec::<usize>::new(), set.matches("INFO: ok"));
+}
+
+#[test]
+fn only_matches_at_start_of_text() {
+    let set = RegexSet::new(&["ab", "b"]).unwrap();
+    assert_eq!(Vec::<usize>::new(), set.matches("cab"));
+    assert!(!set.is_match("cab"));
+}
+
+#[test]
+fn matches_empty_patterns() {
+    let set = RegexSet::new(&["a*", "b"]).unwrap();
+    assert_eq!(vec![0], set.matches(""));
+    assert_eq!(vec![0, 1], set.matches("b"));
+}
+
+#[test]
+fn is_match() {
+    let set = RegexSet::new(&["ab+c", "x?y"]).unwrap();
+    assert!(set.is_match("abbc"));
+    assert!(set.is_match("y"));
+    assert!(set.is_match("xyz"));
+    assert!(!set.is_match("ab"));
+    assert!(!set.is_match(""));
+
+    assert!(!RegexSet::new(&[]).unwrap().is_match("a"));
+}
+
+#[test]
+fn reports_same_patterns_when_nfa_is_stepped() {
+    let set = RegexSet::new(&["ERROR", "E.*", "WARN", "[EW][RA]"]).unwrap();
+
+    for text in ["ERROR: timeout", "WARN: disk", "E", "INFO: ok", ""].iter() {
+        let mut matched = vec![false; set.len()];
+        set.scan_nfa(text.as_bytes(), &mut matched, &mut Budget::unlimited()).unwrap();
+
+        let expected = set.matches(text);
+        assert_eq!(expected, (0..set.len()).filter(|&i| matched[i]).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn matches_literal_patterns_with_automaton() {
+    let patterns = ["foo|foobar", "bar", "fo(o|x)", "foob"];
+    let set = RegexSet::new(&patterns).unwrap();
+    assert!(set.literals.is_some());
+
+    let exprs = patterns.iter().map(|pattern| pattern.parse::<Expr>().unwrap()).collect::<Vec<_>>();
+    let reference = RegexSet {
+        nfa: NFA::from_exprs(&exprs),
+        literals: None,
+        len: exprs.len()
+    };
+
+    for text in ["foobar", "foxy", "bar", "fo", ""].iter() {
+        let mut matched = vec![false; set.len()];
+        reference.scan_nfa(text.as_bytes(), &mut matched, &mut Budget::unlimited()).unwrap();
+
+        let expected = (0..set.len()).filter(|&i| matched[i]).collect::<Vec<_>>();
+        assert_eq!(expected, set.matches(text), "{:?}", text);
+        assert_eq!(!expected.is_empty(), set.is_match(text), "{:?}", text);
+    }
+}
+
+#[test]
+fn stops_when_limits_are_exceeded() {
+    let set = RegexSet::new(&["a+b", "a*c"]).unwrap();
+    assert_eq!(Ok(vec![0]), set.matches_limited("aaab", Limits::new().bytes(4)));
+    assert_eq!(Err(MatchError::BudgetExceeded), set.matches_limited("aaab", Limits::new().bytes(3)));
+    assert_eq!(Err(MatchError::BudgetExceeded), set.is_match_limited("aaab", Limits::new().steps(2)));
+    assert_eq!(Ok(true), set.is_match_limited("aaab", Limits::new().steps(4)));
+
+    let mut matched = vec![false; set.len()];
+    assert_eq!(Err(MatchError::BudgetExceeded), set.scan_nfa(b"aaab", &mut matched, &mut Budget::new(Limits::new().steps(3))));
+
+    let literals = RegexSet::new(&["ab", "abc"]).unwrap();
+    assert_eq!(Ok(vec![0, 1]), literals.matches_limited("abcd", Limits::new().bytes(3)));
+    assert_eq!(Err(MatchError::BudgetExceeded), literals.is_match_limited("abcd", Limits::new().bytes(2)));
+}
+
+#[test]
+fn compiles_within_limits() {
+    let limits = CompileLimits::new().states(10);
+    assert_eq!(Err(CompileError::TooManyStates(10)), RegexSet::with_limits(&["a+b", "c*d", "[ef]+gh"], limits));
+    assert_eq!(Err(CompileError::NestingTooDeep(250)),
+               RegexSet::with_limits(&["a", &format!("{}b{}", "(".repeat(300), ")".repeat(300))], CompileLimits::new()));
+    assert_eq!(Err(CompileError::TooManyStates(10)), RegexSet::with_limits(&["abcdef", "ghijklmnopq"], limits));
+
+    let set = RegexSet::with_limits(&["abcdef", "ghijkl"], limits).unwrap();
+    assert_eq!(vec![1], set.matches("ghijkl"));
+}
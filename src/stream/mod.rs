@@ -0,0 +1,178 @@
+use std::io::{self, Read, ErrorKind};
+
+use ::nfa::{NFA, Closure};
+use ::lazy::{self, LazyDFA};
+use ::limits::{Limits, Budget, MatchError};
+
+#[cfg(test)] mod spec;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+enum Current {
+    Lazy(usize), // id of a lazy DFA state
+    Stepped(Closure) // the lazy DFA cache thrashed, so the NFA is stepped directly
+}
+
+// matches a stream that is pushed a chunk at a time, either at its start
+// or anywhere in it. Match offsets are counted from the start of the stream
+pub struct StreamMatcher<'a> {
+    nfa: &'a NFA,
+    anchored: bool,
+    dfa: LazyDFA<'a>,
+    cache: lazy::Cache,
+    current: Current,
+    offset: usize,
+    shortest: Option<usize>,
+    longest: Option<usize>,
+    budget: Budget,
+    error: Option<MatchError> // why the stream stopped before its result was known
+}
+
+impl<'a> StreamMatcher<'a> {
+    pub fn new(nfa: &'a NFA) -> StreamMatcher<'a> {
+        Self::with_nfa(nfa, true)
+    }
+
+    // finds the first match to end, wherever it starts. nfa must be made
+    // with NFA::unanchored, and the stream is done once a match ends since
+    // no later input can end one sooner
+    pub fn unanchored(nfa: &'a NFA) -> StreamMatcher<'a> {
+        Self::with_nfa(nfa, false)
+    }
+
+    fn with_nfa(nfa: &'a NFA, anchored: bool) -> StreamMatcher<'a> {
+        let dfa = LazyDFA::new(nfa, lazy::DEFAULT_CACHE_SIZE);
+        let mut cache = lazy::Cache::new();
+
+        let current = match dfa.start_state(&mut cache) {
+            Ok(id) => Current::Lazy(id),
+            Err(_) => Current::Stepped(nfa.start_closure())
+        };
+
+        let mut matcher = StreamMatcher {
+            nfa,
+            anchored,
+            dfa,
+            cache,
+            current,
+            offset: 0,
+            shortest: None,
+            longest: None,
+            budget: Budget::unlimited(),
+            error: None
+        };
+        matcher.record();
+        matcher
+    }
+
+    // bounds the work on the whole stream. A step is a lazy DFA state
+    // entered, or an NFA state moved over a byte once the cache thrashes.
+    // Once limits are exceeded the stream takes no more input, and the
+    // matches found before stand
+    pub fn limits(mut self, limits: Limits) -> StreamMatcher<'a> {
+        self.budget = Budget::new(limits);
+        self
+    }
+
+    // consumes the next chunk of the stream, returning false once no further
+    // input can change the result
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        for &byte in chunk {
+            if self.is_done() {
+                return false;
+            }
+            if let Err(error) = self.charge() {
+                self.error = Some(error);
+                return false;
+            }
+
+            self.current = match self.current {
+                Current::Lazy(id) => match self.dfa.next_state(&mut self.cache, id, byte) {
+                    Ok(next) => Current::Lazy(next),
+                    Err(_) => {
+                        let closure = self.cache.closure(id).clone();
+                        Current::Stepped(self.nfa.step(&closure.states, byte))
+                    }
+                },
+                Current::Stepped(ref closure) => Current::Stepped(self.nfa.step(&closure.states, byte))
+            };
+
+            self.offset += 1;
+            self.record();
+        }
+
+        !self.is_done()
+    }
+
+    // consumes `reader` until it is exhausted or the result is known. Running
+    // out of limits is an error of kind Other
+    pub fn push_reader<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut buffer = [0; CHUNK_SIZE];
+
+        while !self.is_done() {
+            let len = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(len) => len,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            self.push(&buffer[..len]);
+        }
+
+        match self.error {
+            Some(error) => Err(io::Error::other(error)),
+            None => Ok(())
+        }
+    }
+
+    // number of bytes consumed so far
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn is_match(&self) -> bool {
+        self.shortest.is_some()
+    }
+
+    // end offsets of the shortest and longest matches seen so far
+    pub fn shortest_match(&self) -> Option<usize> {
+        self.shortest
+    }
+
+    pub fn longest_match(&self) -> Option<usize> {
+        self.longest
+    }
+
+    // no further input can change the matches found, or limits stopped the stream
+    pub fn is_done(&self) -> bool {
+        self.closure().states.is_empty() || (!self.anchored && self.is_match()) || self.error.is_some()
+    }
+
+    // the limit the stream ran out of, if any
+    pub fn error(&self) -> Option<MatchError> {
+        self.error
+    }
+
+    fn closure(&self) -> &Closure {
+        match self.current {
+            Current::Lazy(id) => self.cache.closure(id),
+            Current::Stepped(ref closure) => closure
+        }
+    }
+
+    // the budget for moving over the byte at the current offset
+    fn charge(&mut self) -> Result<(), MatchError> {
+        match self.current {
+            Current::Lazy(_) => self.budget.step()?,
+            Current::Stepped(ref closure) => self.budget.take(closure.states.len())?
+        }
+        self.budget.read(self.offset)
+    }
+
+    fn record(&mut self) {
+        if self.closure().is_match() {
+            self.shortest = self.shortest.or(Some(self.offset));
+            self.longest = Some(self.offset);
+        }
+    }
+}
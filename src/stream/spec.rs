@@ -0,0 +1,170 @@
+use std::io::{self, Read, ErrorKind};
+
+use ::expr::Expr;
+use ::nfa::NFA;
+use ::limits::{Limits, MatchError};
+use super::StreamMatcher;
+
+fn nfa(pattern: &str) -> NFA {
+    NFA::from_expr(&pattern.parse::<Expr>().unwrap())
+}
+
+// reads at most `chunk` bytes at a time
+struct Trickle<'a> {
+    data: &'a [u8],
+    chunk: usize
+}
+
+impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = *[self.chunk, buf.len(), self.data.len()].iter().min().unwrap();
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn matches_across_chunks() {
+    let nfa = nfa("abc+d");
+    let mut matcher = StreamMatcher::new(&nfa);
+
+    assert!(matcher.push(b"a"));
+    assert!(matcher.push(b"bc"));
+    assert!(!matcher.is_match());
+    assert!(!matcher.push(b"cdxyz"));
+
+    assert!(matcher.is_done());
+    assert_eq!(Some(5), matcher.shortest_match());
+    assert_eq!(Some(5), matcher.longest_match());
+}
+
+#[test]
+fn reports_global_offsets() {
+    let nfa = nfa("(ab)+");
+    let mut matcher = StreamMatcher::new(&nfa);
+
+    matcher.push(b"aba");
+    matcher.push(b"bab");
+    assert_eq!(6, matcher.offset());
+    assert_eq!(Some(2), matcher.shortest_match());
+    assert_eq!(Some(6), matcher.longest_match());
+
+    matcher.push(b"x");
+    assert!(matcher.is_done());
+    assert_eq!(Some(6), matcher.longest_match());
+}
+
+#[test]
+fn stops_consuming_once_done() {
+    let nfa = nfa("ab");
+    let mut matcher = StreamMatcher::new(&nfa);
+
+    assert!(!matcher.push(b"abab"));
+    assert_eq!(2, matcher.offset());
+    assert!(!matcher.push(b"ab"));
+    assert_eq!(2, matcher.offset());
+}
+
+#[test]
+fn matches_empty_stream() {
+    let nfa = nfa("a*");
+    let matcher = StreamMatcher::new(&nfa);
+    assert_eq!(Some(0), matcher.shortest_match());
+
+    let nfa = self::nfa("a");
+    let matcher = StreamMatcher::new(&nfa);
+    assert!(!matcher.is_match());
+    assert!(!matcher.is_done());
+}
+
+#[test]
+fn matches_char_split_across_chunks() {
+    let nfa = nfa("é+");
+    let mut matcher = StreamMatcher::new(&nfa);
+
+    matcher.push(b"\xC3");
+    assert!(!matcher.is_match());
+    matcher.push(b"\xA9\xC3");
+    matcher.push(b"\xA9!");
+    assert_eq!(Some(2), matcher.shortest_match());
+    assert_eq!(Some(4), matcher.longest_match());
+}
+
+#[test]
+fn consumes_reader() {
+    let nfa = nfa("[ab]*c");
+    let data = "ab".repeat(10000) + "cab";
+    let mut matcher = StreamMatcher::new(&nfa);
+
+    matcher.push_reader(Trickle { data: data.as_bytes(), chunk: 7 }).unwrap();
+    assert_eq!(Some(20001), matcher.longest_match());
+    assert_eq!(20001, matcher.offset());
+}
+
+#[test]
+fn consumes_whole_reader_without_match() {
+    let nfa = nfa("a*b");
+    let data = "a".repeat(50000);
+    let mut matcher = StreamMatcher::new(&nfa);
+
+    matcher.push_reader(Trickle { data: data.as_bytes(), chunk: 4096 }).unwrap();
+    assert_eq!(None, matcher.longest_match());
+    assert_eq!(50000, matcher.offset());
+}
+
+#[test]
+fn finds_match_starting_in_later_chunk() {
+    let nfa = nfa("abc+d").unanchored();
+    let mut matcher = StreamMatcher::unanchored(&nfa);
+
+    assert!(matcher.push(b"xxabx"));
+    assert!(matcher.push(b"yya"));
+    assert!(matcher.push(b"bcc"));
+    assert!(!matcher.is_match());
+    assert!(!matcher.push(b"dabcd"));
+
+    assert!(matcher.is_done());
+    assert_eq!(Some(12), matcher.shortest_match());
+    assert_eq!(12, matcher.offset());
+}
+
+#[test]
+fn finds_match_in_reader() {
+    let nfa = nfa("é+!").unanchored();
+    let data = "ab".repeat(10000) + "éé!" + &"ab".repeat(10);
+    let mut matcher = StreamMatcher::unanchored(&nfa);
+
+    matcher.push_reader(Trickle { data: data.as_bytes(), chunk: 7 }).unwrap();
+    assert_eq!(Some(20005), matcher.shortest_match());
+    assert_eq!(20005, matcher.offset());
+
+    let data = "ab".repeat(10000);
+    let mut matcher = StreamMatcher::unanchored(&nfa);
+    matcher.push_reader(Trickle { data: data.as_bytes(), chunk: 4096 }).unwrap();
+    assert!(!matcher.is_match());
+    assert_eq!(20000, matcher.offset());
+}
+
+#[test]
+fn stops_when_limits_are_exceeded() {
+    let nfa = nfa("a*b");
+    let mut matcher = StreamMatcher::new(&nfa).limits(Limits::new().bytes(4));
+
+    assert!(matcher.push(b"aa"));
+    assert!(!matcher.push(b"aaa"));
+    assert!(matcher.is_done());
+    assert_eq!(Some(MatchError::BudgetExceeded), matcher.error());
+    assert_eq!(4, matcher.offset());
+
+    let data = "a".repeat(1000);
+    let mut matcher = StreamMatcher::new(&nfa).limits(Limits::new().steps(100));
+    let error = matcher.push_reader(Trickle { data: data.as_bytes(), chunk: 7 }).unwrap_err();
+    assert_eq!(ErrorKind::Other, error.kind());
+    assert_eq!(100, matcher.offset());
+
+    let mut matcher = StreamMatcher::new(&nfa).limits(Limits::new().steps(100));
+    matcher.push_reader(Trickle { data: b"aab", chunk: 7 }).unwrap();
+    assert_eq!(Some(3), matcher.longest_match());
+    assert_eq!(None, matcher.error());
+}